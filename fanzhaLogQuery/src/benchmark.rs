@@ -0,0 +1,154 @@
+use crate::config::Config;
+use crate::progress::console_progress;
+use crate::search::run_query;
+use crate::status;
+use anyhow::{bail, Context, Result};
+use flate2::write::GzEncoder;
+use rayon::prelude::*;
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Day directory the synthetic files are placed in, and the `queryTime_day` used to find them.
+const BENCHMARK_DAY: &str = "20250101";
+/// Every n-th synthetic line carries a domain under [`BENCHMARK_DOMAIN`].
+const BENCHMARK_HIT_EVERY: usize = 10;
+const BENCHMARK_DOMAIN: &str = "bench.test";
+
+/// Size of the synthetic data set for `--benchmark`.
+#[derive(Debug, Clone, Copy)]
+pub struct BenchmarkOptions {
+    pub files: usize,
+    /// Uncompressed size of each generated file
+    pub file_bytes: u64,
+}
+
+/// Throughput of one benchmark run; only the search itself is timed, not the data generation.
+#[derive(Debug)]
+pub struct BenchmarkReport {
+    pub files: usize,
+    pub compressed_bytes: u64,
+    pub uncompressed_bytes: u64,
+    pub lines: usize,
+    pub matches: usize,
+    pub elapsed: Duration,
+}
+
+impl BenchmarkReport {
+    /// Uncompressed MB/s, the figure comparable across compression levels.
+    pub fn mb_per_sec(&self) -> f64 {
+        self.uncompressed_bytes as f64 / 1_000_000.0 / self.elapsed.as_secs_f64()
+    }
+
+    pub fn lines_per_sec(&self) -> f64 {
+        self.lines as f64 / self.elapsed.as_secs_f64()
+    }
+}
+
+/// Removes the synthetic data set however the run ends.
+struct TempDir(PathBuf);
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+/// Generates gzipped aggregated logs in a temp dir and searches them with the production
+/// `run_query` path in count-only mode. The worker settings (`workerPoolSize`, `aggregatedWorkerPoolSize`,
+/// `coreIds`, `executionBackend`, `autoScaleWorkers`, `ioChannelCapacity`) are taken from `tuning` when
+/// given, so the numbers reflect the server's config. Fails if the match count is not the expected one.
+pub fn run_benchmark(tuning: Option<&Config>, options: &BenchmarkOptions) -> Result<BenchmarkReport> {
+    let dir = TempDir(std::env::temp_dir().join(format!("fanzha_benchmark_{}", std::process::id())));
+    let day_dir = dir.0.join(BENCHMARK_DAY);
+    fs::create_dir_all(&day_dir).with_context(|| format!("failed to create {:?}", day_dir))?;
+
+    status!("基准测试: 正在生成 {} 个合成日志文件 (每个解压后约 {} 字节)...", options.files, options.file_bytes);
+    let generated = (0..options.files)
+        .into_par_iter()
+        .map(|i| generate_file(&day_dir.join(format!("bench_{:04}.log.gz", i)), options.file_bytes))
+        .collect::<Result<Vec<_>>>()?;
+    let compressed_bytes = generated.iter().map(|g| g.compressed_bytes).sum();
+    let uncompressed_bytes = generated.iter().map(|g| g.uncompressed_bytes).sum();
+    let expected_lines: usize = generated.iter().map(|g| g.lines).sum();
+    let expected_matches: usize = generated.iter().map(|g| g.matches).sum();
+
+    let mut config: Config = serde_json::from_value(serde_json::json!({
+        "logDirectory": dir.0.to_string_lossy(),
+        "queryDomain": [format!("*.{}", BENCHMARK_DOMAIN)],
+        "queryTime_day": [BENCHMARK_DAY],
+        "isQueryNativeLog": "no",
+        "fileExtensions": [".gz"],
+        "countOnly": true,
+    }))?;
+    if let Some(tuning) = tuning {
+        config.worker_pool_size = tuning.worker_pool_size;
+        config.aggregated_worker_pool_size = tuning.aggregated_worker_pool_size;
+        config.core_ids = tuning.core_ids.clone();
+        config.execution_backend = tuning.execution_backend;
+        config.auto_scale_workers = tuning.auto_scale_workers;
+        config.io_channel_capacity = tuning.io_channel_capacity;
+    }
+
+    let report = run_query(&config, (!status::quiet()).then(console_progress))?;
+    if report.interrupted {
+        bail!("benchmark interrupted");
+    }
+    let (lines, matches) = (report.total_lines(), report.total_matches());
+    if lines != expected_lines || matches != expected_matches {
+        bail!(
+            "benchmark self-test failed: expected {} lines / {} matches, got {} / {}",
+            expected_lines,
+            expected_matches,
+            lines,
+            matches
+        );
+    }
+
+    Ok(BenchmarkReport {
+        files: options.files,
+        compressed_bytes,
+        uncompressed_bytes,
+        lines,
+        matches,
+        elapsed: report.elapsed,
+    })
+}
+
+struct GeneratedFile {
+    compressed_bytes: u64,
+    uncompressed_bytes: u64,
+    lines: usize,
+    matches: usize,
+}
+
+/// Writes `ip|domain|type|answer` lines until `target_bytes` of uncompressed data are written.
+fn generate_file(path: &Path, target_bytes: u64) -> Result<GeneratedFile> {
+    let file = File::create(path).with_context(|| format!("failed to create {:?}", path))?;
+    let mut out = GzEncoder::new(BufWriter::new(file), flate2::Compression::default());
+    let mut line = Vec::with_capacity(128);
+    let (mut written, mut lines, mut matches) = (0u64, 0usize, 0usize);
+    while written < target_bytes {
+        line.clear();
+        let n = lines;
+        write!(line, "10.{}.{}.{}|", (n >> 16) & 255, (n >> 8) & 255, n & 255)?;
+        if n % BENCHMARK_HIT_EVERY == 0 {
+            write!(line, "hit{}.{}", n, BENCHMARK_DOMAIN)?;
+            matches += 1;
+        } else {
+            write!(line, "www{}.example{}.com", n % 1000, n % 97)?;
+        }
+        writeln!(line, "|A|93.184.216.{}", n % 256)?;
+        out.write_all(&line)?;
+        written += line.len() as u64;
+        lines += 1;
+    }
+    out.finish()?.flush()?;
+    Ok(GeneratedFile {
+        compressed_bytes: fs::metadata(path)?.len(),
+        uncompressed_bytes: written,
+        lines,
+        matches,
+    })
+}
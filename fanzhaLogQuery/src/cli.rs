@@ -0,0 +1,111 @@
+use clap::{CommandFactory, Parser};
+use std::path::Path;
+
+/// Build description printed by `--version`; every capability listed is compiled in unconditionally.
+const LONG_VERSION: &str = concat!(
+    env!("CARGO_PKG_VERSION"),
+    "\n",
+    "input decompression: gzip (flate2/zlib-ng), zstd, bzip2\n",
+    "remote inputs: http(s) (ureq/rustls)\n",
+    "log formats: delimited (optionally quoted), json\n",
+    "output formats: text, parquet (snappy)\n",
+    "output compression: gzip\n",
+    "output encryption: chacha20-poly1305, argon2id passphrase key\n",
+    "domain matching: exact, wildcard, regex (re:), negation (!), aho-corasick for large exact sets, label trie for large wildcard sets, idn (punycode), registrable domain (eTLD+1)\n",
+    "ip matching: exact, cidr, range, ipv4/ipv6, uint32-encoded ipv4, source port, geoip country (maxmind)\n",
+    "allocator: mimalloc",
+);
+
+#[derive(Debug, Parser)]
+#[command(
+    name = "fanzha_log_query",
+    about = "DNS 汇总日志 / 原始日志检索工具",
+    version,
+    long_version = LONG_VERSION
+)]
+pub struct Cli {
+    /// Path to the YAML config file, or `-` to filter stdin using ./config.yaml
+    #[arg(default_value = "config.yaml")]
+    pub config: String,
+
+    /// `-` reads log lines from stdin instead of walking logDirectory
+    pub input: Option<String>,
+
+    /// Search generated gzip files and report throughput; worker settings come from the config if it exists
+    #[arg(long)]
+    pub benchmark: bool,
+
+    /// Number of files generated for --benchmark
+    #[arg(long, default_value_t = 8, value_name = "N")]
+    pub bench_files: usize,
+
+    /// Uncompressed size of each --benchmark file, in MB
+    #[arg(long, default_value_t = 32, value_name = "MB")]
+    pub bench_file_mb: u64,
+
+    /// Print no progress or summaries, only errors and warnings; also `quiet: true` in the config
+    #[arg(short, long)]
+    pub quiet: bool,
+
+    /// After the search, keep watching the input directories and search new files as they appear, until Ctrl-C
+    #[arg(long)]
+    pub follow: bool,
+
+    /// Seconds between --follow checks for new files
+    #[arg(long, value_name = "SECS", value_parser = clap::value_parser!(u64).range(1..))]
+    pub follow_interval: Option<u64>,
+
+    /// Decrypt a result file written with outputEncryption (`-` for stdin) to stdout, then exit
+    #[arg(long, value_name = "FILE")]
+    pub decrypt: Option<String>,
+
+    /// Check one log line against the config's filters and print which fields and rules decided it, then exit
+    #[arg(long, value_name = "LINE", conflicts_with_all = ["benchmark", "follow", "decrypt"])]
+    pub explain: Option<String>,
+
+    /// Print per-file diagnostics to stderr, such as skipped empty files or the field delimiter autoDetectDelimiter chose
+    #[arg(long)]
+    pub debug: bool,
+
+    /// Print an example config.yaml listing every supported key with its type, default and description, then exit
+    #[arg(long, conflicts_with_all = ["benchmark", "follow", "decrypt", "explain"])]
+    pub print_config_schema: bool,
+
+    /// File whose first line is the --decrypt passphrase; defaults to the DNSLOG_OUTPUT_PASSPHRASE variable
+    #[arg(long, value_name = "FILE", requires = "decrypt")]
+    pub passphrase_file: Option<String>,
+}
+
+impl Cli {
+    /// Parses the command line, printing usage and exiting if the config path does not exist
+    /// (it is optional with `--benchmark`, `--decrypt` and `--print-config-schema`).
+    pub fn parse_args() -> Self {
+        let mut cli = Cli::parse();
+        if cli.config == "-" && cli.input.is_none() {
+            cli.config = "config.yaml".to_string();
+            cli.input = Some("-".to_string());
+        }
+        if let Some(input) = &cli.input {
+            if input != "-" {
+                eprintln!("Unsupported input {:?}: only \"-\" (stdin) is accepted\n", input);
+                let _ = Cli::command().print_help();
+                std::process::exit(2);
+            }
+        }
+        if cli.follow && cli.reads_stdin() {
+            eprintln!("--follow cannot be combined with stdin input\n");
+            let _ = Cli::command().print_help();
+            std::process::exit(2);
+        }
+        if !cli.benchmark && cli.decrypt.is_none() && !cli.print_config_schema && !Path::new(&cli.config).is_file() {
+            eprintln!("Config file not found: {}\n", cli.config);
+            let _ = Cli::command().print_help();
+            std::process::exit(2);
+        }
+        cli
+    }
+
+    pub fn reads_stdin(&self) -> bool {
+        self.input.as_deref() == Some("-")
+    }
+}
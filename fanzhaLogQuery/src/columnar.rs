@@ -0,0 +1,214 @@
+//! Parquet result files (`outputFormat: parquet`).
+//!
+//! Workers encode each match as a row of raw field values (see [`encode_row`]); the writer
+//! thread parses the values into the declared column types and hands each received chunk to
+//! the Parquet writer as one record batch. Row groups are written out as they fill up.
+
+use crate::output::{HashingWriter, RecordOptions};
+use crate::processor::{split_fields, unquote};
+use arrow_array::builder::{Float64Builder, Int64Builder, StringBuilder, TimestampMillisecondBuilder};
+use arrow_array::{ArrayRef, RecordBatch};
+use arrow_schema::{DataType, Field, Schema, SchemaRef, TimeUnit};
+use chrono::DateTime;
+use parquet::arrow::ArrowWriter;
+use parquet::basic::Compression;
+use parquet::file::properties::WriterProperties;
+use serde::Deserialize;
+use std::fs::File;
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+
+/// Columns every Parquet result file starts with, before the `parquetColumns`.
+pub const BUILTIN_COLUMNS: [&str; 3] = ["ip", "domain", "raw"];
+
+/// Rows per row group unless `parquetRowGroupRows` is set.
+pub const DEFAULT_ROW_GROUP_ROWS: usize = 100_000;
+
+/// Marks a missing value in an encoded row.
+const NULL_LEN: u32 = u32::MAX;
+
+/// Type of a `parquetColumns` entry. Values that do not parse as the type are written as null.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ColumnType {
+    String,
+    Int64,
+    Float64,
+    /// Epoch seconds (up to 10 digits), epoch milliseconds or RFC 3339, stored in milliseconds (UTC)
+    Timestamp,
+}
+
+/// One `parquetColumns` entry: the field at `index` of the log line, as column `name`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ParquetColumn {
+    pub name: String,
+    pub index: usize,
+    #[serde(rename = "type")]
+    pub kind: ColumnType,
+}
+
+/// Columns and row group size of the result files of a search.
+#[derive(Debug)]
+pub struct ParquetSchema {
+    kinds: Vec<ColumnType>,
+    row_group_rows: usize,
+    schema: SchemaRef,
+}
+
+impl ParquetSchema {
+    pub fn new(columns: &[ParquetColumn], row_group_rows: usize) -> Self {
+        let mut fields: Vec<Field> = BUILTIN_COLUMNS.iter().map(|name| Field::new(*name, DataType::Utf8, true)).collect();
+        fields.extend(columns.iter().map(|column| Field::new(column.name.clone(), data_type(column.kind), true)));
+        ParquetSchema {
+            kinds: columns.iter().map(|column| column.kind).collect(),
+            row_group_rows,
+            schema: Arc::new(Schema::new(fields)),
+        }
+    }
+}
+
+fn data_type(kind: ColumnType) -> DataType {
+    match kind {
+        ColumnType::String => DataType::Utf8,
+        ColumnType::Int64 => DataType::Int64,
+        ColumnType::Float64 => DataType::Float64,
+        ColumnType::Timestamp => DataType::Timestamp(TimeUnit::Millisecond, Some("UTC".into())),
+    }
+}
+
+/// Appends one row: the [`BUILTIN_COLUMNS`] values, then the fields of the log `line` at
+/// `options.parquet_fields`, split on `delimiter`, each as a little-endian `u32` length followed
+/// by the bytes. Missing values are written as [`NULL_LEN`] without bytes.
+pub(crate) fn encode_row(buf: &mut Vec<u8>, builtin: [Option<&[u8]>; 3], line: &[u8], delimiter: u8, options: &RecordOptions) {
+    for value in builtin {
+        push_value(buf, value);
+    }
+    let indices = &options.parquet_fields;
+    let max = indices.iter().copied().max().unwrap_or(0);
+    let fields: Vec<&[u8]> = split_fields(line, delimiter, options.quoted).take(max + 1).collect();
+    for &index in indices {
+        let field = fields.get(index).map(|&field| if options.quoted { unquote(field) } else { field });
+        push_value(buf, field);
+    }
+}
+
+fn push_value(buf: &mut Vec<u8>, value: Option<&[u8]>) {
+    match value {
+        Some(value) => {
+            buf.extend_from_slice(&(value.len() as u32).to_le_bytes());
+            buf.extend_from_slice(value);
+        }
+        None => buf.extend_from_slice(&NULL_LEN.to_le_bytes()),
+    }
+}
+
+/// Writes the rows encoded by [`encode_row`] to a Parquet file.
+pub struct ParquetWriter {
+    writer: ArrowWriter<HashingWriter<File>>,
+    schema: Arc<ParquetSchema>,
+}
+
+impl ParquetWriter {
+    pub fn create(path: &Path, schema: Arc<ParquetSchema>) -> io::Result<Self> {
+        let properties = WriterProperties::builder()
+            .set_compression(Compression::SNAPPY)
+            .set_max_row_group_size(schema.row_group_rows)
+            .build();
+        let file = HashingWriter::new(File::create(path)?, true);
+        let writer = ArrowWriter::try_new(file, Arc::clone(&schema.schema), Some(properties))
+            .map_err(io::Error::other)?;
+        Ok(ParquetWriter { writer, schema })
+    }
+
+    /// Adds the rows of a chunk as one record batch; a row group is written out each time
+    /// `parquetRowGroupRows` rows have been added.
+    pub fn write_rows(&mut self, chunk: &[u8]) -> io::Result<()> {
+        let batch = decode_rows(&self.schema, chunk)?;
+        self.writer.write(&batch).map_err(io::Error::other)
+    }
+
+    /// Writes the last row group and the footer; returns the size and the SHA-256 of the file.
+    pub fn finish(self) -> io::Result<(u64, String)> {
+        let file = self.writer.into_inner().map_err(io::Error::other)?;
+        let bytes = file.get_ref().metadata()?.len();
+        let (_, digest) = file.into_parts();
+        Ok((bytes, digest.expect("Parquet files are always hashed")))
+    }
+}
+
+enum ColumnBuilder {
+    String(StringBuilder),
+    Int64(Int64Builder),
+    Float64(Float64Builder),
+    Timestamp(TimestampMillisecondBuilder),
+}
+
+impl ColumnBuilder {
+    fn new(kind: ColumnType) -> Self {
+        match kind {
+            ColumnType::String => ColumnBuilder::String(StringBuilder::new()),
+            ColumnType::Int64 => ColumnBuilder::Int64(Int64Builder::new()),
+            ColumnType::Float64 => ColumnBuilder::Float64(Float64Builder::new()),
+            ColumnType::Timestamp => ColumnBuilder::Timestamp(TimestampMillisecondBuilder::new().with_timezone("UTC")),
+        }
+    }
+
+    fn append(&mut self, value: Option<&[u8]>) {
+        let text = value.map(String::from_utf8_lossy);
+        match self {
+            ColumnBuilder::String(builder) => builder.append_option(text),
+            ColumnBuilder::Int64(builder) => builder.append_option(text.and_then(|text| text.parse().ok())),
+            ColumnBuilder::Float64(builder) => {
+                builder.append_option(text.and_then(|text| text.parse().ok()).filter(|value: &f64| value.is_finite()))
+            }
+            ColumnBuilder::Timestamp(builder) => builder.append_option(value.and_then(timestamp_millis)),
+        }
+    }
+
+    fn finish(&mut self) -> ArrayRef {
+        match self {
+            ColumnBuilder::String(builder) => Arc::new(builder.finish()),
+            ColumnBuilder::Int64(builder) => Arc::new(builder.finish()),
+            ColumnBuilder::Float64(builder) => Arc::new(builder.finish()),
+            ColumnBuilder::Timestamp(builder) => Arc::new(builder.finish()),
+        }
+    }
+}
+
+fn decode_rows(schema: &ParquetSchema, mut chunk: &[u8]) -> io::Result<RecordBatch> {
+    let mut builders: Vec<ColumnBuilder> = BUILTIN_COLUMNS.iter().map(|_| ColumnBuilder::new(ColumnType::String)).collect();
+    builders.extend(schema.kinds.iter().map(|&kind| ColumnBuilder::new(kind)));
+    while !chunk.is_empty() {
+        for builder in &mut builders {
+            let value = take_value(&mut chunk).ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "truncated Parquet row"))?;
+            builder.append(value);
+        }
+    }
+    let columns = builders.iter_mut().map(ColumnBuilder::finish).collect();
+    RecordBatch::try_new(Arc::clone(&schema.schema), columns).map_err(io::Error::other)
+}
+
+/// The next value of an encoded row; the outer `None` when the chunk ends early.
+fn take_value<'a>(chunk: &mut &'a [u8]) -> Option<Option<&'a [u8]>> {
+    let (len, rest) = chunk.split_first_chunk::<4>()?;
+    let len = u32::from_le_bytes(*len);
+    if len == NULL_LEN {
+        *chunk = rest;
+        return Some(None);
+    }
+    let (value, rest) = rest.split_at_checked(len as usize)?;
+    *chunk = rest;
+    Some(Some(value))
+}
+
+/// Epoch seconds (up to 10 digits) or milliseconds (more digits), or an RFC 3339 time.
+fn timestamp_millis(value: &[u8]) -> Option<i64> {
+    if !value.is_empty() && value.len() <= 19 && value.iter().all(u8::is_ascii_digit) {
+        let number: i64 = std::str::from_utf8(value).ok()?.parse().ok()?;
+        return if value.len() <= 10 { number.checked_mul(1000) } else { Some(number) };
+    }
+    let text = std::str::from_utf8(value).ok()?;
+    Some(DateTime::parse_from_rfc3339(text).ok()?.timestamp_millis())
+}
@@ -1,16 +1,72 @@
-use serde::{Deserialize, Deserializer};
+use crate::columnar::{ParquetColumn, ParquetSchema, BUILTIN_COLUMNS, DEFAULT_ROW_GROUP_ROWS};
+use crate::dedupe::DedupeScope;
+use crate::encryption::{read_passphrase, OutputKey};
+use crate::matcher::IpFieldEncoding;
+use crate::output::{
+    ExistingOutput, LineTerminator, OutputCompression, OutputEncoding, OutputFormat, RecordOptions, DEFAULT_WRITER_BUFFER_BYTES,
+};
+use crate::remote::is_remote;
+use crate::processor::{
+    FieldCondition, FieldFilter, FieldLayout, FilterLogic, LogFormat, MatchOptions, Sample, SampleKey, AGGREGATED_LOG_DOMAIN_INDEX, AGGREGATED_LOG_IP_INDEX,
+    DEFAULT_FIELD_DELIMITER, NATIVE_LOG_DOMAIN_INDEX, NATIVE_LOG_IP_INDEX, NATIVE_LOG_RESOLVED_IP_INDEX,
+};
+use crate::search::{ExecutionBackend, FileOrder};
+use crate::sort::{SortOptions, DEFAULT_SORT_BUFFER_BYTES};
+use serde::{Deserialize, Deserializer, Serialize};
+use std::collections::{BTreeMap, HashSet};
 use std::fs;
-use anyhow::Result;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, Local, NaiveDateTime};
+use regex::Regex;
 
 #[derive(Debug, Deserialize, Clone)]
 pub struct Config {
     #[serde(rename = "logDirectory")]
     pub log_directory: String,
 
+    #[serde(rename = "followSymlinks")]
+    pub follow_symlinks: Option<bool>,
+
+    #[serde(rename = "maxDepth")]
+    pub max_depth: Option<usize>,
+
+    #[serde(rename = "pruneDirectories", default, deserialize_with = "string_or_seq_string")]
+    pub prune_directories: Vec<String>,
+
+    #[serde(rename = "remoteFiles", default, deserialize_with = "string_or_seq_string")]
+    pub remote_files: Vec<String>,
+
+    #[serde(rename = "remoteUser")]
+    pub remote_user: Option<String>,
+
+    #[serde(rename = "remotePassword")]
+    pub remote_password: Option<String>,
+
     #[serde(rename = "workerPoolSize")]
     pub worker_pool_size: Option<usize>,
 
-    #[serde(rename = "coreIds")]
+    #[serde(rename = "aggregatedWorkerPoolSize")]
+    pub aggregated_worker_pool_size: Option<usize>,
+
+    #[serde(rename = "nativeWorkerPoolSize")]
+    pub native_worker_pool_size: Option<usize>,
+
+    #[serde(rename = "autoScaleWorkers")]
+    pub auto_scale_workers: Option<bool>,
+
+    #[serde(rename = "executionBackend")]
+    pub execution_backend: Option<ExecutionBackend>,
+
+    #[serde(rename = "splitGzipMinBytes")]
+    pub split_gzip_min_bytes: Option<u64>,
+
+    #[serde(rename = "mmapInputs")]
+    pub mmap_inputs: Option<bool>,
+
+    #[serde(rename = "coreIds", default, deserialize_with = "core_ids_or_spec")]
     pub core_ids: Option<Vec<usize>>,
 
     #[serde(rename = "queryDomain", default, deserialize_with = "string_or_seq_string")]
@@ -19,31 +75,1117 @@ pub struct Config {
     #[serde(rename = "sourceIP", default, deserialize_with = "string_or_seq_string")]
     pub source_ip: Vec<String>,
 
+    #[serde(rename = "stripTrailingDot")]
+    pub strip_trailing_dot: Option<bool>,
+
+    #[serde(rename = "idnNormalize")]
+    pub idn_normalize: Option<bool>,
+
+    #[serde(rename = "domainAnyField")]
+    pub domain_any_field: Option<bool>,
+
+    #[serde(rename = "minDomainLabels")]
+    pub min_domain_labels: Option<usize>,
+
+    #[serde(rename = "maxDomainLabels")]
+    pub max_domain_labels: Option<usize>,
+
+    #[serde(rename = "matchRegistrableDomain")]
+    pub match_registrable_domain: Option<bool>,
+
+    #[serde(rename = "publicSuffixList")]
+    pub public_suffix_list: Option<String>,
+
+    #[serde(rename = "queryDomainFile")]
+    pub query_domain_file: Option<String>,
+
+    #[serde(rename = "sourceIPFile")]
+    pub source_ip_file: Option<String>,
+
+    #[serde(rename = "excludeSourceIP", default, deserialize_with = "string_or_seq_string")]
+    pub exclude_source_ip: Vec<String>,
+
+    #[serde(rename = "geoDatabase")]
+    pub geo_database: Option<String>,
+
+    #[serde(rename = "sourceCountry", default, deserialize_with = "string_or_seq_string")]
+    pub source_country: Vec<String>,
+
+    #[serde(rename = "excludeCountry", default, deserialize_with = "string_or_seq_string")]
+    pub exclude_country: Vec<String>,
+
+    #[serde(rename = "resolvedIP", default, deserialize_with = "string_or_seq_string")]
+    pub resolved_ip: Vec<String>,
+
+    #[serde(rename = "ipFieldEncoding")]
+    pub ip_field_encoding: Option<IpFieldEncoding>,
+
+    #[serde(rename = "stripSourcePort")]
+    pub strip_source_port: Option<bool>,
+
+    #[serde(rename = "sourcePort", default, deserialize_with = "port_or_seq_port")]
+    pub source_port: Vec<u16>,
+
     #[serde(rename = "queryTime_hour")]
     pub query_time_hour: Option<Vec<String>>,
 
     #[serde(rename = "queryTime_day")]
     pub query_time_day: Option<Vec<String>>,
 
+    #[serde(rename = "queryTimeStart")]
+    pub query_time_start: Option<String>,
+
+    #[serde(rename = "queryTimeEnd")]
+    pub query_time_end: Option<String>,
+
     #[serde(rename = "isQueryNativeLog")]
     pub is_query_native_log: String,
 
     #[serde(rename = "nativeLogLoc")]
     pub native_log_loc: Option<String>,
 
+    #[serde(rename = "nativeFileTimestampRegex")]
+    pub native_file_timestamp_regex: Option<String>,
+
     #[serde(rename = "nativeLogResultLoc")]
     pub native_log_result_loc: Option<String>,
 
     #[serde(rename = "aggregatedLogResultLoc")]
     pub aggregated_log_result_loc: Option<String>,
+
+    #[serde(rename = "aggregatedIpIndex")]
+    pub aggregated_ip_index: Option<usize>,
+
+    #[serde(rename = "aggregatedDomainIndex")]
+    pub aggregated_domain_index: Option<usize>,
+
+    #[serde(rename = "nativeIpIndex")]
+    pub native_ip_index: Option<usize>,
+
+    #[serde(rename = "resolvedIpIndex")]
+    pub resolved_ip_index: Option<usize>,
+
+    #[serde(rename = "aggregatedResolvedIpIndex")]
+    pub aggregated_resolved_ip_index: Option<usize>,
+
+    #[serde(rename = "nativeDomainIndex")]
+    pub native_domain_index: Option<usize>,
+
+    #[serde(rename = "fieldDelimiter")]
+    pub field_delimiter: Option<String>,
+
+    #[serde(rename = "autoDetectDelimiter")]
+    pub auto_detect_delimiter: Option<bool>,
+
+    #[serde(rename = "logFormat")]
+    pub log_format: Option<LogFormat>,
+
+    #[serde(rename = "ipField")]
+    pub ip_field: Option<String>,
+
+    #[serde(rename = "domainField")]
+    pub domain_field: Option<String>,
+
+    #[serde(rename = "resolvedIpField")]
+    pub resolved_ip_field: Option<String>,
+
+    #[serde(rename = "quotedFields")]
+    pub quoted_fields: Option<bool>,
+
+    #[serde(rename = "fieldFilters", default)]
+    pub field_filters: BTreeMap<usize, RuleList>,
+
+    #[serde(rename = "aggregatedFieldFilters")]
+    pub aggregated_field_filters: Option<BTreeMap<usize, RuleList>>,
+
+    #[serde(rename = "nativeFieldFilters")]
+    pub native_field_filters: Option<BTreeMap<usize, RuleList>>,
+
+    #[serde(rename = "numericFieldFilters", default)]
+    pub numeric_field_filters: Vec<NumericFieldFilter>,
+
+    #[serde(rename = "aggregatedNumericFieldFilters")]
+    pub aggregated_numeric_field_filters: Option<Vec<NumericFieldFilter>>,
+
+    #[serde(rename = "nativeNumericFieldFilters")]
+    pub native_numeric_field_filters: Option<Vec<NumericFieldFilter>>,
+
+    #[serde(rename = "expectedFieldCount")]
+    pub expected_field_count: Option<usize>,
+
+    #[serde(rename = "aggregatedExpectedFieldCount")]
+    pub aggregated_expected_field_count: Option<usize>,
+
+    #[serde(rename = "nativeExpectedFieldCount")]
+    pub native_expected_field_count: Option<usize>,
+
+    #[serde(rename = "fileExtensions", default, deserialize_with = "string_or_seq_string")]
+    pub file_extensions: Vec<String>,
+
+    #[serde(rename = "excludeFileRegex")]
+    pub exclude_file_regex: Option<String>,
+
+    #[serde(rename = "fileOrder")]
+    pub file_order: Option<FileOrder>,
+
+    #[serde(rename = "strictInputs")]
+    pub strict_inputs: Option<bool>,
+
+    #[serde(rename = "countOnly")]
+    pub count_only: Option<bool>,
+
+    #[serde(rename = "outputFormat")]
+    pub output_format: Option<OutputFormat>,
+
+    #[serde(rename = "parquetColumns", default)]
+    pub parquet_columns: Vec<ParquetColumn>,
+
+    #[serde(rename = "parquetRowGroupRows")]
+    pub parquet_row_group_rows: Option<usize>,
+
+    #[serde(rename = "lineTerminator")]
+    pub line_terminator: Option<LineTerminator>,
+
+    #[serde(rename = "outputFields", default)]
+    pub output_fields: Vec<usize>,
+
+    #[serde(rename = "prependSourceFile")]
+    pub prepend_source_file: Option<bool>,
+
+    #[serde(rename = "prependSourceTimestamp")]
+    pub prepend_source_timestamp: Option<bool>,
+
+    #[serde(rename = "normalizeTimestampField")]
+    pub normalize_timestamp_field: Option<usize>,
+
+    #[serde(rename = "normalizeDomainCase")]
+    pub normalize_domain_case: Option<bool>,
+
+    #[serde(rename = "outputCompression")]
+    pub output_compression: Option<OutputCompression>,
+
+    #[serde(rename = "outputEncryption")]
+    pub output_encryption: Option<bool>,
+
+    #[serde(rename = "outputPassphraseFile")]
+    pub output_passphrase_file: Option<String>,
+
+    #[serde(rename = "outputToStdout")]
+    pub output_to_stdout: Option<bool>,
+
+    #[serde(rename = "filterLogic")]
+    pub filter_logic: Option<FilterLogic>,
+
+    #[serde(rename = "samplePercent")]
+    pub sample_percent: Option<f64>,
+
+    #[serde(rename = "sampleKey")]
+    pub sample_key: Option<SampleKey>,
+
+    #[serde(rename = "outputMaxBytes")]
+    pub output_max_bytes: Option<u64>,
+
+    #[serde(rename = "outputAppend")]
+    pub output_append: Option<bool>,
+
+    #[serde(rename = "onExistingOutput")]
+    pub on_existing_output: Option<ExistingOutput>,
+
+    #[serde(rename = "ruleMatchCounts")]
+    pub rule_match_counts: Option<bool>,
+
+    #[serde(rename = "sortByTimestampField")]
+    pub sort_by_timestamp_field: Option<usize>,
+
+    #[serde(rename = "sortBufferBytes")]
+    pub sort_buffer_bytes: Option<usize>,
+
+    #[serde(rename = "maxLineBytes")]
+    pub max_line_bytes: Option<usize>,
+
+    #[serde(rename = "confirmLargeRuns")]
+    pub confirm_large_runs: Option<bool>,
+
+    #[serde(rename = "confirmThresholdBytes")]
+    pub confirm_threshold_bytes: Option<u64>,
+
+    #[serde(rename = "splitByDomainRule")]
+    pub split_by_domain_rule: Option<bool>,
+
+    #[serde(rename = "maxMatches")]
+    pub max_matches: Option<usize>,
+
+    #[serde(rename = "perFileReport")]
+    pub per_file_report: Option<bool>,
+
+    #[serde(rename = "dedupe")]
+    pub dedupe: Option<bool>,
+
+    #[serde(rename = "dedupeScope")]
+    pub dedupe_scope: Option<DedupeScope>,
+
+    #[serde(rename = "quiet")]
+    pub quiet: Option<bool>,
+
+    #[serde(rename = "reportPeakMemory")]
+    pub report_peak_memory: Option<bool>,
+
+    #[serde(rename = "ioChannelCapacity")]
+    pub io_channel_capacity: Option<usize>,
+
+    #[serde(rename = "writeChannelCapacity")]
+    pub write_channel_capacity: Option<usize>,
+
+    #[serde(rename = "workerFlushBytes")]
+    pub worker_flush_bytes: Option<usize>,
+
+    #[serde(rename = "workerFlushIntervalMs")]
+    pub worker_flush_interval_ms: Option<u64>,
+
+    #[serde(rename = "writerBufferBytes")]
+    pub writer_buffer_bytes: Option<usize>,
+
+    #[serde(rename = "profiles", default)]
+    pub profiles: Vec<Profile>,
+
+    /// Name of the profile this config was derived for by [`Config::profile_configs`]
+    #[serde(skip)]
+    pub profile_name: Option<String>,
+
+    /// Start time of the run put into result file names by `onExistingOutput: timestamp`
+    #[serde(skip)]
+    pub run_stamp: Option<String>,
+}
+
+/// Files read ahead of the workers (each held fully in memory).
+pub const DEFAULT_IO_CHANNEL_CAPACITY: usize = 4;
+/// Match buffers queued for the writer thread.
+pub const DEFAULT_WRITE_CHANNEL_CAPACITY: usize = 1024;
+/// Matches a worker collects before handing them to the writer thread (128 KB).
+pub const DEFAULT_WORKER_FLUSH_BYTES: usize = 128 * 1024;
+/// Longest a match waits in a worker or writer buffer before it is passed on (ms).
+pub const DEFAULT_WORKER_FLUSH_INTERVAL_MS: u64 = 500;
+/// Smallest accepted `workerFlushBytes` and `writerBufferBytes`.
+const MIN_BUFFER_BYTES: usize = 4 * 1024;
+/// Longest line scanned before it is skipped as malformed (1 MB).
+pub const DEFAULT_MAX_LINE_BYTES: usize = 1024 * 1024;
+/// Compressed input size above which `confirmLargeRuns` asks before starting (100 GB).
+pub const DEFAULT_CONFIRM_THRESHOLD_BYTES: u64 = 100_000_000_000;
+/// Largest core id accepted in `coreIds`, so a typo cannot expand to a huge list.
+const MAX_CORE_ID: usize = 65535;
+
+/// A single value or a list of values, as accepted by the rule list keys.
+#[derive(Debug, Deserialize, Clone, Serialize)]
+#[serde(transparent)]
+pub struct RuleList(#[serde(deserialize_with = "string_or_seq_string")] pub Vec<String>);
+
+/// One `numericFieldFilters` entry: the field at `index`, read as a number, must lie within
+/// `min..=max`. Either bound may be left out.
+#[derive(Debug, Deserialize, Clone, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct NumericFieldFilter {
+    pub index: usize,
+    pub min: Option<f64>,
+    pub max: Option<f64>,
+}
+
+/// One query of a `profiles` run. Set keys replace the top-level key of the same name (a
+/// profile `queryDomain` also drops `queryDomainFile`, and `sourceIP` drops `sourceIPFile`);
+/// everything else is shared with the top level.
+#[derive(Debug, Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct Profile {
+    pub name: String,
+
+    #[serde(rename = "queryDomain")]
+    pub query_domain: Option<RuleList>,
+
+    #[serde(rename = "sourceIP")]
+    pub source_ip: Option<RuleList>,
+
+    #[serde(rename = "excludeSourceIP")]
+    pub exclude_source_ip: Option<RuleList>,
+
+    #[serde(rename = "resolvedIP")]
+    pub resolved_ip: Option<RuleList>,
+
+    #[serde(rename = "filterLogic")]
+    pub filter_logic: Option<FilterLogic>,
+
+    #[serde(rename = "queryTime_day")]
+    pub query_time_day: Option<Vec<String>>,
+
+    #[serde(rename = "queryTime_hour")]
+    pub query_time_hour: Option<Vec<String>>,
+
+    #[serde(rename = "aggregatedLogResultLoc")]
+    pub aggregated_log_result_loc: Option<String>,
+
+    #[serde(rename = "nativeLogResultLoc")]
+    pub native_log_result_loc: Option<String>,
+}
+
+/// Timestamp format embedded in native log file names, e.g. `20251209151802`.
+pub const NATIVE_TIMESTAMP_FORMAT: &str = "%Y%m%d%H%M%S";
+
+/// Inclusive time window for native log files; either bound may be open.
+#[derive(Debug, Clone)]
+pub struct TimeRange {
+    pub start: Option<NaiveDateTime>,
+    pub end: Option<NaiveDateTime>,
+}
+
+/// Parses a file name timestamp. Shorter all-digit stamps such as `20251209` or `2025120915`
+/// are padded with zeros, i.e. taken as the start of that day or hour.
+pub fn parse_native_timestamp(ts: &str) -> Option<NaiveDateTime> {
+    const FULL_LEN: usize = 14;
+    if ts.len() < FULL_LEN && ts.len() >= 8 && ts.bytes().all(|b| b.is_ascii_digit()) {
+        let padded = format!("{:0<width$}", ts, width = FULL_LEN);
+        return NaiveDateTime::parse_from_str(&padded, NATIVE_TIMESTAMP_FORMAT).ok();
+    }
+    NaiveDateTime::parse_from_str(ts, NATIVE_TIMESTAMP_FORMAT).ok()
+}
+
+/// Expands a `coreIds` string of ids and inclusive ranges: `"8-23,40-55"` or `"0, 2, 4"`.
+pub fn parse_core_ids(spec: &str) -> Result<Vec<usize>> {
+    let mut ids = Vec::new();
+    for part in split_env_list(spec) {
+        let (start, end) = part.split_once('-').unwrap_or((&part, &part));
+        let parse = |id: &str| -> Result<usize> {
+            let id: usize = id.trim().parse().with_context(|| format!("invalid core id {:?} in coreIds {:?}", id.trim(), spec))?;
+            if id > MAX_CORE_ID {
+                bail!("core id {} in coreIds is above {}", id, MAX_CORE_ID);
+            }
+            Ok(id)
+        };
+        let (start, end) = (parse(start)?, parse(end)?);
+        if start > end {
+            bail!("core range {:?} in coreIds runs backwards", part);
+        }
+        ids.extend(start..=end);
+    }
+    Ok(ids)
+}
+
+impl TimeRange {
+    pub fn contains(&self, ts: &NaiveDateTime) -> bool {
+        self.start.is_none_or(|start| *ts >= start) && self.end.is_none_or(|end| *ts <= end)
+    }
 }
 
 impl Config {
     pub fn load(path: &str) -> Result<Self> {
-        let content = fs::read_to_string(path)?;
-        let config: Config = serde_yaml::from_str(&content)?;
+        let content = fs::read_to_string(path).with_context(|| {
+            format!("failed to read config file {:?}; pass the path of the config as the first argument", path)
+        })?;
+        // Only comments and blank lines would otherwise fail as an obscure type error
+        if content.lines().map(str::trim).all(|line| line.is_empty() || line.starts_with('#')) {
+            bail!("config file {:?} is empty", path);
+        }
+        let mut config: Config =
+            serde_yaml::from_str(&content).with_context(|| format!("failed to parse config file {:?}", path))?;
+        config.apply_env_overrides()?;
+        if let Some(file) = &config.query_domain_file {
+            config.query_domain.extend(read_rule_file(file)?);
+        }
+        if let Some(file) = &config.source_ip_file {
+            config.source_ip.extend(read_rule_file(file)?);
+        }
+        config.validate()?;
         Ok(config)
     }
+
+    /// Overrides file values with `DNSLOG_*` environment variables, e.g. `DNSLOG_WORKER_POOL_SIZE=16`
+    /// or `DNSLOG_QUERY_TIME_DAY=20250626,20250627`; list values are comma-separated. A variable
+    /// that is set but cannot be parsed is an error.
+    fn apply_env_overrides(&mut self) -> Result<()> {
+        if let Some(value) = env_var("LOG_DIRECTORY")? {
+            self.log_directory = value;
+        }
+        if let Some(value) = env_var("IS_QUERY_NATIVE_LOG")? {
+            self.is_query_native_log = value;
+        }
+        if let Some(value) = env_var("REMOTE_PASSWORD")? {
+            self.remote_password = Some(value);
+        }
+        if let Some(value) = env_var("NATIVE_LOG_LOC")? {
+            self.native_log_loc = Some(value);
+        }
+        if let Some(value) = env_var("AGGREGATED_LOG_RESULT_LOC")? {
+            self.aggregated_log_result_loc = Some(value);
+        }
+        if let Some(value) = env_var("NATIVE_LOG_RESULT_LOC")? {
+            self.native_log_result_loc = Some(value);
+        }
+        if let Some(value) = env_var("QUERY_DOMAIN")? {
+            self.query_domain = split_env_list(&value);
+        }
+        if let Some(value) = env_var("SOURCE_IP")? {
+            self.source_ip = split_env_list(&value);
+        }
+        if let Some(value) = env_var("QUERY_TIME_DAY")? {
+            self.query_time_day = Some(split_env_list(&value));
+        }
+        if let Some(value) = env_var("QUERY_TIME_HOUR")? {
+            self.query_time_hour = Some(split_env_list(&value));
+        }
+        if let Some(value) = env_var("QUERY_TIME_START")? {
+            self.query_time_start = Some(value);
+        }
+        if let Some(value) = env_var("QUERY_TIME_END")? {
+            self.query_time_end = Some(value);
+        }
+        if let Some(value) = env_parse("WORKER_POOL_SIZE")? {
+            self.worker_pool_size = Some(value);
+        }
+        if let Some(value) = env_parse("AGGREGATED_WORKER_POOL_SIZE")? {
+            self.aggregated_worker_pool_size = Some(value);
+        }
+        if let Some(value) = env_parse("NATIVE_WORKER_POOL_SIZE")? {
+            self.native_worker_pool_size = Some(value);
+        }
+        if let Some(value) = env_var("CORE_IDS")? {
+            self.core_ids = Some(parse_core_ids(&value).with_context(|| format!("invalid {}CORE_IDS {:?}", ENV_PREFIX, value))?);
+        }
+        if let Some(value) = env_parse("MAX_MATCHES")? {
+            self.max_matches = Some(value);
+        }
+        Ok(())
+    }
+
+    fn validate(&self) -> Result<()> {
+        if let Some(delimiter) = &self.field_delimiter {
+            if delimiter.len() != 1 {
+                bail!(
+                    "fieldDelimiter must be exactly one byte, got {:?} ({} bytes)",
+                    delimiter,
+                    delimiter.len()
+                );
+            }
+        }
+        if self.log_format.unwrap_or_default() == LogFormat::Json {
+            if self.ip_field.is_none() || self.domain_field.is_none() {
+                bail!("logFormat json needs ipField and domainField (the JSON keys of the source IP and domain)");
+            }
+            let by_index = [
+                ("fieldFilters", !self.field_filters.is_empty()),
+                ("aggregatedFieldFilters", self.aggregated_field_filters.is_some()),
+                ("nativeFieldFilters", self.native_field_filters.is_some()),
+                ("numericFieldFilters", !self.numeric_field_filters.is_empty()),
+                ("aggregatedNumericFieldFilters", self.aggregated_numeric_field_filters.is_some()),
+                ("nativeNumericFieldFilters", self.native_numeric_field_filters.is_some()),
+                ("expectedFieldCount", self.expected_field_count.is_some()),
+                ("aggregatedExpectedFieldCount", self.aggregated_expected_field_count.is_some()),
+                ("nativeExpectedFieldCount", self.native_expected_field_count.is_some()),
+                ("outputFields", !self.output_fields.is_empty()),
+                ("normalizeTimestampField", self.normalize_timestamp_field.is_some()),
+                ("sortByTimestampField", self.sort_by_timestamp_field.is_some()),
+                ("parquetColumns", !self.parquet_columns.is_empty()),
+                ("autoDetectDelimiter", self.auto_detect_delimiter.unwrap_or(false)),
+            ];
+            if let Some((key, _)) = by_index.iter().find(|(_, set)| *set) {
+                bail!("{} addresses delimited fields by index and cannot be combined with logFormat json", key);
+            }
+        }
+        if self.public_suffix_list.is_some() && !self.match_registrable_domain.unwrap_or(false) {
+            bail!("publicSuffixList is set but matchRegistrableDomain is not");
+        }
+        let country_filter = !self.source_country.is_empty() || !self.exclude_country.is_empty();
+        if country_filter && self.geo_database.is_none() {
+            bail!("sourceCountry / excludeCountry need geoDatabase (the path of a MaxMind .mmdb file)");
+        }
+        if !country_filter && self.geo_database.is_some() {
+            bail!("geoDatabase is set but neither sourceCountry nor excludeCountry is");
+        }
+        if let Some(url) = self.remote_files.iter().find(|url| !is_remote(Path::new(url))) {
+            bail!("remoteFiles entry {:?} is not an http:// or https:// URL", url);
+        }
+        if self.remote_password.is_some() && self.remote_user.is_none() {
+            bail!("remotePassword is set but remoteUser is not");
+        }
+        self.query_time_range()?;
+        self.native_file_timestamp_regex()?;
+        self.exclude_file_regex()?;
+        self.prune_patterns()?;
+        if self.split_by_domain_rule.unwrap_or(false) {
+            if !self.query_domain.iter().any(|d| !d.trim().is_empty() && !d.starts_with('!')) {
+                bail!("splitByDomainRule needs at least one queryDomain rule that is not a negation");
+            }
+            if self.writes_results_to_stdout() {
+                bail!("splitByDomainRule cannot be combined with results written to stdout");
+            }
+        }
+        if self.prepend_source_timestamp.unwrap_or(false) && !self.prepend_source_file.unwrap_or(false) {
+            bail!("prependSourceTimestamp requires prependSourceFile");
+        }
+        if self.sort_by_timestamp_field.is_some() {
+            if self.output_format.unwrap_or_default() != OutputFormat::Raw {
+                bail!("sortByTimestampField only works with outputFormat raw");
+            }
+            if self.writes_results_to_stdout() {
+                bail!("sortByTimestampField cannot be combined with results written to stdout");
+            }
+            if self.output_max_bytes.is_some() {
+                bail!("sortByTimestampField cannot be combined with outputMaxBytes");
+            }
+            if self.output_encryption.unwrap_or(false) {
+                bail!("sortByTimestampField cannot be combined with outputEncryption");
+            }
+            // Result lines would not share a delimiter to find the field by
+            if self.auto_detect_delimiter.unwrap_or(false) {
+                bail!("sortByTimestampField cannot be combined with autoDetectDelimiter");
+            }
+        }
+        if self.output_format.unwrap_or_default() == OutputFormat::Parquet {
+            self.validate_parquet()?;
+        } else if !self.parquet_columns.is_empty() || self.parquet_row_group_rows.is_some() {
+            bail!("parquetColumns and parquetRowGroupRows only apply to outputFormat parquet");
+        }
+        if self.on_existing_output.unwrap_or_default() != ExistingOutput::Overwrite && self.output_append.unwrap_or(false) {
+            bail!("onExistingOutput only applies when results are overwritten and cannot be combined with outputAppend");
+        }
+        if self.output_encryption.unwrap_or(false) {
+            read_passphrase(self.output_passphrase_file.as_deref()).context("outputEncryption needs a passphrase")?;
+        }
+        if self.max_line_bytes == Some(0) {
+            bail!("maxLineBytes must be greater than 0");
+        }
+        if self.max_domain_labels == Some(0) {
+            bail!("maxDomainLabels must be greater than 0");
+        }
+        if let (Some(min), Some(max)) = (self.min_domain_labels, self.max_domain_labels) {
+            if min > max {
+                bail!("minDomainLabels ({}) is greater than maxDomainLabels ({})", min, max);
+            }
+        }
+        if self.parquet_row_group_rows == Some(0) {
+            bail!("parquetRowGroupRows must be greater than 0");
+        }
+        if self.sort_buffer_bytes == Some(0) {
+            bail!("sortBufferBytes must be greater than 0");
+        }
+        if self.output_max_bytes == Some(0) {
+            bail!("outputMaxBytes must be greater than 0");
+        }
+        for (key, filters) in [
+            ("numericFieldFilters", Some(&self.numeric_field_filters)),
+            ("aggregatedNumericFieldFilters", self.aggregated_numeric_field_filters.as_ref()),
+            ("nativeNumericFieldFilters", self.native_numeric_field_filters.as_ref()),
+        ] {
+            let mut indices = HashSet::new();
+            for filter in filters.into_iter().flatten() {
+                if filter.min.is_none() && filter.max.is_none() {
+                    bail!("{} entry for field {} needs min, max or both", key, filter.index);
+                }
+                if let (Some(min), Some(max)) = (filter.min, filter.max) {
+                    if min > max {
+                        bail!("{} entry for field {} has min {} greater than max {}", key, filter.index, min, max);
+                    }
+                }
+                if !indices.insert(filter.index) {
+                    bail!("{} lists field {} twice; give min and max in one entry", key, filter.index);
+                }
+            }
+        }
+        if let Some(percent) = self.sample_percent {
+            if !(0.01..=100.0).contains(&percent) {
+                bail!("samplePercent must be between 0.01 and 100, got {}", percent);
+            }
+        } else if self.sample_key.is_some() {
+            bail!("sampleKey is set but samplePercent is not");
+        }
+        if self.max_matches == Some(0) {
+            bail!("maxMatches must be greater than 0");
+        }
+        for (key, value) in [
+            ("expectedFieldCount", self.expected_field_count),
+            ("aggregatedExpectedFieldCount", self.aggregated_expected_field_count),
+            ("nativeExpectedFieldCount", self.native_expected_field_count),
+        ] {
+            if value == Some(0) {
+                bail!("{} must be greater than 0", key);
+            }
+        }
+        if self.split_gzip_min_bytes == Some(0) {
+            bail!("splitGzipMinBytes must be greater than 0");
+        }
+        if self.io_channel_capacity == Some(0) {
+            bail!("ioChannelCapacity must be at least 1");
+        }
+        if self.write_channel_capacity == Some(0) {
+            bail!("writeChannelCapacity must be at least 1");
+        }
+        for (key, value) in [("workerFlushBytes", self.worker_flush_bytes), ("writerBufferBytes", self.writer_buffer_bytes)] {
+            if value.is_some_and(|bytes| bytes < MIN_BUFFER_BYTES) {
+                bail!("{} must be at least {}", key, MIN_BUFFER_BYTES);
+            }
+        }
+        if !self.profiles.is_empty() {
+            self.validate_profiles()?;
+        }
+        Ok(())
+    }
+
+    /// Profile names end up in result directory names, so they are kept to safe characters.
+    /// Options that act on a single result stream are rejected; the profile rules themselves
+    /// are checked when their matchers are built.
+    fn validate_profiles(&self) -> Result<()> {
+        let single_query = [
+            ("splitByDomainRule", self.split_by_domain_rule.unwrap_or(false)),
+            ("maxMatches", self.max_matches.is_some()),
+            ("dedupe", self.dedupe.unwrap_or(false)),
+            ("ruleMatchCounts", self.rule_match_counts.unwrap_or(false)),
+            ("perFileReport", self.per_file_report.unwrap_or(false)),
+        ];
+        if let Some((key, _)) = single_query.iter().find(|(_, set)| *set) {
+            bail!("{} cannot be combined with profiles", key);
+        }
+        // Profiles check each line again on their own layout, without the file to detect from
+        if self.auto_detect_delimiter.unwrap_or(false) {
+            bail!("autoDetectDelimiter cannot be combined with profiles");
+        }
+        if self.reads_stdin() || self.writes_results_to_stdout() {
+            bail!("profiles cannot be combined with reading stdin or results written to stdout");
+        }
+        let mut names = HashSet::new();
+        for profile in &self.profiles {
+            let name = &profile.name;
+            if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_')) {
+                bail!("profile name {:?} must be non-empty and use only ASCII letters, digits, '-' and '_'", name);
+            }
+            if !names.insert(name) {
+                bail!("duplicate profile name {:?}", name);
+            }
+        }
+        Ok(())
+    }
+
+    /// Parquet files are written whole by one writer, so options that split, append to,
+    /// compress or stream the result text are rejected.
+    fn validate_parquet(&self) -> Result<()> {
+        if self.parquet_columns.is_empty() {
+            bail!("outputFormat parquet needs parquetColumns (the name, index and type of each column)");
+        }
+        let text_only = [
+            ("outputCompression", self.output_compression.unwrap_or_default() != OutputCompression::None),
+            ("outputEncryption", self.output_encryption.unwrap_or(false)),
+            ("outputMaxBytes", self.output_max_bytes.is_some()),
+            ("outputAppend", self.output_append.unwrap_or(false)),
+            ("prependSourceFile", self.prepend_source_file.unwrap_or(false)),
+        ];
+        if let Some((key, _)) = text_only.iter().find(|(_, set)| *set) {
+            bail!("{} cannot be combined with outputFormat parquet", key);
+        }
+        if self.reads_stdin() || self.writes_results_to_stdout() {
+            bail!("outputFormat parquet cannot be combined with reading stdin or results written to stdout");
+        }
+        let mut names = HashSet::new();
+        for column in &self.parquet_columns {
+            let name = column.name.as_str();
+            if name.is_empty() || BUILTIN_COLUMNS.contains(&name) {
+                bail!("parquetColumns name {:?} must be non-empty and not one of {:?}", name, BUILTIN_COLUMNS);
+            }
+            if !names.insert(name) {
+                bail!("duplicate parquetColumns name {:?}", name);
+            }
+        }
+        Ok(())
+    }
+
+    /// One config per entry of `profiles`: this config with the profile's keys applied.
+    pub fn profile_configs(&self) -> Vec<Config> {
+        self.profiles
+            .iter()
+            .map(|profile| {
+                let mut config = self.clone();
+                config.profiles = Vec::new();
+                config.profile_name = Some(profile.name.clone());
+                if let Some(rules) = &profile.query_domain {
+                    config.query_domain = rules.0.clone();
+                    config.query_domain_file = None;
+                }
+                if let Some(rules) = &profile.source_ip {
+                    config.source_ip = rules.0.clone();
+                    config.source_ip_file = None;
+                }
+                if let Some(rules) = &profile.exclude_source_ip {
+                    config.exclude_source_ip = rules.0.clone();
+                }
+                if let Some(rules) = &profile.resolved_ip {
+                    config.resolved_ip = rules.0.clone();
+                }
+                config.filter_logic = profile.filter_logic.or(self.filter_logic);
+                if profile.query_time_day.is_some() || profile.query_time_hour.is_some() {
+                    config.query_time_day = profile.query_time_day.clone();
+                    config.query_time_hour = profile.query_time_hour.clone();
+                }
+                if let Some(loc) = &profile.aggregated_log_result_loc {
+                    config.aggregated_log_result_loc = Some(loc.clone());
+                }
+                if let Some(loc) = &profile.native_log_result_loc {
+                    config.native_log_result_loc = Some(loc.clone());
+                }
+                config
+            })
+            .collect()
+    }
+
+    /// This config with every line filter removed, so each line read is passed on as a match.
+    /// A `profiles` run reads its inputs through it and leaves the filtering to the profiles.
+    pub fn without_filters(&self) -> Config {
+        let mut config = self.clone();
+        config.profiles = Vec::new();
+        config.query_domain = Vec::new();
+        config.source_ip = Vec::new();
+        config.exclude_source_ip = Vec::new();
+        config.source_port = Vec::new();
+        config.resolved_ip = Vec::new();
+        config.geo_database = None;
+        config.source_country = Vec::new();
+        config.exclude_country = Vec::new();
+        config.field_filters = BTreeMap::new();
+        config.aggregated_field_filters = None;
+        config.native_field_filters = None;
+        config.numeric_field_filters = Vec::new();
+        config.aggregated_numeric_field_filters = None;
+        config.native_numeric_field_filters = None;
+        config.sample_percent = None;
+        config.min_domain_labels = None;
+        config.max_domain_labels = None;
+        config.match_registrable_domain = None;
+        config
+    }
+
+    /// Parses `queryTimeStart`/`queryTimeEnd`; `None` when neither is set.
+    pub fn query_time_range(&self) -> Result<Option<TimeRange>> {
+        let parse = |key: &str, value: &Option<String>| -> Result<Option<NaiveDateTime>> {
+            value
+                .as_deref()
+                .map(|v| {
+                    NaiveDateTime::parse_from_str(v, NATIVE_TIMESTAMP_FORMAT)
+                        .with_context(|| format!("{} must be formatted as YYYYMMDDHHMMSS, got {:?}", key, v))
+                })
+                .transpose()
+        };
+        let start = parse("queryTimeStart", &self.query_time_start)?;
+        let end = parse("queryTimeEnd", &self.query_time_end)?;
+        if start.is_none() && end.is_none() {
+            return Ok(None);
+        }
+        Ok(Some(TimeRange { start, end }))
+    }
+
+    /// Compiles `nativeFileTimestampRegex`, which must capture the timestamp as a group named `ts`.
+    pub fn native_file_timestamp_regex(&self) -> Result<Option<Regex>> {
+        let Some(pattern) = &self.native_file_timestamp_regex else {
+            return Ok(None);
+        };
+        let regex = Regex::new(pattern)
+            .with_context(|| format!("invalid nativeFileTimestampRegex {:?}", pattern))?;
+        if !regex.capture_names().any(|name| name == Some("ts")) {
+            bail!("nativeFileTimestampRegex {:?} has no capture group named `ts`", pattern);
+        }
+        Ok(Some(regex))
+    }
+
+    /// Compiles `excludeFileRegex`, matched against file names.
+    pub fn exclude_file_regex(&self) -> Result<Option<Regex>> {
+        self.exclude_file_regex
+            .as_deref()
+            .map(|pattern| Regex::new(pattern).with_context(|| format!("invalid excludeFileRegex {:?}", pattern)))
+            .transpose()
+    }
+
+    /// Compiles `pruneDirectories`, glob patterns matched against directory names.
+    pub fn prune_patterns(&self) -> Result<Vec<glob::Pattern>> {
+        self.prune_directories
+            .iter()
+            .map(|p| glob::Pattern::new(p).with_context(|| format!("invalid pruneDirectories pattern {:?}", p)))
+            .collect()
+    }
+
+    pub fn field_delimiter(&self) -> u8 {
+        self.field_delimiter
+            .as_ref()
+            .map(|d| d.as_bytes()[0])
+            .unwrap_or(DEFAULT_FIELD_DELIMITER)
+    }
+
+    /// File name suffixes picked up by the directory walk; defaults to gzip files only.
+    pub fn file_extensions(&self) -> Vec<String> {
+        if self.file_extensions.is_empty() {
+            vec![".gz".to_string()]
+        } else {
+            self.file_extensions.clone()
+        }
+    }
+
+    /// This config with `started_at` recorded as the run stamp when `onExistingOutput` is
+    /// `timestamp`; otherwise an unchanged copy.
+    pub fn with_run_stamp(&self, started_at: &DateTime<Local>) -> Config {
+        let mut config = self.clone();
+        if self.on_existing_output == Some(ExistingOutput::Timestamp) {
+            config.run_stamp = Some(started_at.format("%Y%m%d%H%M%S").to_string());
+        }
+        config
+    }
+
+    /// `_{run stamp}` to insert before the extensions of result file names, or nothing.
+    pub fn run_stamp_suffix(&self) -> String {
+        self.run_stamp.as_ref().map(|stamp| format!("_{}", stamp)).unwrap_or_default()
+    }
+
+    /// Whether a task's matches stream to stdout: `outputToStdout`, or a result location of `-`.
+    pub fn result_loc_is_stdout(&self, is_aggregated: bool) -> bool {
+        let loc = if is_aggregated {
+            &self.aggregated_log_result_loc
+        } else {
+            &self.native_log_result_loc
+        };
+        self.output_to_stdout.unwrap_or(false) || loc.as_deref() == Some("-")
+    }
+
+    /// Worker count for one task: the task's own override, then `workerPoolSize`, then the CPU count.
+    pub fn worker_pool_size(&self, is_aggregated: bool) -> usize {
+        let task_size = if is_aggregated {
+            self.aggregated_worker_pool_size
+        } else {
+            self.native_worker_pool_size
+        };
+        task_size.or(self.worker_pool_size).unwrap_or_else(num_cpus::get)
+    }
+
+    /// Fields a line of one task must have: the task's own override, then `expectedFieldCount`.
+    pub fn expected_field_count(&self, is_aggregated: bool) -> Option<usize> {
+        let task_count = if is_aggregated {
+            self.aggregated_expected_field_count
+        } else {
+            self.native_expected_field_count
+        };
+        task_count.or(self.expected_field_count)
+    }
+
+    /// `logDirectory: "-"` filters stdin instead of walking a directory.
+    pub fn reads_stdin(&self) -> bool {
+        self.log_directory == "-"
+    }
+
+    pub fn writes_results_to_stdout(&self) -> bool {
+        self.result_loc_is_stdout(true) || self.result_loc_is_stdout(false)
+    }
+
+    pub fn strip_trailing_dot(&self) -> bool {
+        self.strip_trailing_dot.unwrap_or(true)
+    }
+
+    pub fn confirm_threshold_bytes(&self) -> u64 {
+        self.confirm_threshold_bytes.unwrap_or(DEFAULT_CONFIRM_THRESHOLD_BYTES)
+    }
+
+    pub fn io_channel_capacity(&self) -> usize {
+        self.io_channel_capacity.unwrap_or(DEFAULT_IO_CHANNEL_CAPACITY)
+    }
+
+    pub fn write_channel_capacity(&self) -> usize {
+        self.write_channel_capacity.unwrap_or(DEFAULT_WRITE_CHANNEL_CAPACITY)
+    }
+
+    pub fn worker_flush_bytes(&self) -> usize {
+        self.worker_flush_bytes.unwrap_or(DEFAULT_WORKER_FLUSH_BYTES)
+    }
+
+    /// `workerFlushIntervalMs`; `None` when set to 0, leaving only the size-based flush.
+    pub fn worker_flush_interval(&self) -> Option<Duration> {
+        match self.worker_flush_interval_ms.unwrap_or(DEFAULT_WORKER_FLUSH_INTERVAL_MS) {
+            0 => None,
+            ms => Some(Duration::from_millis(ms)),
+        }
+    }
+
+    pub fn writer_buffer_bytes(&self) -> usize {
+        self.writer_buffer_bytes.unwrap_or(DEFAULT_WRITER_BUFFER_BYTES)
+    }
+
+    pub fn match_options(&self) -> MatchOptions {
+        MatchOptions {
+            logic: self.filter_logic.unwrap_or_default(),
+            capture_fields: matches!(self.output_format.unwrap_or_default(), OutputFormat::Jsonl | OutputFormat::Parquet),
+            domain_rules: self.split_by_domain_rule.unwrap_or(false) || self.rule_match_counts.unwrap_or(false),
+            ip_rules: self.rule_match_counts.unwrap_or(false),
+            capture_domain: self.normalize_domain_case.unwrap_or(false),
+            domain_any_field: self.domain_any_field.unwrap_or(false),
+            domain_labels: (self.min_domain_labels.is_some() || self.max_domain_labels.is_some())
+                .then(|| self.min_domain_labels.unwrap_or(0)..=self.max_domain_labels.unwrap_or(usize::MAX)),
+            strip_trailing_dot: self.strip_trailing_dot(),
+            idn_normalize: self.idn_normalize.unwrap_or(false),
+            max_line_bytes: Some(self.max_line_bytes.unwrap_or(DEFAULT_MAX_LINE_BYTES)),
+            sample: self.sample_percent.map(|percent| Sample {
+                key: self.sample_key.unwrap_or_default(),
+                per_10000: (percent * 100.0).round() as u64,
+            }),
+        }
+    }
+
+    pub fn record_options(&self) -> RecordOptions {
+        RecordOptions {
+            format: self.output_format.unwrap_or_default(),
+            quoted: self.quoted_fields.unwrap_or(false),
+            timestamp_field: self.normalize_timestamp_field,
+            lowercase_domain: self.normalize_domain_case.unwrap_or(false),
+            output_fields: self.output_fields.clone(),
+            parquet_fields: self.parquet_columns.iter().map(|column| column.index).collect(),
+            line_terminator: self.line_terminator.unwrap_or_default(),
+        }
+    }
+
+    /// Compression and, with `outputEncryption`, a key freshly derived from the passphrase, or
+    /// the Parquet columns with `outputFormat: parquet`.
+    pub fn output_encoding(&self) -> Result<OutputEncoding> {
+        let encryption = if self.output_encryption.unwrap_or(false) {
+            let passphrase = read_passphrase(self.output_passphrase_file.as_deref())?;
+            Some(Arc::new(OutputKey::derive(&passphrase)?))
+        } else {
+            None
+        };
+        Ok(OutputEncoding {
+            compression: self.output_compression.unwrap_or_default(),
+            encryption,
+            buffer_bytes: self.writer_buffer_bytes(),
+            parquet: (self.output_format.unwrap_or_default() == OutputFormat::Parquet).then(|| {
+                let rows = self.parquet_row_group_rows.unwrap_or(DEFAULT_ROW_GROUP_ROWS);
+                Arc::new(ParquetSchema::new(&self.parquet_columns, rows))
+            }),
+        })
+    }
+
+    /// Set with `sortByTimestampField`.
+    pub fn sort_options(&self) -> Option<SortOptions> {
+        Some(SortOptions {
+            field: self.sort_by_timestamp_field?,
+            delimiter: self.field_delimiter(),
+            quoted: self.quoted_fields.unwrap_or(false),
+            compression: self.output_compression.unwrap_or_default(),
+            buffer_bytes: self.sort_buffer_bytes.unwrap_or(DEFAULT_SORT_BUFFER_BYTES),
+        })
+    }
+
+    pub fn aggregated_layout(&self) -> FieldLayout {
+        if let Some(layout) = self.json_layout() {
+            return layout;
+        }
+        FieldLayout {
+            ip_index: self.aggregated_ip_index.unwrap_or(AGGREGATED_LOG_IP_INDEX),
+            domain_index: self.aggregated_domain_index.unwrap_or(AGGREGATED_LOG_DOMAIN_INDEX),
+            resolved_ip_index: self.aggregated_resolved_ip_index,
+            delimiter: self.field_delimiter(),
+            detect_delimiter: self.auto_detect_delimiter.unwrap_or(false),
+            quoted: self.quoted_fields.unwrap_or(false),
+            field_filters: field_filters(
+                self.aggregated_field_filters.as_ref().unwrap_or(&self.field_filters),
+                self.aggregated_numeric_field_filters.as_ref().unwrap_or(&self.numeric_field_filters),
+            ),
+            json_keys: None,
+            expected_field_count: self.expected_field_count(true),
+        }
+    }
+
+    pub fn native_layout(&self) -> FieldLayout {
+        if let Some(layout) = self.json_layout() {
+            return layout;
+        }
+        FieldLayout {
+            ip_index: self.native_ip_index.unwrap_or(NATIVE_LOG_IP_INDEX),
+            domain_index: self.native_domain_index.unwrap_or(NATIVE_LOG_DOMAIN_INDEX),
+            resolved_ip_index: Some(self.resolved_ip_index.unwrap_or(NATIVE_LOG_RESOLVED_IP_INDEX)),
+            delimiter: self.field_delimiter(),
+            detect_delimiter: self.auto_detect_delimiter.unwrap_or(false),
+            quoted: self.quoted_fields.unwrap_or(false),
+            field_filters: field_filters(
+                self.native_field_filters.as_ref().unwrap_or(&self.field_filters),
+                self.native_numeric_field_filters.as_ref().unwrap_or(&self.numeric_field_filters),
+            ),
+            json_keys: None,
+            expected_field_count: self.expected_field_count(false),
+        }
+    }
+
+    /// With `logFormat: json` both tasks read the same keys: `ipField` and `domainField` as
+    /// fields 0 and 1, and `resolvedIpField`, when set, as field 2.
+    fn json_layout(&self) -> Option<FieldLayout> {
+        if self.log_format.unwrap_or_default() != LogFormat::Json {
+            return None;
+        }
+        let mut keys = vec![self.ip_field.clone().unwrap_or_default(), self.domain_field.clone().unwrap_or_default()];
+        keys.extend(self.resolved_ip_field.clone());
+        Some(FieldLayout {
+            ip_index: 0,
+            domain_index: 1,
+            resolved_ip_index: self.resolved_ip_field.as_ref().map(|_| 2),
+            delimiter: self.field_delimiter(),
+            detect_delimiter: false,
+            quoted: false,
+            field_filters: Vec::new(),
+            json_keys: Some(keys),
+            expected_field_count: None,
+        })
+    }
+}
+
+/// Converts an index -> values map and the numeric ranges into filters sorted by index.
+fn field_filters(map: &BTreeMap<usize, RuleList>, ranges: &[NumericFieldFilter]) -> Vec<FieldFilter> {
+    let mut filters: Vec<_> = map
+        .iter()
+        .map(|(&index, values)| FieldFilter {
+            index,
+            condition: FieldCondition::OneOf(values.0.iter().map(|v| v.as_bytes().to_vec()).collect()),
+        })
+        .collect();
+    filters.extend(ranges.iter().map(|range| FieldFilter {
+        index: range.index,
+        condition: FieldCondition::Range { min: range.min, max: range.max },
+    }));
+    // Stable, so a value list is checked before a range on the same field
+    filters.sort_by_key(|filter| filter.index);
+    filters
+}
+
+/// Prefix of the environment variables read by [`Config::apply_env_overrides`].
+const ENV_PREFIX: &str = "DNSLOG_";
+
+fn env_var(name: &str) -> Result<Option<String>> {
+    match std::env::var(format!("{}{}", ENV_PREFIX, name)) {
+        Ok(value) => Ok(Some(value)),
+        Err(std::env::VarError::NotPresent) => Ok(None),
+        Err(e) => bail!("invalid {}{}: {}", ENV_PREFIX, name, e),
+    }
+}
+
+fn env_parse<T>(name: &str) -> Result<Option<T>>
+where
+    T: std::str::FromStr,
+    T::Err: std::error::Error + Send + Sync + 'static,
+{
+    let Some(value) = env_var(name)? else {
+        return Ok(None);
+    };
+    let parsed = value.trim().parse().with_context(|| format!("invalid {}{} {:?}", ENV_PREFIX, name, value))?;
+    Ok(Some(parsed))
+}
+
+/// `a, b,,c` -> `["a", "b", "c"]`; an empty value gives an empty list.
+fn split_env_list(value: &str) -> Vec<String> {
+    value.split(',').map(str::trim).filter(|item| !item.is_empty()).map(str::to_string).collect()
+}
+
+/// Reads one rule per line, skipping blank lines and `#` comments.
+fn read_rule_file(path: &str) -> Result<Vec<String>> {
+    let content = fs::read_to_string(path).with_context(|| format!("failed to read rule file {:?}", path))?;
+    Ok(content
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(str::to_string)
+        .collect())
 }
 
 fn string_or_seq_string<'de, D>(deserializer: D) -> Result<Vec<String>, D::Error>
@@ -70,3 +1212,42 @@ where
         StringOrVec::None => Ok(vec![]),
     }
 }
+
+/// `coreIds` as a list of ids or as a range string for [`parse_core_ids`].
+fn core_ids_or_spec<'de, D>(deserializer: D) -> Result<Option<Vec<usize>>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum IdsOrSpec {
+        Ids(Vec<usize>),
+        Spec(String),
+        None,
+    }
+
+    match IdsOrSpec::deserialize(deserializer)? {
+        IdsOrSpec::Ids(ids) => Ok(Some(ids)),
+        IdsOrSpec::Spec(spec) => parse_core_ids(&spec).map(Some).map_err(|e| serde::de::Error::custom(format!("{:#}", e))),
+        IdsOrSpec::None => Ok(None),
+    }
+}
+
+fn port_or_seq_port<'de, D>(deserializer: D) -> Result<Vec<u16>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum PortOrVec {
+        Port(u16),
+        Vec(Vec<u16>),
+        None,
+    }
+
+    match PortOrVec::deserialize(deserializer)? {
+        PortOrVec::Port(p) => Ok(vec![p]),
+        PortOrVec::Vec(v) => Ok(v),
+        PortOrVec::None => Ok(vec![]),
+    }
+}
@@ -0,0 +1,79 @@
+use serde::Deserialize;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashSet;
+use std::hash::Hasher;
+use std::sync::{Arc, Mutex};
+
+/// Where duplicate lines are tracked when `dedupe` is on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DedupeScope {
+    /// One set shared by all workers: exact across the whole task, at the cost of a lock per match.
+    #[default]
+    Global,
+    /// One set per worker thread: no locking, but a duplicate handled by another worker slips through.
+    Worker,
+}
+
+const SHARD_COUNT: usize = 64;
+
+/// Line hashes seen so far by all workers, sharded to keep lock contention low.
+#[derive(Debug)]
+pub struct SharedSeen {
+    shards: Vec<Mutex<HashSet<u64>>>,
+}
+
+impl SharedSeen {
+    pub fn new() -> Self {
+        SharedSeen {
+            shards: (0..SHARD_COUNT).map(|_| Mutex::new(HashSet::new())).collect(),
+        }
+    }
+
+    fn insert(&self, hash: u64) -> bool {
+        let shard = &self.shards[(hash as usize) % SHARD_COUNT];
+        shard.lock().unwrap().insert(hash)
+    }
+}
+
+impl Default for SharedSeen {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Per-worker duplicate filter. Lines are compared by a 64-bit hash of their raw bytes,
+/// so memory stays at roughly 16 bytes per distinct line.
+#[derive(Debug)]
+pub enum Deduper {
+    Off,
+    Local(HashSet<u64>),
+    Shared(Arc<SharedSeen>),
+}
+
+impl Deduper {
+    /// Builds the filter for one worker; `shared` is only used for [`DedupeScope::Global`].
+    pub fn new(enabled: bool, scope: DedupeScope, shared: &Arc<SharedSeen>) -> Self {
+        match (enabled, scope) {
+            (false, _) => Deduper::Off,
+            (true, DedupeScope::Worker) => Deduper::Local(HashSet::new()),
+            (true, DedupeScope::Global) => Deduper::Shared(Arc::clone(shared)),
+        }
+    }
+
+    /// Returns `true` the first time a line is seen, `false` for a repeat.
+    pub fn first_seen(&mut self, line: &[u8]) -> bool {
+        match self {
+            Deduper::Off => true,
+            Deduper::Local(seen) => seen.insert(line_hash(line)),
+            Deduper::Shared(seen) => seen.insert(line_hash(line)),
+        }
+    }
+}
+
+pub(crate) fn line_hash(line: &[u8]) -> u64 {
+    // DefaultHasher::new() uses fixed keys, so hashes agree across threads
+    let mut hasher = DefaultHasher::new();
+    hasher.write(line);
+    hasher.finish()
+}
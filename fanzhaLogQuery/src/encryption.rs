@@ -0,0 +1,214 @@
+use anyhow::{anyhow, bail, Context, Result};
+use argon2::{Algorithm, Argon2, Params, Version};
+use chacha20poly1305::aead::generic_array::GenericArray;
+use chacha20poly1305::aead::rand_core::RngCore;
+use chacha20poly1305::aead::stream::{DecryptorBE32, EncryptorBE32};
+use chacha20poly1305::aead::{KeyInit, OsRng};
+use chacha20poly1305::{ChaCha20Poly1305, Key};
+use std::fmt;
+use std::fs;
+use std::io::{self, ErrorKind, Read, Write};
+
+/// Environment variable read for the passphrase when no passphrase file is given.
+pub const PASSPHRASE_ENV: &str = "DNSLOG_OUTPUT_PASSPHRASE";
+
+/// Start of every encrypted segment, format version 1.
+const MAGIC: &[u8; 8] = b"FZLQENC1";
+const SALT_LEN: usize = 16;
+/// STREAM nonce prefix: the 12-byte nonce minus the 32-bit chunk counter and last-chunk flag
+const NONCE_PREFIX_LEN: usize = 7;
+const TAG_LEN: usize = 16;
+/// Plaintext bytes per sealed chunk; only the last chunk of a segment is shorter
+const CHUNK_LEN: usize = 64 * 1024;
+/// Highest Argon2 memory cost (in KiB) accepted from a file header, so a damaged header
+/// cannot make decryption allocate without bound
+const MAX_MEMORY_KIB: u32 = 4 * 1024 * 1024;
+
+/// Argon2id cost parameters, stored in each header so files stay readable if the defaults change.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct KdfParams {
+    memory_kib: u32,
+    iterations: u32,
+    lanes: u32,
+}
+
+impl Default for KdfParams {
+    fn default() -> Self {
+        KdfParams { memory_kib: Params::DEFAULT_M_COST, iterations: Params::DEFAULT_T_COST, lanes: Params::DEFAULT_P_COST }
+    }
+}
+
+fn derive_key(passphrase: &[u8], params: KdfParams, salt: &[u8; SALT_LEN]) -> Result<Key> {
+    let params = Params::new(params.memory_kib, params.iterations, params.lanes, Some(32))
+        .map_err(|e| anyhow!("invalid key derivation parameters: {}", e))?;
+    let mut key = Key::default();
+    Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+        .hash_password_into(passphrase, salt, &mut key)
+        .map_err(|e| anyhow!("key derivation failed: {}", e))?;
+    Ok(key)
+}
+
+/// Key derived from the passphrase for one run (`outputEncryption`). Every file written with
+/// it shares the salt but gets its own random nonce prefix.
+pub struct OutputKey {
+    key: Key,
+    params: KdfParams,
+    salt: [u8; SALT_LEN],
+}
+
+impl OutputKey {
+    pub fn derive(passphrase: &[u8]) -> Result<Self> {
+        let mut salt = [0u8; SALT_LEN];
+        OsRng.fill_bytes(&mut salt);
+        let params = KdfParams::default();
+        Ok(OutputKey { key: derive_key(passphrase, params, &salt)?, params, salt })
+    }
+}
+
+impl fmt::Debug for OutputKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("OutputKey").finish_non_exhaustive()
+    }
+}
+
+/// Reads the passphrase from the first line of `file`, or else from [`PASSPHRASE_ENV`].
+pub fn read_passphrase(file: Option<&str>) -> Result<Vec<u8>> {
+    let passphrase = match file {
+        Some(file) => {
+            let content = fs::read_to_string(file).with_context(|| format!("failed to read passphrase file {:?}", file))?;
+            content.lines().next().unwrap_or_default().to_string()
+        }
+        None => std::env::var(PASSPHRASE_ENV).with_context(|| format!("{} is not set", PASSPHRASE_ENV))?,
+    };
+    if passphrase.is_empty() {
+        bail!("the passphrase is empty");
+    }
+    Ok(passphrase.into_bytes())
+}
+
+/// Encrypts a stream with ChaCha20-Poly1305 in the STREAM construction. A segment is a header
+/// (magic, Argon2 parameters, salt, nonce prefix) followed by sealed chunks of [`CHUNK_LEN`]
+/// plaintext bytes, each written as a little-endian `u32` length and the ciphertext with its
+/// tag. The last chunk is shorter and sealed as last, so a truncated file fails to decrypt.
+pub struct EncryptWriter<W: Write> {
+    inner: W,
+    encryptor: Option<EncryptorBE32<ChaCha20Poly1305>>,
+    /// Plaintext of the chunk being filled
+    chunk: Vec<u8>,
+}
+
+impl<W: Write> EncryptWriter<W> {
+    /// Writes the segment header to `inner`.
+    pub fn new(mut inner: W, key: &OutputKey) -> io::Result<Self> {
+        let mut nonce = [0u8; NONCE_PREFIX_LEN];
+        OsRng.fill_bytes(&mut nonce);
+        inner.write_all(MAGIC)?;
+        for value in [key.params.memory_kib, key.params.iterations, key.params.lanes] {
+            inner.write_all(&value.to_le_bytes())?;
+        }
+        inner.write_all(&key.salt)?;
+        inner.write_all(&nonce)?;
+        let encryptor = EncryptorBE32::from_aead(ChaCha20Poly1305::new(&key.key), GenericArray::from_slice(&nonce));
+        Ok(EncryptWriter { inner, encryptor: Some(encryptor), chunk: Vec::with_capacity(CHUNK_LEN + TAG_LEN) })
+    }
+
+    fn write_chunk(&mut self) -> io::Result<()> {
+        self.inner.write_all(&(self.chunk.len() as u32).to_le_bytes())?;
+        self.inner.write_all(&self.chunk)?;
+        self.chunk.clear();
+        Ok(())
+    }
+
+    /// Seals the buffered rest as the last chunk and returns the inner writer, flushed.
+    pub fn finish(mut self) -> io::Result<W> {
+        let encryptor = self.encryptor.take().expect("segment already finished");
+        encryptor.encrypt_last_in_place(b"", &mut self.chunk).map_err(|_| io::Error::other("encryption failed"))?;
+        self.write_chunk()?;
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+}
+
+impl<W: Write> Write for EncryptWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let taken = buf.len().min(CHUNK_LEN - self.chunk.len());
+        self.chunk.extend_from_slice(&buf[..taken]);
+        if self.chunk.len() == CHUNK_LEN {
+            let encryptor = self.encryptor.as_mut().expect("segment already finished");
+            encryptor.encrypt_next_in_place(b"", &mut self.chunk).map_err(|_| io::Error::other("encryption failed"))?;
+            self.write_chunk()?;
+        }
+        Ok(taken)
+    }
+
+    /// Passes on whole chunks only; a partly filled chunk waits for more data or [`EncryptWriter::finish`].
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Decrypts `reader` into `writer` (`--decrypt`), segment after segment, so files appended to
+/// by several runs come out whole. Returns the number of plaintext bytes written.
+pub fn decrypt_stream<R: Read, W: Write>(mut reader: R, writer: &mut W, passphrase: &[u8]) -> Result<u64> {
+    // Segments written by the same run share the salt; derive each key only once
+    let mut keys: Vec<(KdfParams, [u8; SALT_LEN], Key)> = Vec::new();
+    let mut chunk = Vec::with_capacity(CHUNK_LEN + TAG_LEN);
+    let mut written = 0u64;
+    let mut segments = 0;
+    loop {
+        let mut magic = [0u8; MAGIC.len()];
+        match reader.read_exact(&mut magic) {
+            Ok(()) => {}
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof && segments > 0 => break,
+            Err(e) => return Err(e).context("not an encrypted result file (too short)"),
+        }
+        if &magic != MAGIC {
+            bail!("not an encrypted result file (segment {} has no header)", segments + 1);
+        }
+        let mut header = [0u8; 12 + SALT_LEN + NONCE_PREFIX_LEN];
+        reader.read_exact(&mut header).context("truncated encrypted file header")?;
+        let value = |at: usize| u32::from_le_bytes(header[at..at + 4].try_into().unwrap());
+        let params = KdfParams { memory_kib: value(0), iterations: value(4), lanes: value(8) };
+        if params.memory_kib > MAX_MEMORY_KIB {
+            bail!("corrupt encrypted file header (memory cost {} KiB)", params.memory_kib);
+        }
+        let salt: [u8; SALT_LEN] = header[12..12 + SALT_LEN].try_into().unwrap();
+        let nonce = &header[12 + SALT_LEN..];
+        let key = match keys.iter().find(|(p, s, _)| *p == params && *s == salt) {
+            Some((_, _, key)) => *key,
+            None => {
+                let key = derive_key(passphrase, params, &salt)?;
+                keys.push((params, salt, key));
+                key
+            }
+        };
+        let mut decryptor = DecryptorBE32::from_aead(ChaCha20Poly1305::new(&key), GenericArray::from_slice(nonce));
+        segments += 1;
+
+        loop {
+            let mut len = [0u8; 4];
+            reader.read_exact(&mut len).context("truncated encrypted file")?;
+            let len = u32::from_le_bytes(len) as usize;
+            if !(TAG_LEN..=CHUNK_LEN + TAG_LEN).contains(&len) {
+                bail!("corrupt encrypted file (chunk length {})", len);
+            }
+            chunk.resize(len, 0);
+            reader.read_exact(&mut chunk).context("truncated encrypted file")?;
+            if len < CHUNK_LEN + TAG_LEN {
+                decryptor.decrypt_last_in_place(b"", &mut chunk).map_err(|_| open_failed())?;
+                writer.write_all(&chunk)?;
+                written += chunk.len() as u64;
+                break;
+            }
+            decryptor.decrypt_next_in_place(b"", &mut chunk).map_err(|_| open_failed())?;
+            writer.write_all(&chunk)?;
+            written += chunk.len() as u64;
+        }
+    }
+    writer.flush()?;
+    Ok(written)
+}
+
+fn open_failed() -> anyhow::Error {
+    anyhow!("decryption failed: wrong passphrase or corrupted file")
+}
@@ -0,0 +1,115 @@
+use crate::config::Config;
+use crate::matcher::IpMiss;
+use crate::processor::{FilterLogic, LineTrace, TraceCheck, TraceStep};
+use crate::search::build_processor;
+use anyhow::Result;
+use std::fmt::Write;
+
+/// Describes how `line` fares against the configured filters (`--explain`): the field each
+/// filter read, the rule that decided it, and the final decision. The line is checked as an
+/// aggregated log line, and also as a native one when `isQueryNativeLog` is `yes`. With
+/// `profiles` it is checked against each profile in turn.
+pub fn explain_line(config: &Config, line: &str) -> Result<String> {
+    let mut report = String::new();
+    if config.profiles.is_empty() {
+        explain_config(&mut report, config, "", line)?;
+    }
+    for profile in config.profile_configs() {
+        let prefix = format!("查询配置 {} / ", profile.profile_name.as_deref().unwrap_or_default());
+        explain_config(&mut report, &profile, &prefix, line)?;
+    }
+    Ok(report)
+}
+
+fn explain_config(report: &mut String, config: &Config, prefix: &str, line: &str) -> Result<()> {
+    let processor = build_processor(config)?;
+    let mut layouts = vec![("汇总日志", false)];
+    if config.is_query_native_log.to_lowercase() == "yes" {
+        layouts.push(("原始日志", true));
+    }
+    for (label, native) in layouts {
+        let trace = processor.explain_line(line.as_bytes(), native);
+        write_trace(report, &format!("{}{}", prefix, label), &trace);
+    }
+    Ok(())
+}
+
+fn write_trace(out: &mut String, label: &str, trace: &LineTrace) {
+    let logic = match trace.logic {
+        FilterLogic::And => "and",
+        FilterLogic::Or => "or",
+    };
+    let _ = match trace.field_count {
+        Some(count) => writeln!(out, "{}: 共 {} 个字段, 条件组合方式 {}", label, count, logic),
+        None => writeln!(out, "{}: 不是 JSON 对象, 不会匹配", label),
+    };
+    if let (Some(count), Some(expected)) = (trace.field_count, trace.expected_field_count) {
+        if count != expected {
+            let _ = writeln!(out, "  字段数 {} 不等于 expectedFieldCount {}, 作为异常行写入 malformed_lines 文件, 不参与匹配", count, expected);
+            let _ = writeln!(out, "  结论: 不匹配");
+            return;
+        }
+    }
+    if trace.checks.is_empty() {
+        let _ = writeln!(out, "  未配置任何过滤条件, 所有行都匹配");
+    }
+    for &(check, index) in &trace.checks {
+        if check == TraceCheck::Domain && trace.domain_any_field {
+            let outcome = match trace.steps.iter().find(|step| step.check == check) {
+                Some(step) if step.passed => format!("字段 #{} {}", step.index, describe_step(step)),
+                Some(_) => "没有任何字段匹配 queryDomain 规则".to_string(),
+                None => "未检查 (结果在此之前已确定)".to_string(),
+            };
+            let _ = writeln!(out, "  任意字段 域名 (domainAnyField): {}", outcome);
+            continue;
+        }
+        let step = trace.steps.iter().find(|step| step.check == check && step.index == index);
+        let outcome = match (step, trace.field_count) {
+            (Some(step), _) => describe_step(step),
+            (None, Some(count)) if index >= count => "字段缺失, 视为不满足".to_string(),
+            (None, Some(_)) => "未检查 (结果在此之前已确定)".to_string(),
+            (None, None) => "未检查".to_string(),
+        };
+        let _ = writeln!(out, "  字段 #{} {}: {}", index, check_name(check), outcome);
+    }
+    if trace.sampled_out {
+        let _ = writeln!(out, "  满足所有过滤条件, 但未被 samplePercent 抽中");
+    }
+    let _ = writeln!(out, "  结论: {}", if trace.matched { "匹配" } else { "不匹配" });
+}
+
+fn check_name(check: TraceCheck) -> &'static str {
+    match check {
+        TraceCheck::FieldFilter => "fieldFilters",
+        TraceCheck::NumericFieldFilter => "numericFieldFilters",
+        TraceCheck::SourceIp => "源IP",
+        TraceCheck::Domain => "域名",
+        TraceCheck::ResolvedIp => "解析结果IP",
+    }
+}
+
+fn describe_step(step: &TraceStep) -> String {
+    let verdict = if step.passed {
+        match &step.rule {
+            Some(rule) => format!("满足, 命中规则 {:?}", rule),
+            None => "满足".to_string(),
+        }
+    } else {
+        let reason = match (&step.ip_miss, &step.rule, step.domain_labels) {
+            (_, _, Some(labels)) => format!("共 {} 级, 不在 minDomainLabels ~ maxDomainLabels 范围内", labels),
+            (Some(IpMiss::Port), _, _) => "没有端口或端口不在 sourcePort 中".to_string(),
+            (_, Some(rule), _) => format!("命中排除规则 {:?}", rule),
+            (Some(IpMiss::Country(Some(country))), _, _) => format!("所属国家 {} 未通过 sourceCountry / excludeCountry", country),
+            (Some(IpMiss::Country(None)), _, _) => "数据库中查不到所属国家, 未通过 sourceCountry".to_string(),
+            (_, None, _) => match step.check {
+                TraceCheck::FieldFilter => "不是允许的取值".to_string(),
+                TraceCheck::NumericFieldFilter => "不是数字或不在 min ~ max 范围内".to_string(),
+                TraceCheck::SourceIp => "没有匹配的 sourceIP 规则".to_string(),
+                TraceCheck::Domain => "没有匹配的 queryDomain 规则".to_string(),
+                TraceCheck::ResolvedIp => "没有匹配的 resolvedIP 规则".to_string(),
+            },
+        };
+        format!("不满足, {}", reason)
+    };
+    format!("{:?} {}", step.value, verdict)
+}
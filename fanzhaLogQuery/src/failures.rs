@@ -0,0 +1,91 @@
+use crate::manifest::TaskRecord;
+use serde::Serialize;
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// Failed files listed by name in the summary at the end of a run; the manifest lists all
+/// of them under `fileFailures`.
+pub const SUMMARY_PATHS: usize = 10;
+
+/// Why an input file could not be searched to the end.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FailureKind {
+    /// The file could not be opened
+    Open,
+    /// Reading or downloading the file failed
+    Read,
+    /// Bad compressed header, or corrupt or truncated compressed data
+    Decode,
+    /// Searching the file failed otherwise
+    Process,
+}
+
+impl FailureKind {
+    pub const ALL: [FailureKind; 4] = [FailureKind::Open, FailureKind::Read, FailureKind::Decode, FailureKind::Process];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            FailureKind::Open => "open",
+            FailureKind::Read => "read",
+            FailureKind::Decode => "decode",
+            FailureKind::Process => "process",
+        }
+    }
+}
+
+/// One input counted in `failedFiles`.
+#[derive(Debug, Clone, Serialize)]
+pub struct FileFailure {
+    pub path: PathBuf,
+    pub kind: FailureKind,
+    pub error: String,
+}
+
+/// Failures of one search task, shared by its workers. A file is kept once, with its first
+/// failure, also when its pieces fail separately (`splitGzipMinBytes`).
+#[derive(Debug, Default)]
+pub struct FailureLog {
+    state: Mutex<(Vec<FileFailure>, HashSet<PathBuf>)>,
+}
+
+impl FailureLog {
+    pub fn record(&self, path: &Path, kind: FailureKind, error: impl ToString) {
+        let mut state = self.state.lock().unwrap();
+        let (failures, seen) = &mut *state;
+        if seen.insert(path.to_path_buf()) {
+            failures.push(FileFailure { path: path.to_path_buf(), kind, error: error.to_string() });
+        }
+    }
+
+    /// The failures in the order they happened.
+    pub fn take(&self) -> Vec<FileFailure> {
+        let mut state = self.state.lock().unwrap();
+        state.1.clear();
+        std::mem::take(&mut state.0)
+    }
+}
+
+/// Prints the failures of all tasks to stderr in one place, so they are not lost among the
+/// progress output: counts per kind, then the first [`SUMMARY_PATHS`] files. Nothing is
+/// printed when every file was searched.
+pub fn print_failure_summary(records: &[TaskRecord]) {
+    let failures: Vec<&FileFailure> = records.iter().flat_map(|record| &record.file_failures).collect();
+    if failures.is_empty() {
+        return;
+    }
+    let counts: Vec<String> = FailureKind::ALL
+        .iter()
+        .map(|&kind| (kind, failures.iter().filter(|failure| failure.kind == kind).count()))
+        .filter(|&(_, count)| count > 0)
+        .map(|(kind, count)| format!("{} {}", kind.name(), count))
+        .collect();
+    eprintln!("\nInput failures: {} file(s) could not be searched to the end ({})", failures.len(), counts.join(", "));
+    for failure in failures.iter().take(SUMMARY_PATHS) {
+        eprintln!("  {:<8} {:?}: {}", failure.kind.name(), failure.path, failure.error);
+    }
+    if failures.len() > SUMMARY_PATHS {
+        eprintln!("  ... and {} more", failures.len() - SUMMARY_PATHS);
+    }
+}
@@ -0,0 +1,111 @@
+use anyhow::{anyhow, bail, Result};
+use maxminddb::Reader;
+use serde::Deserialize;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::net::IpAddr;
+use std::sync::Mutex;
+
+const SHARD_COUNT: usize = 64;
+/// Addresses cached per shard; a full shard starts over, which caps the cache at about a
+/// million addresses
+const SHARD_CAPACITY: usize = 16 * 1024;
+
+/// The part of a GeoIP2 / GeoLite2 Country or City record read here.
+#[derive(Deserialize)]
+struct CountryRecord<'a> {
+    #[serde(borrow)]
+    country: Option<IsoCode<'a>>,
+}
+
+#[derive(Deserialize)]
+struct IsoCode<'a> {
+    iso_code: Option<&'a str>,
+}
+
+/// Filters source IPs by the country a MaxMind database (`geoDatabase`) places them in:
+/// `sourceCountry` lists the countries kept, `excludeCountry` those dropped. An address the
+/// database has no country for (private ranges, unparseable fields) is in no country, so it
+/// fails `sourceCountry` and passes `excludeCountry`.
+pub struct CountryFilter {
+    reader: Reader<Vec<u8>>,
+    include: Vec<[u8; 2]>,
+    exclude: Vec<[u8; 2]>,
+    /// Decision per address, sharded to keep lock contention low; DNS logs repeat the same
+    /// clients heavily, so most lines skip the database lookup
+    cache: Vec<Mutex<HashMap<IpAddr, bool>>>,
+}
+
+impl CountryFilter {
+    /// Loads the database at `path` and checks that every code is a two-letter ISO 3166 code.
+    pub fn open(path: &str, include: &[String], exclude: &[String]) -> Result<Self> {
+        let reader = Reader::open_readfile(path).map_err(|e| anyhow!("failed to open geo database {:?}: {}", path, e))?;
+        Ok(CountryFilter {
+            reader,
+            include: country_codes(include, "sourceCountry")?,
+            exclude: country_codes(exclude, "excludeCountry")?,
+            cache: (0..SHARD_COUNT).map(|_| Mutex::new(HashMap::new())).collect(),
+        })
+    }
+
+    /// Whether an address (`None` when the field is not an IP) passes the country lists.
+    pub fn allows(&self, ip: Option<IpAddr>) -> bool {
+        let Some(ip) = ip else {
+            return self.decide(None);
+        };
+        let mut hasher = DefaultHasher::new();
+        ip.hash(&mut hasher);
+        let shard = &self.cache[(hasher.finish() as usize) % SHARD_COUNT];
+        if let Some(&allowed) = shard.lock().unwrap().get(&ip) {
+            return allowed;
+        }
+        // Looked up outside the lock; a concurrent miss on the same address just repeats the work
+        let allowed = self.decide(self.country(ip));
+        let mut shard = shard.lock().unwrap();
+        if shard.len() >= SHARD_CAPACITY {
+            shard.clear();
+        }
+        shard.insert(ip, allowed);
+        allowed
+    }
+
+    fn decide(&self, country: Option<[u8; 2]>) -> bool {
+        let included = self.include.is_empty() || country.is_some_and(|code| self.include.contains(&code));
+        included && !country.is_some_and(|code| self.exclude.contains(&code))
+    }
+
+    /// The ISO code of the country the database places `ip` in, e.g. `"CN"`.
+    pub fn country_code(&self, ip: IpAddr) -> Option<String> {
+        self.country(ip).map(|code| String::from_utf8_lossy(&code).into_owned())
+    }
+
+    fn country(&self, ip: IpAddr) -> Option<[u8; 2]> {
+        let record: CountryRecord = self.reader.lookup(ip).ok()?;
+        record.country?.iso_code?.as_bytes().try_into().ok()
+    }
+}
+
+impl fmt::Debug for CountryFilter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CountryFilter")
+            .field("include", &self.include)
+            .field("exclude", &self.exclude)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Upper-cased codes, as the database stores them.
+fn country_codes(codes: &[String], key: &str) -> Result<Vec<[u8; 2]>> {
+    codes
+        .iter()
+        .map(|code| {
+            let upper = code.trim().to_ascii_uppercase();
+            match <[u8; 2]>::try_from(upper.as_bytes()) {
+                Ok(bytes) if bytes.iter().all(u8::is_ascii_alphabetic) => Ok(bytes),
+                _ => bail!("invalid country code {:?} in {} (expected a two-letter code such as \"CN\")", code, key),
+            }
+        })
+        .collect()
+}
@@ -0,0 +1,36 @@
+//! DNS aggregated / native log search.
+//!
+//! [`run_query`] runs the searches described by a [`Config`] the same way the
+//! `fanzha_log_query` binary does; [`FileProcessor`] with [`IPMatcher`] and
+//! [`DomainMatcher`] is the matching engine underneath, usable on its own.
+
+#[macro_use]
+pub mod status;
+
+pub mod benchmark;
+pub mod columnar;
+pub mod config;
+pub mod dedupe;
+pub mod encryption;
+pub mod explain;
+pub mod failures;
+pub mod geo;
+pub mod manifest;
+pub mod matcher;
+mod memory;
+pub mod output;
+pub mod processor;
+pub mod progress;
+mod remote;
+pub mod schema;
+pub mod search;
+pub mod sort;
+pub mod suffix;
+
+pub use crate::config::Config;
+pub use crate::matcher::{DomainMatcher, IPMatcher};
+pub use crate::processor::FileProcessor;
+pub use crate::search::{
+    build_processor, exit_code, request_shutdown, run_follow_query, run_query, run_stdin_query, shutdown_requested,
+    QueryReport, DEFAULT_FOLLOW_INTERVAL, EXIT_INTERRUPTED, EXIT_MATCHES, EXIT_NO_MATCHES, EXIT_PARTIAL_FAILURE,
+};
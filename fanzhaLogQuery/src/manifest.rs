@@ -0,0 +1,293 @@
+use crate::config::{Config, NumericFieldFilter, RuleList};
+use crate::failures::FileFailure;
+use crate::output::FileChecksum;
+use crate::processor::{FilterLogic, ProcessStats, SampleKey};
+use anyhow::{Context, Result};
+use chrono::{DateTime, Local};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Clone, Serialize)]
+pub struct InputFile {
+    pub path: PathBuf,
+    pub bytes: u64,
+}
+
+/// Collects the sizes of the input files up front; a file that cannot be stat'ed is listed with 0 bytes.
+pub fn input_files(files: &[PathBuf]) -> Vec<InputFile> {
+    files
+        .iter()
+        .map(|path| InputFile {
+            path: path.clone(),
+            bytes: fs::metadata(path).map(|m| m.len()).unwrap_or(0),
+        })
+        .collect()
+}
+
+/// What one search task read and produced.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TaskRecord {
+    pub task: &'static str,
+    /// Entry of `profiles` the record belongs to
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub profile: Option<String>,
+    /// Result file, or `None` when results went to stdout or were only counted.
+    pub output: Option<PathBuf>,
+    pub inputs: Vec<InputFile>,
+    pub lines_scanned: usize,
+    pub matches: usize,
+    pub interrupted: bool,
+    /// Stopped early on `maxMatches`
+    pub truncated: bool,
+    /// Compressed inputs that failed the header check and were not processed
+    pub skipped_inputs: Vec<PathBuf>,
+    /// Inputs that were skipped, could not be read or were cut short by corrupt data
+    pub failed_files: usize,
+    /// What went wrong with each of those inputs
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub file_failures: Vec<FileFailure>,
+    /// Lines whose field count differs from `expectedFieldCount`
+    pub malformed_lines: usize,
+    /// Where those lines were written
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub malformed_output: Option<PathBuf>,
+    /// Per-rule result files written with `splitByDomainRule`, instead of `output`
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub split_outputs: Vec<PathBuf>,
+    /// SHA-256 of each result file created (parts and per-rule files included), computed as it
+    /// was written; files appended to are not listed
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub output_sha256: Vec<FileChecksum>,
+    /// Matches per `sourceIP` rule, with `ruleMatchCounts`
+    #[serde(rename = "sourceIPRuleMatches", skip_serializing_if = "RuleCounts::is_empty")]
+    pub ip_rule_matches: RuleCounts,
+    /// Matches per `queryDomain` rule, with `ruleMatchCounts`
+    #[serde(skip_serializing_if = "RuleCounts::is_empty")]
+    pub domain_rule_matches: RuleCounts,
+}
+
+impl TaskRecord {
+    pub fn new(task: &'static str, output: Option<PathBuf>, inputs: Vec<InputFile>, stats: ProcessStats, interrupted: bool, truncated: bool) -> Self {
+        TaskRecord {
+            task,
+            profile: None,
+            output,
+            inputs,
+            lines_scanned: stats.lines,
+            matches: stats.matches,
+            interrupted,
+            truncated,
+            skipped_inputs: Vec::new(),
+            failed_files: stats.failed_files,
+            file_failures: Vec::new(),
+            malformed_lines: stats.malformed_lines,
+            malformed_output: None,
+            split_outputs: Vec::new(),
+            output_sha256: Vec::new(),
+            ip_rule_matches: RuleCounts::default(),
+            domain_rule_matches: RuleCounts::default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RuleCount {
+    pub rule: String,
+    pub matches: usize,
+}
+
+/// Matches attributed to each rule of one rule list (`ruleMatchCounts`), in config order.
+/// A line is counted under the first rule it satisfies; counts are of lines written, after
+/// `dedupe` and `maxMatches`.
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(transparent)]
+pub struct RuleCounts(pub Vec<RuleCount>);
+
+impl RuleCounts {
+    pub fn new(rules: &[String]) -> Self {
+        RuleCounts(rules.iter().map(|rule| RuleCount { rule: rule.clone(), matches: 0 }).collect())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Counts one match for rule `index`; out-of-range indexes are ignored.
+    pub fn add(&mut self, index: usize) {
+        if let Some(count) = self.0.get_mut(index) {
+            count.matches += 1;
+        }
+    }
+
+    /// Adds another worker's counts for the same rule list.
+    pub fn merge(&mut self, other: RuleCounts) {
+        if self.0.is_empty() {
+            *self = other;
+            return;
+        }
+        for (count, other) in self.0.iter_mut().zip(other.0) {
+            count.matches += other.matches;
+        }
+    }
+
+    /// Rules with at least one match, most matches first.
+    pub fn ranked(&self) -> Vec<&RuleCount> {
+        let mut ranked: Vec<&RuleCount> = self.0.iter().filter(|count| count.matches > 0).collect();
+        ranked.sort_by_key(|count| std::cmp::Reverse(count.matches));
+        ranked
+    }
+}
+
+/// Counters for one processed input file.
+#[derive(Debug)]
+pub struct FileReport {
+    pub path: PathBuf,
+    /// Size as read from disk, before decompression
+    pub bytes: u64,
+    pub stats: ProcessStats,
+}
+
+/// Writes `per_file_report_{task}{stamp}.csv` (`path,matches,lines,bytes`) into `dir`, one row per
+/// processed file in path order. Match counts are taken before `dedupe`.
+pub fn write_per_file_report(dir: &Path, task: &str, stamp: &str, mut reports: Vec<FileReport>) -> Result<PathBuf> {
+    reports.sort_by(|a, b| a.path.cmp(&b.path));
+    fs::create_dir_all(dir)?;
+    let path = dir.join(format!("per_file_report_{}{}.csv", task, stamp));
+    let file = File::create(&path).with_context(|| format!("failed to create {:?}", path))?;
+    let mut out = BufWriter::new(file);
+    writeln!(out, "path,matches,lines,bytes")?;
+    for report in &reports {
+        writeln!(
+            out,
+            "{},{},{},{}",
+            csv_field(&report.path.to_string_lossy()),
+            report.stats.matches,
+            report.stats.lines,
+            report.bytes
+        )?;
+    }
+    out.flush().with_context(|| format!("failed to write {:?}", path))?;
+    Ok(path)
+}
+
+/// Quotes a CSV field when it contains a separator, quote or line break.
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Filters<'a> {
+    // Field names follow the config keys
+    query_domain: &'a [String],
+    query_domain_file: &'a Option<String>,
+    #[serde(rename = "sourceIP")]
+    source_ip: &'a [String],
+    #[serde(rename = "sourceIPFile")]
+    source_ip_file: &'a Option<String>,
+    #[serde(rename = "excludeSourceIP")]
+    exclude_source_ip: &'a [String],
+    source_port: &'a [u16],
+    #[serde(rename = "resolvedIP")]
+    resolved_ip: &'a [String],
+    filter_logic: FilterLogic,
+    field_filters: &'a BTreeMap<usize, RuleList>,
+    aggregated_field_filters: &'a Option<BTreeMap<usize, RuleList>>,
+    native_field_filters: &'a Option<BTreeMap<usize, RuleList>>,
+    numeric_field_filters: &'a [NumericFieldFilter],
+    aggregated_numeric_field_filters: &'a Option<Vec<NumericFieldFilter>>,
+    native_numeric_field_filters: &'a Option<Vec<NumericFieldFilter>>,
+    #[serde(rename = "queryTime_day")]
+    query_time_day: &'a Option<Vec<String>>,
+    #[serde(rename = "queryTime_hour")]
+    query_time_hour: &'a Option<Vec<String>>,
+    query_time_start: &'a Option<String>,
+    query_time_end: &'a Option<String>,
+    sample_percent: Option<f64>,
+    sample_key: Option<SampleKey>,
+    min_domain_labels: Option<usize>,
+    max_domain_labels: Option<usize>,
+    match_registrable_domain: Option<bool>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Manifest<'a> {
+    tool_version: &'static str,
+    started_at: String,
+    finished_at: String,
+    filters: Filters<'a>,
+    tasks: Vec<&'a TaskRecord>,
+    total_lines_scanned: usize,
+    total_matches: usize,
+}
+
+/// Writes `manifest.json` (with the run stamp of `onExistingOutput: timestamp`) next to each result file, covering the tasks whose results share
+/// that directory. Tasks without a result file (stdout, count-only) get no manifest.
+pub fn write_manifests<'a>(
+    config: &Config,
+    started_at: DateTime<Local>,
+    finished_at: DateTime<Local>,
+    tasks: impl IntoIterator<Item = &'a TaskRecord>,
+) -> Result<Vec<PathBuf>> {
+    let mut by_dir: BTreeMap<&Path, Vec<&TaskRecord>> = BTreeMap::new();
+    for task in tasks {
+        if let Some(dir) = task.output.as_deref().and_then(Path::parent) {
+            by_dir.entry(dir).or_default().push(task);
+        }
+    }
+
+    let mut written = Vec::new();
+    for (dir, tasks) in by_dir {
+        let manifest = Manifest {
+            tool_version: env!("CARGO_PKG_VERSION"),
+            started_at: started_at.to_rfc3339(),
+            finished_at: finished_at.to_rfc3339(),
+            filters: Filters {
+                query_domain: &config.query_domain,
+                query_domain_file: &config.query_domain_file,
+                source_ip: &config.source_ip,
+                source_ip_file: &config.source_ip_file,
+                exclude_source_ip: &config.exclude_source_ip,
+                source_port: &config.source_port,
+                resolved_ip: &config.resolved_ip,
+                filter_logic: config.filter_logic.unwrap_or_default(),
+                field_filters: &config.field_filters,
+                aggregated_field_filters: &config.aggregated_field_filters,
+                native_field_filters: &config.native_field_filters,
+                numeric_field_filters: &config.numeric_field_filters,
+                aggregated_numeric_field_filters: &config.aggregated_numeric_field_filters,
+                native_numeric_field_filters: &config.native_numeric_field_filters,
+                query_time_day: &config.query_time_day,
+                query_time_hour: &config.query_time_hour,
+                query_time_start: &config.query_time_start,
+                query_time_end: &config.query_time_end,
+                sample_percent: config.sample_percent,
+                sample_key: config.sample_percent.map(|_| config.sample_key.unwrap_or_default()),
+                min_domain_labels: config.min_domain_labels,
+                max_domain_labels: config.max_domain_labels,
+                match_registrable_domain: config.match_registrable_domain,
+            },
+            total_lines_scanned: tasks.iter().map(|t| t.lines_scanned).sum(),
+            total_matches: tasks.iter().map(|t| t.matches).sum(),
+            tasks,
+        };
+
+        let path = dir.join(format!("manifest{}.json", config.run_stamp_suffix()));
+        let file = File::create(&path).with_context(|| format!("failed to create {:?}", path))?;
+        let mut out = BufWriter::new(file);
+        serde_json::to_writer_pretty(&mut out, &manifest)?;
+        out.write_all(b"\n")?;
+        out.flush().with_context(|| format!("failed to write {:?}", path))?;
+        written.push(path);
+    }
+    Ok(written)
+}
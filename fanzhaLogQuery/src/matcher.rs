@@ -1,7 +1,25 @@
-use std::net::IpAddr;
+use crate::geo::CountryFilter;
+use crate::suffix::RegistrableDomains;
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr};
 use std::str::FromStr;
+use std::sync::Arc;
 use cidr::IpCidr;
-use anyhow::Result;
+use anyhow::{anyhow, bail, Context, Result};
+use serde::Deserialize;
+use regex::bytes::Regex;
+use aho_corasick::{AhoCorasick, AhoCorasickBuilder, Anchored, Input, MatchKind, StartKind};
+
+/// How addresses are written in the source IP field.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum IpFieldEncoding {
+    /// Dotted-quad IPv4 or IPv6 text
+    #[default]
+    Dotted,
+    /// IPv4 as a decimal 32-bit integer, `167772161` for 10.0.0.1
+    Uint32,
+}
 
 #[derive(Debug)]
 enum IPRule {
@@ -9,70 +27,119 @@ enum IPRule {
     Cidr(IpCidr),
     Range(IpAddr, IpAddr),
     Prefix(Vec<u8>), // Optimization for /8, /16, /24
+    Prefix6(Vec<u8>, IpCidr), // Optimization for IPv6 CIDRs on a colon boundary (/16, /32, /48, ...)
 }
 
 impl IPRule {
-    fn parse(input: &str) -> Result<Self> {
+    /// `text_prefixes` allows the byte-level shortcuts (`Exact`, `Prefix`, `Prefix6`), which only
+    /// hold for fields written as IP text. Anything with a `/` must be a valid CIDR and anything
+    /// with a `-` a valid range; without the shortcuts an exact rule must be a valid address.
+    fn parse(input: &str, text_prefixes: bool) -> Result<Self> {
+        let input = input.trim();
         // Try CIDR
         if input.contains('/') {
-            if let Ok(cidr) = IpCidr::from_str(input) {
-                // Optimization: Convert common IPv4 CIDRs to prefix matches
-                if let IpCidr::V4(v4_cidr) = cidr {
-                    let mask = v4_cidr.network_length();
-                    let ip = v4_cidr.first_address();
-                    let octets = ip.octets();
-                    
-                    if mask == 24 {
-                        let prefix = format!("{}.{}.{}.", octets[0], octets[1], octets[2]);
-                        return Ok(IPRule::Prefix(prefix.into_bytes()));
-                    } else if mask == 16 {
-                        let prefix = format!("{}.{}.", octets[0], octets[1]);
-                        return Ok(IPRule::Prefix(prefix.into_bytes()));
-                    } else if mask == 8 {
-                        let prefix = format!("{}.", octets[0]);
-                        return Ok(IPRule::Prefix(prefix.into_bytes()));
-                    }
-                }
+            let cidr = IpCidr::from_str(input).map_err(|e| anyhow!("invalid CIDR: {}", e))?;
+            if !text_prefixes {
                 return Ok(IPRule::Cidr(cidr));
             }
+            // Optimization: Convert common IPv4 CIDRs to prefix matches
+            if let IpCidr::V4(v4_cidr) = cidr {
+                let mask = v4_cidr.network_length();
+                let ip = v4_cidr.first_address();
+                let octets = ip.octets();
+
+                if mask == 24 {
+                    let prefix = format!("{}.{}.{}.", octets[0], octets[1], octets[2]);
+                    return Ok(IPRule::Prefix(prefix.into_bytes()));
+                } else if mask == 16 {
+                    let prefix = format!("{}.{}.", octets[0], octets[1]);
+                    return Ok(IPRule::Prefix(prefix.into_bytes()));
+                } else if mask == 8 {
+                    let prefix = format!("{}.", octets[0]);
+                    return Ok(IPRule::Prefix(prefix.into_bytes()));
+                }
+            }
+            if let IpCidr::V6(v6_cidr) = cidr {
+                if let Some(prefix) = ipv6_text_prefix(v6_cidr.first_address().segments(), v6_cidr.network_length()) {
+                    return Ok(IPRule::Prefix6(prefix.into_bytes(), cidr));
+                }
+            }
+            return Ok(IPRule::Cidr(cidr));
         }
 
         // Try Range
-        if input.contains('-') {
-            let parts: Vec<&str> = input.split('-').collect();
-            if parts.len() == 2 {
-                let start = IpAddr::from_str(parts[0].trim())?;
-                let end = IpAddr::from_str(parts[1].trim())?;
-                return Ok(IPRule::Range(start, end));
+        if let Some((start, end)) = input.split_once('-') {
+            let start = IpAddr::from_str(start.trim()).with_context(|| format!("invalid range start {:?}", start.trim()))?;
+            let end = IpAddr::from_str(end.trim()).with_context(|| format!("invalid range end {:?}", end.trim()))?;
+            if start.is_ipv4() != end.is_ipv4() {
+                bail!("range mixes IPv4 and IPv6 addresses");
+            }
+            if start > end {
+                bail!("range start is after its end");
             }
+            return Ok(IPRule::Range(start, end));
         }
 
         // Default Exact
+        if !text_prefixes {
+            let ip = IpAddr::from_str(input).context("invalid IP address")?;
+            return Ok(IPRule::Range(ip, ip));
+        }
         Ok(IPRule::Exact(input.to_string()))
     }
 
     fn matches(&self, ip_bytes: &[u8]) -> bool {
+        // Some logs pad their fields (` 10.0.0.1 `); padding must not change the result
+        let ip_bytes = ip_bytes.trim_ascii();
         match self {
             IPRule::Exact(target) => ip_bytes == target.as_bytes(),
             IPRule::Prefix(prefix) => ip_bytes.starts_with(prefix),
-            IPRule::Cidr(cidr) => {
-                if let Some(ip) = parse_ip_from_bytes(ip_bytes) {
-                    return cidr.contains(&ip);
-                }
-                false
-            }
-            IPRule::Range(start, end) => {
-                if let Some(ip) = parse_ip_from_bytes(ip_bytes) {
-                    return ip >= *start && ip <= *end;
-                }
-                false
+            IPRule::Prefix6(prefix, cidr) => {
+                (ip_bytes.len() > prefix.len() && ip_bytes[..prefix.len()].eq_ignore_ascii_case(prefix))
+                    // Leading zeros or expanded groups hide the prefix; the address still counts
+                    || (ip_bytes.contains(&b':')
+                        && parse_ip_from_bytes(ip_bytes).is_some_and(|ip| cidr.contains(&ip)))
             }
+            IPRule::Cidr(_) | IPRule::Range(..) => parse_ip_from_bytes(ip_bytes).is_some_and(|ip| self.contains(ip)),
+        }
+    }
+
+    /// Checks a parsed address; the text-only rules never match here.
+    fn contains(&self, ip: IpAddr) -> bool {
+        match self {
+            IPRule::Cidr(cidr) | IPRule::Prefix6(_, cidr) => cidr.contains(&ip),
+            IPRule::Range(start, end) => ip >= *start && ip <= *end,
+            IPRule::Exact(_) | IPRule::Prefix(_) => false,
         }
     }
 }
 
+/// Builds the textual prefix shared by every address in an IPv6 CIDR, e.g. `2001:db8:` for
+/// `2001:db8::/32`.
+///
+/// Only colon-aligned lengths below /128 qualify, and only when none of the network groups is zero:
+/// a zero group may be written as `::` in the log, which a literal prefix would not see.
+/// With all network groups non-zero they always appear verbatim at the start of the
+/// compressed (inet_ntop / RFC 5952) form, so `starts_with` settles such input; other spellings
+/// of an address (`2001:0db8::1`, all eight groups written out) are parsed instead.
+fn ipv6_text_prefix(segments: [u16; 8], network_length: u8) -> Option<String> {
+    if network_length == 0 || network_length >= 128 || !network_length.is_multiple_of(16) {
+        return None;
+    }
+    let groups = &segments[..(network_length / 16) as usize];
+    if groups.contains(&0) {
+        return None;
+    }
+    let mut prefix = String::new();
+    for group in groups {
+        prefix.push_str(&format!("{:x}:", group));
+    }
+    Some(prefix)
+}
+
 #[inline(always)]
 fn parse_ip_from_bytes(bytes: &[u8]) -> Option<IpAddr> {
+    let bytes = bytes.trim_ascii();
     // Try fast path for IPv4
     // IPv4 typically: d.d.d.d, max length 15.
     if bytes.len() > 15 {
@@ -100,7 +167,7 @@ fn parse_ip_from_bytes(bytes: &[u8]) -> Option<IpAddr> {
             octet_idx += 1;
             current = 0;
             has_digit = false;
-        } else if b >= b'0' && b <= b'9' {
+        } else if b.is_ascii_digit() {
             current = current * 10 + (b - b'0') as u16;
             has_digit = true;
         } else {
@@ -123,46 +190,254 @@ fn parse_ip_from_bytes(bytes: &[u8]) -> Option<IpAddr> {
     }
 }
 
-#[derive(Debug)]
+/// Splits a source field into address and port: `10.0.0.5:53211`, `[2001:db8::1]:53`.
+/// A bare IPv6 address (several colons, no brackets) has no port.
+fn split_host_port(field: &[u8]) -> (&[u8], Option<&[u8]>) {
+    if let Some(rest) = field.strip_prefix(b"[") {
+        if let Some(end) = memchr::memchr(b']', rest) {
+            let port = rest[end + 1..].strip_prefix(b":");
+            return (&rest[..end], port);
+        }
+        return (field, None);
+    }
+    let mut colons = memchr::memchr_iter(b':', field);
+    match (colons.next(), colons.next()) {
+        (Some(pos), None) => (&field[..pos], Some(&field[pos + 1..])),
+        _ => (field, None),
+    }
+}
+
+/// Parses a decimal `u32` field (`167772161`) as an IPv4 address.
+fn parse_uint32_ip(bytes: &[u8]) -> Option<IpAddr> {
+    let value: u32 = std::str::from_utf8(bytes.trim_ascii()).ok()?.parse().ok()?;
+    Some(IpAddr::V4(Ipv4Addr::from(value)))
+}
+
+fn parse_port(bytes: &[u8]) -> Option<u16> {
+    std::str::from_utf8(bytes).ok()?.parse().ok()
+}
+
+/// Why a source field did not pass an [`IPMatcher`], for `--explain`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum IpMiss {
+    /// No port, or one missing from `sourcePort`
+    Port,
+    /// Hit the exclude rule at this index in [`IPMatcher::exclude_rule_names`]
+    Excluded(usize),
+    /// Satisfied no include rule, or is not an address
+    NoRule,
+    /// Passed the rules but not `sourceCountry` / `excludeCountry`; the country the database
+    /// places it in, if any
+    Country(Option<String>),
+}
+
+#[derive(Debug, Default)]
 pub struct IPMatcher {
     rules: Vec<IPRule>,
+    exclude_rules: Vec<IPRule>,
+    strip_port: bool,
+    ports: Vec<u16>,
+    encoding: IpFieldEncoding,
+    /// The include rules as written, indexed like `rules`
+    rule_names: Vec<String>,
+    /// The exclude rules as written, indexed like `exclude_rules`
+    exclude_rule_names: Vec<String>,
+    /// `sourceCountry` / `excludeCountry`, checked after the address rules
+    countries: Option<Arc<CountryFilter>>,
 }
 
 impl IPMatcher {
-    pub fn new(inputs: &[String]) -> Result<Self> {
-        let mut rules = Vec::new();
-        for input in inputs {
-            if !input.trim().is_empty() {
-                rules.push(IPRule::parse(input)?);
+    /// Builds a matcher from include rules and exclude rules; an address matches when it
+    /// satisfies the include rules (or there are none) and hits no exclude rule.
+    pub fn new(inputs: &[String], excludes: &[String]) -> Result<Self> {
+        Self::with_encoding(inputs, excludes, IpFieldEncoding::Dotted)
+    }
+
+    /// Like [`IPMatcher::new`] for fields written in `encoding`. With [`IpFieldEncoding::Uint32`]
+    /// every rule is checked against the parsed address; exact rules must then be valid IPs.
+    ///
+    /// Every malformed rule is reported at once, each with its text and 1-based position in
+    /// its list, so a long rule list can be fixed in one go.
+    pub fn with_encoding(inputs: &[String], excludes: &[String], encoding: IpFieldEncoding) -> Result<Self> {
+        let text_prefixes = encoding == IpFieldEncoding::Dotted;
+        let mut errors = Vec::new();
+        let rules = parse_ip_rules(inputs, text_prefixes, "rule", &mut errors);
+        let exclude_rules = parse_ip_rules(excludes, text_prefixes, "exclude rule", &mut errors);
+        if !errors.is_empty() {
+            bail!("{} invalid IP rule(s):\n{}", errors.len(), errors.join("\n"));
+        }
+        Ok(IPMatcher {
+            rules,
+            exclude_rules,
+            strip_port: false,
+            ports: Vec::new(),
+            encoding,
+            rule_names: inputs.iter().filter(|input| !input.trim().is_empty()).cloned().collect(),
+            exclude_rule_names: excludes.iter().filter(|input| !input.trim().is_empty()).cloned().collect(),
+            countries: None,
+        })
+    }
+
+    /// Strips a trailing `:port` (or `[v6]:port`) from the field before the address rules run,
+    /// and, when `ports` is non-empty, requires the port to be one of them.
+    pub fn with_ports(mut self, strip_port: bool, ports: &[u16]) -> Self {
+        self.strip_port = strip_port || !ports.is_empty();
+        self.ports = ports.to_vec();
+        self
+    }
+
+    /// Also requires addresses that pass the rules to pass `countries`, which costs a geo
+    /// database lookup per distinct address.
+    pub fn with_countries(mut self, countries: CountryFilter) -> Self {
+        self.countries = Some(Arc::new(countries));
+        self
+    }
+
+    pub fn matches(&self, field: &[u8]) -> bool {
+        self.find_rule(field).is_some()
+    }
+
+    /// Like [`IPMatcher::matches`], but names the rule that matched: the index in
+    /// [`IPMatcher::rule_names`] of the first include rule, in config order, that the address
+    /// satisfies. `None` when the address does not match or there are no include rules.
+    pub fn matched_rule(&self, field: &[u8]) -> Option<usize> {
+        self.find_rule(field).flatten()
+    }
+
+    /// The include rules as written in the config.
+    pub fn rule_names(&self) -> &[String] {
+        &self.rule_names
+    }
+
+    /// The exclude rules as written in the config.
+    pub fn exclude_rule_names(&self) -> &[String] {
+        &self.exclude_rule_names
+    }
+
+    /// Why `field` does not match, checked in the order [`IPMatcher::matches`] applies the
+    /// filters; `None` when it matches. Slower than `matches`, meant for single lines.
+    pub fn explain_miss(&self, field: &[u8]) -> Option<IpMiss> {
+        if self.matches(field) {
+            return None;
+        }
+        let ip_bytes = if self.strip_port {
+            let (host, port) = split_host_port(field.trim_ascii());
+            if !self.ports.is_empty() && !port.and_then(parse_port).is_some_and(|p| self.ports.contains(&p)) {
+                return Some(IpMiss::Port);
+            }
+            host
+        } else {
+            field
+        };
+        let ip = match self.encoding {
+            IpFieldEncoding::Dotted => parse_ip_from_bytes(ip_bytes),
+            IpFieldEncoding::Uint32 => parse_uint32_ip(ip_bytes),
+        };
+        let excluded = match self.encoding {
+            IpFieldEncoding::Dotted => self.exclude_rules.iter().position(|rule| rule.matches(ip_bytes)),
+            IpFieldEncoding::Uint32 => ip.and_then(|ip| self.exclude_rules.iter().position(|rule| rule.contains(ip))),
+        };
+        if let Some(index) = excluded {
+            return Some(IpMiss::Excluded(index));
+        }
+        if self.find_address_rule(ip_bytes).is_none() {
+            return Some(IpMiss::NoRule);
+        }
+        let country = ip.zip(self.countries.as_ref()).and_then(|(ip, countries)| countries.country_code(ip));
+        Some(IpMiss::Country(country))
+    }
+
+    /// `None` when the field does not match, `Some(None)` when it matches without include rules.
+    #[inline]
+    fn find_rule(&self, field: &[u8]) -> Option<Option<usize>> {
+        let ip_bytes = if self.strip_port {
+            let (host, port) = split_host_port(field.trim_ascii());
+            if !self.ports.is_empty() && !port.and_then(parse_port).is_some_and(|p| self.ports.contains(&p)) {
+                return None;
+            }
+            host
+        } else {
+            field
+        };
+        let found = self.find_address_rule(ip_bytes)?;
+        if let Some(countries) = &self.countries {
+            let ip = match self.encoding {
+                IpFieldEncoding::Dotted => parse_ip_from_bytes(ip_bytes),
+                IpFieldEncoding::Uint32 => parse_uint32_ip(ip_bytes),
+            };
+            if !countries.allows(ip) {
+                return None;
             }
         }
-        Ok(IPMatcher { rules })
+        Some(found)
     }
 
-    pub fn matches(&self, ip_bytes: &[u8]) -> bool {
+    #[inline]
+    fn find_address_rule(&self, ip_bytes: &[u8]) -> Option<Option<usize>> {
+        if self.encoding == IpFieldEncoding::Uint32 {
+            return self.find_rule_uint32(ip_bytes);
+        }
+        if self.exclude_rules.iter().any(|rule| rule.matches(ip_bytes)) {
+            return None;
+        }
         if self.rules.is_empty() {
-            return true;
+            return Some(None);
         }
-        self.rules.iter().any(|rule| rule.matches(ip_bytes))
+        self.rules.iter().position(|rule| rule.matches(ip_bytes)).map(Some)
+    }
+
+    fn find_rule_uint32(&self, ip_bytes: &[u8]) -> Option<Option<usize>> {
+        let ip = parse_uint32_ip(ip_bytes);
+        if ip.is_some_and(|ip| self.exclude_rules.iter().any(|rule| rule.contains(ip))) {
+            return None;
+        }
+        if self.rules.is_empty() {
+            return Some(None);
+        }
+        let ip = ip?;
+        self.rules.iter().position(|rule| rule.contains(ip)).map(Some)
     }
 
     pub fn is_none(&self) -> bool {
-        self.rules.is_empty()
+        self.rules.is_empty() && self.exclude_rules.is_empty() && self.ports.is_empty() && self.countries.is_none()
     }
 }
 
+/// Parses the non-empty entries of `inputs`, adding a line to `errors` for each one that fails.
+fn parse_ip_rules(inputs: &[String], text_prefixes: bool, kind: &str, errors: &mut Vec<String>) -> Vec<IPRule> {
+    let mut rules = Vec::new();
+    for (i, input) in inputs.iter().enumerate() {
+        if input.trim().is_empty() {
+            continue;
+        }
+        match IPRule::parse(input, text_prefixes) {
+            Ok(rule) => rules.push(rule),
+            Err(e) => errors.push(format!("  {} #{} {:?}: {:#}", kind, i + 1, input, e)),
+        }
+    }
+    rules
+}
+
 #[derive(Debug)]
 enum DomainRule {
     Exact(Vec<u8>),
     Wildcard(Vec<u8>), // Suffix
+    Pattern(Vec<Vec<u8>>), // `*` elsewhere: literal segments split on `*`
+    Regex(Regex),      // `re:` prefixed pattern
 }
 
 impl DomainRule {
-    fn parse(input: &str) -> Self {
-        if input.starts_with("*.") {
-            DomainRule::Wildcard(input[2..].as_bytes().to_vec())
+    fn parse(input: &str) -> Result<Self> {
+        if let Some(pattern) = input.strip_prefix("re:") {
+            let regex = Regex::new(pattern)
+                .with_context(|| format!("invalid domain regex {:?}", pattern))?;
+            Ok(DomainRule::Regex(regex))
+        } else if let Some(suffix) = input.strip_prefix("*.").filter(|rest| !rest.contains('*')) {
+            Ok(DomainRule::Wildcard(suffix.as_bytes().to_vec()))
+        } else if input.contains('*') {
+            Ok(DomainRule::Pattern(input.split('*').map(|seg| seg.as_bytes().to_vec()).collect()))
         } else {
-            DomainRule::Exact(input.as_bytes().to_vec())
+            Ok(DomainRule::Exact(input.as_bytes().to_vec()))
         }
     }
 
@@ -178,34 +453,290 @@ impl DomainRule {
                 }
                 domain.len() == suffix.len() || domain[domain.len() - suffix.len() - 1] == b'.'
             }
+            DomainRule::Pattern(segments) => matches_segments(domain, segments),
+            DomainRule::Regex(regex) => regex.is_match(domain),
         }
     }
 }
 
+/// Glob match of `domain` against `segments` (the pattern split on `*`): the first segment
+/// anchors the start, the last anchors the end, and the ones between must appear in order.
+/// Each `*` stands for any run of bytes, dots included.
+fn matches_segments(domain: &[u8], segments: &[Vec<u8>]) -> bool {
+    let (Some(first), Some(last)) = (segments.first(), segments.last()) else {
+        return false;
+    };
+    if segments.len() < 2 {
+        return domain == first.as_slice();
+    }
+    if domain.len() < first.len() + last.len() || !domain.starts_with(first) || !domain.ends_with(last) {
+        return false;
+    }
+    let mut rest = &domain[first.len()..domain.len() - last.len()];
+    for segment in &segments[1..segments.len() - 1] {
+        match memchr::memmem::find(rest, segment) {
+            Some(pos) => rest = &rest[pos + segment.len()..],
+            None => return false,
+        }
+    }
+    true
+}
+
+/// Converts the non-ASCII labels of a domain, or of a domain rule, to punycode (`bücher.example`
+/// -> `xn--bcher-kva.example`); ASCII labels, `*` wildcards and dots stay as they are. `None`
+/// when the input is not UTF-8 or a label is not a valid IDN, in which case callers compare
+/// the raw bytes.
+pub fn idn_to_ascii(domain: &[u8]) -> Option<Vec<u8>> {
+    let domain = std::str::from_utf8(domain).ok()?;
+    let mut ace = Vec::with_capacity(domain.len() * 2);
+    for (i, label) in domain.split('.').enumerate() {
+        if i > 0 {
+            ace.push(b'.');
+        }
+        if label.is_ascii() {
+            ace.extend_from_slice(label.as_bytes());
+        } else {
+            ace.extend_from_slice(idna::domain_to_ascii(label).ok()?.as_bytes());
+        }
+    }
+    Some(ace)
+}
+
+/// Above this many exact rules, exact domains are matched through an automaton
+/// instead of the linear rule scan.
+const EXACT_AUTOMATON_THRESHOLD: usize = 64;
+
+/// Above this many `*.suffix` rules, wildcards are matched through a [`SuffixTrie`]
+/// instead of the linear rule scan.
+const WILDCARD_TRIE_THRESHOLD: usize = 16;
+
+/// `*.suffix` rules as a trie of domain labels, last label first (`*.mail.example.com` is
+/// `com` -> `example` -> `mail`), so a domain is checked in one walk over its own labels
+/// however many rules there are.
+#[derive(Debug)]
+struct SuffixTrie {
+    /// Node 0 is the root
+    nodes: Vec<SuffixNode>,
+}
+
+#[derive(Debug, Default)]
+struct SuffixNode {
+    children: HashMap<Box<[u8]>, usize>,
+    /// Rule whose suffix ends at this label; the first in config order when several do
+    rule: Option<usize>,
+}
+
+impl SuffixTrie {
+    fn new() -> Self {
+        SuffixTrie { nodes: vec![SuffixNode::default()] }
+    }
+
+    fn insert(&mut self, suffix: &[u8], id: usize) {
+        let mut node = 0;
+        for label in suffix.rsplit(|&b| b == b'.') {
+            node = match self.nodes[node].children.get(label) {
+                Some(&child) => child,
+                None => {
+                    let child = self.nodes.len();
+                    self.nodes.push(SuffixNode::default());
+                    self.nodes[node].children.insert(label.into(), child);
+                    child
+                }
+            };
+        }
+        let rule = &mut self.nodes[node].rule;
+        *rule = Some(rule.map_or(id, |existing| existing.min(id)));
+    }
+
+    /// The first rule, in config order, that `domain` equals or ends with on a label boundary.
+    fn find(&self, domain: &[u8]) -> Option<usize> {
+        let mut node = 0;
+        let mut found: Option<usize> = None;
+        for label in domain.rsplit(|&b| b == b'.') {
+            match self.nodes[node].children.get(label) {
+                Some(&child) => node = child,
+                None => break,
+            }
+            if let Some(id) = self.nodes[node].rule {
+                found = Some(found.map_or(id, |first| first.min(id)));
+            }
+        }
+        found
+    }
+}
+
 #[derive(Debug)]
 pub struct DomainMatcher {
     rules: Vec<DomainRule>,
+    /// Index in [`DomainMatcher::rule_names`] of each entry of `rules`
+    rule_ids: Vec<usize>,
+    exact_automaton: Option<AhoCorasick>,
+    /// Index in [`DomainMatcher::rule_names`] of each automaton pattern
+    exact_ids: Vec<usize>,
+    wildcard_trie: Option<SuffixTrie>,
+    negated_rules: Vec<DomainRule>,
+    rule_names: Vec<String>,
+    /// The negation rules as written, `!` included, indexed like `negated_rules`
+    negated_rule_names: Vec<String>,
+    /// Set with `matchRegistrableDomain`: rules see the registrable domain of each name
+    registrable: Option<RegistrableDomains>,
 }
 
 impl DomainMatcher {
-    pub fn new(inputs: &[String]) -> Self {
+    /// Entries prefixed with `!` are negation rules (`!*.internal.example.com`, `!re:...`).
+    /// Negation takes precedence: a domain hitting any negation rule never matches, whatever
+    /// the positive rules say. With only negation rules, every other domain matches.
+    pub fn new(inputs: &[String]) -> Result<Self> {
         let mut rules = Vec::new();
+        let mut rule_names = Vec::new();
+        let mut negated_rules = Vec::new();
+        let mut negated_rule_names = Vec::new();
         for input in inputs {
-            if !input.trim().is_empty() {
-                rules.push(DomainRule::parse(input));
+            if input.trim().is_empty() {
+                continue;
+            }
+            if let Some(negated) = input.strip_prefix('!') {
+                negated_rules.push(DomainRule::parse(negated)?);
+                negated_rule_names.push(input.clone());
+            } else {
+                rules.push((rule_names.len(), DomainRule::parse(input)?));
+                rule_names.push(input.clone());
             }
         }
-        DomainMatcher { rules }
+
+        let exact_count = rules.iter().filter(|(_, r)| matches!(r, DomainRule::Exact(_))).count();
+        let mut exact_automaton = None;
+        let mut exact_ids = Vec::new();
+        if exact_count > EXACT_AUTOMATON_THRESHOLD {
+            let (exact, rest): (Vec<_>, Vec<_>) = rules
+                .into_iter()
+                .partition(|(_, r)| matches!(r, DomainRule::Exact(_)));
+            let mut patterns = Vec::with_capacity(exact.len());
+            for (id, rule) in exact {
+                if let DomainRule::Exact(target) = rule {
+                    exact_ids.push(id);
+                    patterns.push(target);
+                }
+            }
+            // Anchored + leftmost-longest: the longest pattern starting at
+            // offset 0 is returned, so a full-field hit is never shadowed by
+            // a shorter pattern that is merely a prefix of the field.
+            let automaton = AhoCorasickBuilder::new()
+                .match_kind(MatchKind::LeftmostLongest)
+                .start_kind(StartKind::Anchored)
+                .build(patterns)
+                .context("failed to build exact domain automaton")?;
+            exact_automaton = Some(automaton);
+            rules = rest;
+        }
+
+        let wildcard_count = rules.iter().filter(|(_, r)| matches!(r, DomainRule::Wildcard(_))).count();
+        let mut wildcard_trie = None;
+        if wildcard_count > WILDCARD_TRIE_THRESHOLD {
+            let (wildcards, rest): (Vec<_>, Vec<_>) = rules
+                .into_iter()
+                .partition(|(_, r)| matches!(r, DomainRule::Wildcard(_)));
+            let mut trie = SuffixTrie::new();
+            for (id, rule) in wildcards {
+                if let DomainRule::Wildcard(suffix) = rule {
+                    trie.insert(&suffix, id);
+                }
+            }
+            wildcard_trie = Some(trie);
+            rules = rest;
+        }
+
+        let (rule_ids, rules) = rules.into_iter().unzip();
+        Ok(DomainMatcher {
+            rules,
+            rule_ids,
+            exact_automaton,
+            exact_ids,
+            wildcard_trie,
+            negated_rules,
+            rule_names,
+            negated_rule_names,
+            registrable: None,
+        })
+    }
+
+    /// Matches the rules, negations included, against the registrable domain (eTLD+1) of each
+    /// name instead of the full name, so `*.foo.co.uk` and `foo.co.uk` hit every name under
+    /// `foo.co.uk` alike.
+    pub fn with_registrable_domains(mut self, domains: RegistrableDomains) -> Self {
+        self.registrable = Some(domains);
+        self
+    }
+
+    /// The part of `domain` the rules are checked against.
+    fn match_key<'a>(&self, domain: &'a [u8]) -> &'a [u8] {
+        match &self.registrable {
+            Some(domains) => domains.registrable(domain),
+            None => domain,
+        }
     }
 
     pub fn matches(&self, domain: &[u8]) -> bool {
-        if self.rules.is_empty() {
+        let domain = self.match_key(domain);
+        if self.negated_rules.iter().any(|rule| rule.matches(domain)) {
+            return false;
+        }
+        if self.rules.is_empty() && self.exact_automaton.is_none() && self.wildcard_trie.is_none() {
+            return true;
+        }
+        if let Some(automaton) = &self.exact_automaton {
+            let input = Input::new(domain).anchored(Anchored::Yes);
+            if automaton.find(input).is_some_and(|m| m.end() == domain.len()) {
+                return true;
+            }
+        }
+        if self.wildcard_trie.as_ref().is_some_and(|trie| trie.find(domain).is_some()) {
             return true;
         }
         self.rules.iter().any(|rule| rule.matches(domain))
     }
 
+    /// Like [`DomainMatcher::matches`], but names the rule that matched: the index in
+    /// [`DomainMatcher::rule_names`] of the first positive rule, in config order, that the
+    /// domain satisfies. `None` when the domain does not match or there are no positive rules.
+    pub fn matched_rule(&self, domain: &[u8]) -> Option<usize> {
+        let domain = self.match_key(domain);
+        if self.negated_rules.iter().any(|rule| rule.matches(domain)) {
+            return None;
+        }
+        let exact = self.exact_automaton.as_ref().and_then(|automaton| {
+            let input = Input::new(domain).anchored(Anchored::Yes);
+            automaton
+                .find(input)
+                .filter(|m| m.end() == domain.len())
+                .map(|m| self.exact_ids[m.pattern().as_usize()])
+        });
+        let wildcard = self.wildcard_trie.as_ref().and_then(|trie| trie.find(domain));
+        let scanned = self
+            .rules
+            .iter()
+            .zip(&self.rule_ids)
+            .find(|(rule, _)| rule.matches(domain))
+            .map(|(_, &id)| id);
+        [exact, wildcard, scanned].into_iter().flatten().min()
+    }
+
+    /// The positive rules as written in the config, negations left out.
+    pub fn rule_names(&self) -> &[String] {
+        &self.rule_names
+    }
+
+    /// The first negation rule, in config order, that `domain` hits, as written in the config.
+    pub fn negated_by(&self, domain: &[u8]) -> Option<&str> {
+        let domain = self.match_key(domain);
+        let index = self.negated_rules.iter().position(|rule| rule.matches(domain))?;
+        Some(&self.negated_rule_names[index])
+    }
+
     pub fn is_none(&self) -> bool {
         self.rules.is_empty()
+            && self.exact_automaton.is_none()
+            && self.wildcard_trie.is_none()
+            && self.negated_rules.is_empty()
     }
 }
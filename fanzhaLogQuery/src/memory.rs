@@ -0,0 +1,57 @@
+use std::fs;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+/// How often the sampler reads the resident set size.
+const SAMPLE_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Background thread recording the largest resident set size (RSS) of the process while it
+/// runs (`reportPeakMemory`).
+pub struct MemorySampler {
+    stop: Arc<AtomicBool>,
+    handle: JoinHandle<u64>,
+}
+
+impl MemorySampler {
+    /// `None` where the resident size cannot be read, i.e. outside Linux.
+    pub fn start() -> Option<Self> {
+        let first = resident_bytes()?;
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread_stop = Arc::clone(&stop);
+        let handle = thread::spawn(move || {
+            let mut peak = first;
+            while !thread_stop.load(Ordering::Relaxed) {
+                thread::park_timeout(SAMPLE_INTERVAL);
+                if let Some(rss) = resident_bytes() {
+                    peak = peak.max(rss);
+                }
+            }
+            peak
+        });
+        Some(MemorySampler { stop, handle })
+    }
+
+    /// Stops the sampler and returns the peak in bytes. The kernel's own high-water mark
+    /// (`VmHWM`) is taken into account where available, so short spikes between two samples
+    /// are not missed.
+    pub fn finish(self) -> u64 {
+        self.stop.store(true, Ordering::Relaxed);
+        self.handle.thread().unpark();
+        let sampled = self.handle.join().unwrap_or(0);
+        sampled.max(proc_status_bytes("VmHWM").unwrap_or(0))
+    }
+}
+
+fn resident_bytes() -> Option<u64> {
+    proc_status_bytes("VmRSS")
+}
+
+/// Reads a `kB` value such as `VmRSS:   123456 kB` from `/proc/self/status`.
+fn proc_status_bytes(key: &str) -> Option<u64> {
+    let status = fs::read_to_string("/proc/self/status").ok()?;
+    let value = status.lines().find_map(|line| line.strip_prefix(key)?.strip_prefix(':'))?;
+    let kb: u64 = value.trim().strip_suffix("kB")?.trim().parse().ok()?;
+    Some(kb * 1024)
+}
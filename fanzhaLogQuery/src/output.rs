@@ -0,0 +1,720 @@
+use crate::columnar::{encode_row, ParquetSchema, ParquetWriter};
+use crate::encryption::{EncryptWriter, OutputKey};
+use crate::processor::{split_fields, unquote, MatchedLine};
+use chrono::{DateTime, SecondsFormat, Utc};
+use flate2::write::GzEncoder;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::borrow::Cow;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// How matched lines are written to the result file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputFormat {
+    /// The original log line, unchanged
+    #[default]
+    Raw,
+    /// One JSON object per line: `{"ip": ..., "domain": ..., "raw": ...}`
+    Jsonl,
+    /// A Parquet file with `ip`, `domain` and `raw` plus the typed `parquetColumns`; see
+    /// [`crate::columnar`]
+    Parquet,
+}
+
+/// Line ending written after each result line (`lineTerminator`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LineTerminator {
+    #[default]
+    Lf,
+    /// `\r\n`, for Windows tools
+    Crlf,
+}
+
+impl LineTerminator {
+    pub fn as_bytes(self) -> &'static [u8] {
+        match self {
+            LineTerminator::Lf => b"\n",
+            LineTerminator::Crlf => b"\r\n",
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct JsonRecord<'a> {
+    ip: Option<Cow<'a, str>>,
+    domain: Option<Cow<'a, str>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    resolved_ip: Option<Cow<'a, str>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    source_file: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    source_timestamp: Option<&'a str>,
+    raw: Cow<'a, str>,
+}
+
+/// The input a matched line was read from, written in front of it with `prependSourceFile`.
+#[derive(Debug, Clone, Copy)]
+pub struct SourceFile<'a> {
+    pub path: &'a str,
+    /// Time taken from the file name or directory, with `prependSourceTimestamp`
+    pub timestamp: Option<&'a str>,
+}
+
+/// How each matched line is rendered.
+#[derive(Debug, Clone, Default)]
+pub struct RecordOptions {
+    pub format: OutputFormat,
+    /// Fields may be double-quoted (`quotedFields`); quotes are kept in the output
+    pub quoted: bool,
+    /// Field holding an epoch timestamp to rewrite as RFC 3339 (UTC)
+    pub timestamp_field: Option<usize>,
+    /// Lowercase the domain field (`normalizeDomainCase`)
+    pub lowercase_domain: bool,
+    /// Fields to keep, in output order; empty keeps the whole line
+    pub output_fields: Vec<usize>,
+    /// Fields copied into the `parquetColumns`, in column order
+    pub parquet_fields: Vec<usize>,
+    pub line_terminator: LineTerminator,
+}
+
+/// Appends one matched line, terminated by the configured line ending, to `buf` in the requested format. In raw
+/// format `source` is prepended as `path<delim>[timestamp<delim>]`; in JSONL it becomes the
+/// `source_file` / `source_timestamp` keys. In Parquet format a row is appended instead, without
+/// line ending.
+pub fn write_record(buf: &mut Vec<u8>, matched: &MatchedLine, source: Option<&SourceFile>, options: &RecordOptions) {
+    // First, while the domain still points into the raw line
+    let mut line = match options.lowercase_domain {
+        true => lowercase_domain(matched.raw, matched.domain),
+        false => Cow::Borrowed(matched.raw),
+    };
+    if let Some(index) = options.timestamp_field {
+        if let Cow::Owned(rewritten) = normalize_timestamp(&line, matched.delimiter, options.quoted, index) {
+            line = Cow::Owned(rewritten);
+        }
+    }
+    if !options.output_fields.is_empty() {
+        line = Cow::Owned(project_fields(&line, matched.delimiter, options.quoted, &options.output_fields));
+    }
+    match options.format {
+        OutputFormat::Raw => {
+            if let Some(source) = source {
+                buf.extend_from_slice(source.path.as_bytes());
+                buf.push(matched.delimiter);
+                if let Some(timestamp) = source.timestamp {
+                    buf.extend_from_slice(timestamp.as_bytes());
+                    buf.push(matched.delimiter);
+                }
+            }
+            buf.extend_from_slice(&line);
+        }
+        OutputFormat::Parquet => {
+            let domain = match options.lowercase_domain {
+                true => matched.domain.map(|domain| Cow::Owned(domain.to_ascii_lowercase())),
+                false => matched.domain.map(Cow::Borrowed),
+            };
+            encode_row(buf, [matched.ip, domain.as_deref(), Some(&line)], matched.raw, matched.delimiter, options);
+            return;
+        }
+        OutputFormat::Jsonl => {
+            let mut domain = matched.domain.map(String::from_utf8_lossy);
+            if options.lowercase_domain && domain.as_deref().is_some_and(|d| d.bytes().any(|b| b.is_ascii_uppercase())) {
+                domain = domain.map(|d| Cow::Owned(d.to_ascii_lowercase()));
+            }
+            let record = JsonRecord {
+                ip: matched.ip.map(String::from_utf8_lossy),
+                domain,
+                resolved_ip: matched.resolved_ip.map(String::from_utf8_lossy),
+                source_file: source.map(|s| s.path),
+                source_timestamp: source.and_then(|s| s.timestamp),
+                raw: String::from_utf8_lossy(&line),
+            };
+            // Serializing borrowed strings into a Vec cannot fail
+            serde_json::to_writer(&mut *buf, &record).expect("serialize JSON record");
+        }
+    }
+    buf.extend_from_slice(options.line_terminator.as_bytes());
+}
+
+/// Joins the selected fields with the delimiter; a field past the end of the line is emitted
+/// empty. Ascending selections are copied in a single pass that stops at the last wanted field.
+fn project_fields(line: &[u8], delimiter: u8, quoted: bool, selected: &[usize]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(line.len());
+    if selected.is_sorted_by(|a, b| a < b) {
+        let mut wanted = selected.iter().enumerate().peekable();
+        for (idx, field) in split_fields(line, delimiter, quoted).enumerate() {
+            let Some(&(pos, &next)) = wanted.peek() else { break };
+            if idx == next {
+                if pos > 0 {
+                    out.push(delimiter);
+                }
+                out.extend_from_slice(field);
+                wanted.next();
+            }
+        }
+        // Fields past the end of the line stay empty
+        for (pos, _) in wanted {
+            if pos > 0 {
+                out.push(delimiter);
+            }
+        }
+        return out;
+    }
+
+    let max = selected.iter().copied().max().unwrap_or(0);
+    let found: Vec<&[u8]> = split_fields(line, delimiter, quoted).take(max + 1).collect();
+    for (i, &index) in selected.iter().enumerate() {
+        if i > 0 {
+            out.push(delimiter);
+        }
+        out.extend_from_slice(found.get(index).copied().unwrap_or_default());
+    }
+    out
+}
+
+/// Lowercases the ASCII letters of `domain`, a subslice of `line`. The line is borrowed as it
+/// is, without copying, when the domain is already lowercase, was not located, or is not part
+/// of the line (a `logFormat: json` value that had escapes).
+fn lowercase_domain<'a>(line: &'a [u8], domain: Option<&[u8]>) -> Cow<'a, [u8]> {
+    let Some(domain) = domain.filter(|domain| domain.iter().any(u8::is_ascii_uppercase)) else {
+        return Cow::Borrowed(line);
+    };
+    let (bounds, field) = (line.as_ptr_range(), domain.as_ptr_range());
+    if field.start < bounds.start || field.end > bounds.end {
+        return Cow::Borrowed(line);
+    }
+    let start = field.start as usize - bounds.start as usize;
+    let mut rewritten = line.to_vec();
+    rewritten[start..start + domain.len()].make_ascii_lowercase();
+    Cow::Owned(rewritten)
+}
+
+/// Rewrites field `index` from epoch seconds (up to 10 digits) or milliseconds (more digits)
+/// to RFC 3339 in UTC. The line is left untouched when the field is missing or not an epoch.
+/// A quoted epoch keeps its quotes.
+fn normalize_timestamp(line: &[u8], delimiter: u8, quoted: bool, index: usize) -> Cow<'_, [u8]> {
+    let Some(field) = split_fields(line, delimiter, quoted).nth(index) else {
+        return Cow::Borrowed(line);
+    };
+    let value = if quoted { unquote(field) } else { field };
+    let Some(formatted) = epoch_to_rfc3339(value) else {
+        return Cow::Borrowed(line);
+    };
+    // Every field is a subslice of `line`, so its offset locates it
+    let start = value.as_ptr() as usize - line.as_ptr() as usize;
+    let end = start + value.len();
+
+    let mut rewritten = Vec::with_capacity(line.len() + formatted.len());
+    rewritten.extend_from_slice(&line[..start]);
+    rewritten.extend_from_slice(formatted.as_bytes());
+    rewritten.extend_from_slice(&line[end..]);
+    Cow::Owned(rewritten)
+}
+
+fn epoch_to_rfc3339(field: &[u8]) -> Option<String> {
+    if field.is_empty() || field.len() > 19 || !field.iter().all(u8::is_ascii_digit) {
+        return None;
+    }
+    let value: i64 = std::str::from_utf8(field).ok()?.parse().ok()?;
+    if field.len() <= 10 {
+        let ts = DateTime::<Utc>::from_timestamp(value, 0)?;
+        Some(ts.to_rfc3339_opts(SecondsFormat::Secs, true))
+    } else {
+        let ts = DateTime::<Utc>::from_timestamp_millis(value)?;
+        Some(ts.to_rfc3339_opts(SecondsFormat::Millis, true))
+    }
+}
+
+/// Compression applied to the result file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputCompression {
+    #[default]
+    None,
+    Gzip,
+}
+
+impl OutputCompression {
+    /// Suffix appended to the result file name.
+    pub fn extension(self) -> &'static str {
+        match self {
+            OutputCompression::None => "",
+            OutputCompression::Gzip => ".gz",
+        }
+    }
+}
+
+/// What to do when a result file of an earlier run is already in the result directory
+/// (`onExistingOutput`). Covers the manifest, per-file report and malformed-line file too.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ExistingOutput {
+    /// Replace the earlier results
+    #[default]
+    Overwrite,
+    /// Refuse to start
+    Error,
+    /// Put the start time of the run into every file name, so nothing is replaced
+    Timestamp,
+}
+
+/// Bytes buffered in front of each result file or stdout (1 MB).
+pub const DEFAULT_WRITER_BUFFER_BYTES: usize = 1024 * 1024;
+
+/// How result bytes are stored: compressed, then encrypted with `outputEncryption`.
+#[derive(Debug, Clone)]
+pub struct OutputEncoding {
+    pub compression: OutputCompression,
+    pub encryption: Option<Arc<OutputKey>>,
+    /// `writerBufferBytes`
+    pub buffer_bytes: usize,
+    /// Write Parquet files instead of text (`outputFormat: parquet`); only for result files
+    /// opened whole, not split into parts, appended to or written to stdout
+    pub parquet: Option<Arc<ParquetSchema>>,
+}
+
+impl Default for OutputEncoding {
+    fn default() -> Self {
+        OutputCompression::default().into()
+    }
+}
+
+impl From<OutputCompression> for OutputEncoding {
+    fn from(compression: OutputCompression) -> Self {
+        OutputEncoding { compression, encryption: None, buffer_bytes: DEFAULT_WRITER_BUFFER_BYTES, parquet: None }
+    }
+}
+
+type Target = Box<dyn Write + Send>;
+
+/// SHA-256 of a finished result file, as `sha256sum` prints it.
+#[derive(Debug, Clone, Serialize)]
+pub struct FileChecksum {
+    pub path: PathBuf,
+    pub sha256: String,
+}
+
+/// Passes writes on to `inner`, hashing the bytes as they go out. Sitting below compression
+/// and encryption, it sees exactly the bytes of the file, so its digest needs no second
+/// pass over the file.
+pub struct HashingWriter<W> {
+    inner: W,
+    /// `None` where there is no file of our own to vouch for (stdout, appending)
+    hasher: Option<Sha256>,
+}
+
+impl<W: Write> HashingWriter<W> {
+    pub fn new(inner: W, hash: bool) -> Self {
+        HashingWriter { inner, hasher: hash.then(Sha256::new) }
+    }
+
+    pub fn get_ref(&self) -> &W {
+        &self.inner
+    }
+
+    /// The inner writer and the hex digest of everything written to it.
+    pub fn into_parts(self) -> (W, Option<String>) {
+        (self.inner, self.hasher.map(|hasher| format!("{:x}", hasher.finalize())))
+    }
+}
+
+impl<W: Write> Write for HashingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        if let Some(hasher) = &mut self.hasher {
+            hasher.update(&buf[..written]);
+        }
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// The file or stdout, behind encryption when enabled.
+pub enum Sink {
+    Direct(HashingWriter<Target>),
+    Encrypted(EncryptWriter<HashingWriter<Target>>),
+}
+
+impl Sink {
+    /// Flushes the target; returns the digest of a created file.
+    fn finish(self) -> io::Result<Option<String>> {
+        let mut target = match self {
+            Sink::Direct(target) => target,
+            Sink::Encrypted(writer) => writer.finish()?,
+        };
+        target.flush()?;
+        Ok(target.into_parts().1)
+    }
+}
+
+impl Write for Sink {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Sink::Direct(target) => target.write(buf),
+            Sink::Encrypted(writer) => writer.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Sink::Direct(target) => target.flush(),
+            Sink::Encrypted(writer) => writer.flush(),
+        }
+    }
+}
+
+/// Buffered result writer (file or stdout), optionally gzip-compressing and encrypting on the fly.
+pub enum OutputWriter {
+    Plain(BufWriter<Sink>),
+    Gzip(GzEncoder<BufWriter<Sink>>),
+}
+
+impl OutputWriter {
+    pub fn create(path: &Path, encoding: &OutputEncoding) -> io::Result<Self> {
+        let file = File::create(path)?;
+        Self::wrap(Box::new(file), encoding, true)
+    }
+
+    /// Opens `path` for appending, creating it if needed. Gzip output is added as a new
+    /// gzip member, which gzip readers (`zcat`, [`flate2::read::MultiGzDecoder`]) read through;
+    /// encrypted output as a new segment, which `--decrypt` reads through. The file is not
+    /// hashed, since only the added bytes pass through here.
+    pub fn append(path: &Path, encoding: &OutputEncoding) -> io::Result<Self> {
+        let file = OpenOptions::new().append(true).create(true).open(path)?;
+        Self::wrap(Box::new(file), encoding, false)
+    }
+
+    pub fn stdout(encoding: &OutputEncoding) -> io::Result<Self> {
+        Self::wrap(Box::new(io::stdout()), encoding, false)
+    }
+
+    fn wrap(target: Target, encoding: &OutputEncoding, hash: bool) -> io::Result<Self> {
+        let target = HashingWriter::new(target, hash);
+        let sink = match &encoding.encryption {
+            Some(key) => Sink::Encrypted(EncryptWriter::new(target, key)?),
+            None => Sink::Direct(target),
+        };
+        let writer = BufWriter::with_capacity(encoding.buffer_bytes, sink);
+        Ok(match encoding.compression {
+            OutputCompression::None => OutputWriter::Plain(writer),
+            OutputCompression::Gzip => OutputWriter::Gzip(GzEncoder::new(writer, flate2::Compression::default())),
+        })
+    }
+
+    /// Flushes buffered data and writes the gzip trailer and the last encrypted chunk. Returns
+    /// the SHA-256 of the file when it was created rather than appended to.
+    pub fn finish(self) -> io::Result<Option<String>> {
+        let writer = match self {
+            OutputWriter::Plain(writer) => writer,
+            OutputWriter::Gzip(encoder) => encoder.finish()?,
+        };
+        writer.into_inner().map_err(|e| e.into_error())?.finish()
+    }
+}
+
+impl Write for OutputWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            OutputWriter::Plain(writer) => writer.write(buf),
+            OutputWriter::Gzip(encoder) => encoder.write(buf),
+        }
+    }
+
+    fn write_all(&mut self, buf: &[u8]) -> io::Result<()> {
+        match self {
+            OutputWriter::Plain(writer) => writer.write_all(buf),
+            OutputWriter::Gzip(encoder) => encoder.write_all(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            OutputWriter::Plain(writer) => writer.flush(),
+            OutputWriter::Gzip(encoder) => encoder.flush(),
+        }
+    }
+}
+
+/// Totals reported by a [`ResultWriter`] once finished.
+#[derive(Debug, Clone, Default)]
+pub struct WriteSummary {
+    /// Uncompressed bytes written; the file size for Parquet
+    pub bytes: u64,
+    /// Number of files written; 0 for stdout
+    pub parts: usize,
+    /// One per file created, in the order they were closed
+    pub checksums: Vec<FileChecksum>,
+}
+
+impl WriteSummary {
+    fn add(&mut self, other: WriteSummary) {
+        self.bytes += other.bytes;
+        self.parts += other.parts;
+        self.checksums.extend(other.checksums);
+    }
+}
+
+/// Writes result chunks to stdout or a result file, rolling over to numbered part files
+/// (`matched_x_logs.001.txt`, `.002.txt`, ...) when `max_bytes` is set. Chunks always hold
+/// whole lines, so parts split on line boundaries; a part is closed after the chunk that
+/// takes it past the limit, and the next one is only created once there is more to write.
+pub struct ResultWriter {
+    path: Option<PathBuf>,
+    encoding: OutputEncoding,
+    max_bytes: Option<u64>,
+    current: Option<OutputWriter>,
+    /// File `current` or `parquet` writes to; `None` for stdout
+    current_path: Option<PathBuf>,
+    /// Set instead of `current` with [`OutputEncoding::parquet`]
+    parquet: Option<Box<ParquetWriter>>,
+    part_bytes: u64,
+    /// Number of the part file created on the next rollover
+    next_part: usize,
+    summary: WriteSummary,
+}
+
+impl ResultWriter {
+    /// `path` of `None` writes to stdout, where splitting does not apply. With `append` an
+    /// existing result file is added to instead of replaced; when splitting, existing parts are
+    /// left untouched and numbering continues after the last one.
+    pub fn open(path: Option<PathBuf>, encoding: OutputEncoding, max_bytes: Option<u64>, append: bool) -> io::Result<Self> {
+        if let (Some(schema), Some(file)) = (&encoding.parquet, &path) {
+            let parquet = ParquetWriter::create(file, Arc::clone(schema))?;
+            return Ok(Self {
+                current_path: path.clone(),
+                path,
+                encoding,
+                max_bytes: None,
+                current: None,
+                parquet: Some(Box::new(parquet)),
+                part_bytes: 0,
+                next_part: 1,
+                summary: WriteSummary { parts: 1, ..WriteSummary::default() },
+            });
+        }
+        let mut first_part = 1;
+        let (current, current_path) = match &path {
+            None => (OutputWriter::stdout(&encoding)?, None),
+            Some(path) if max_bytes.is_some() => {
+                if append {
+                    while part_path(path, first_part).exists() {
+                        first_part += 1;
+                    }
+                }
+                let part = part_path(path, first_part);
+                (OutputWriter::create(&part, &encoding)?, Some(part))
+            }
+            Some(path) if append => (OutputWriter::append(path, &encoding)?, Some(path.clone())),
+            Some(path) => (OutputWriter::create(path, &encoding)?, Some(path.clone())),
+        };
+        let parts = usize::from(current_path.is_some());
+        Ok(Self {
+            path,
+            encoding,
+            max_bytes,
+            current: Some(current),
+            current_path,
+            parquet: None,
+            part_bytes: 0,
+            next_part: first_part + 1,
+            summary: WriteSummary { parts, ..WriteSummary::default() },
+        })
+    }
+
+    pub fn write_chunk(&mut self, chunk: &[u8]) -> io::Result<()> {
+        if let Some(parquet) = &mut self.parquet {
+            return parquet.write_rows(chunk);
+        }
+        let current = match &mut self.current {
+            Some(current) => current,
+            None => {
+                // Only reachable once a part has been closed, so there is a file path
+                let path = self.path.as_ref().expect("rolled-over output has a path");
+                let part = part_path(path, self.next_part);
+                self.next_part += 1;
+                self.summary.parts += 1;
+                let writer = OutputWriter::create(&part, &self.encoding)?;
+                self.current_path = Some(part);
+                self.current.insert(writer)
+            }
+        };
+        current.write_all(chunk)?;
+        self.part_bytes += chunk.len() as u64;
+        self.summary.bytes += chunk.len() as u64;
+
+        if self.path.is_some() && self.max_bytes.is_some_and(|max| self.part_bytes >= max) {
+            if let Some(full) = self.current.take() {
+                let digest = full.finish()?;
+                self.add_checksum(digest);
+            }
+            self.part_bytes = 0;
+        }
+        Ok(())
+    }
+
+    /// Pushes buffered output to the file or stdout. Parquet rows are only written out with
+    /// their row group, so this is a no-op for Parquet.
+    pub fn flush(&mut self) -> io::Result<()> {
+        match &mut self.current {
+            Some(current) => current.flush(),
+            None => Ok(()),
+        }
+    }
+
+    pub fn finish(mut self) -> io::Result<WriteSummary> {
+        if let Some(current) = self.current.take() {
+            let digest = current.finish()?;
+            self.add_checksum(digest);
+        }
+        if let Some(parquet) = self.parquet.take() {
+            let (bytes, digest) = parquet.finish()?;
+            self.summary.bytes = bytes;
+            self.add_checksum(Some(digest));
+        }
+        Ok(self.summary)
+    }
+
+    fn add_checksum(&mut self, digest: Option<String>) {
+        if let (Some(sha256), Some(path)) = (digest, &self.current_path) {
+            self.summary.checksums.push(FileChecksum { path: path.clone(), sha256 });
+        }
+    }
+}
+
+/// Result files of one task split by domain rule (`splitByDomainRule`). Slot `i` holds the
+/// matches of rule `i`; each file is created on its first chunk, so rules without matches
+/// leave no file behind.
+pub struct PartitionedWriter {
+    base: PathBuf,
+    suffixes: Vec<String>,
+    encoding: OutputEncoding,
+    max_bytes: Option<u64>,
+    append: bool,
+    writers: Vec<Option<ResultWriter>>,
+}
+
+impl PartitionedWriter {
+    /// `names` gives one file name suffix per slot, sanitized here; see [`rule_file_suffix`].
+    pub fn new(base: PathBuf, names: &[String], encoding: OutputEncoding, max_bytes: Option<u64>, append: bool) -> Self {
+        let mut suffixes: Vec<String> = Vec::with_capacity(names.len());
+        for (i, name) in names.iter().enumerate() {
+            let mut suffix = rule_file_suffix(name);
+            // Distinct rules may sanitize to the same name
+            if suffixes.contains(&suffix) {
+                suffix = format!("{}_{}", suffix, i);
+            }
+            suffixes.push(suffix);
+        }
+        Self {
+            base,
+            writers: (0..suffixes.len()).map(|_| None).collect(),
+            suffixes,
+            encoding,
+            max_bytes,
+            append,
+        }
+    }
+
+    pub fn write_chunk(&mut self, slot: usize, chunk: &[u8]) -> io::Result<()> {
+        let writer = match &mut self.writers[slot] {
+            Some(writer) => writer,
+            empty => {
+                let path = suffixed_path(&self.base, &self.suffixes[slot]);
+                empty.insert(ResultWriter::open(Some(path), self.encoding.clone(), self.max_bytes, self.append)?)
+            }
+        };
+        writer.write_chunk(chunk)
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.writers.iter_mut().flatten().try_for_each(ResultWriter::flush)
+    }
+
+    /// Totals over all files, and the paths of the files created, in slot order.
+    pub fn finish(self) -> io::Result<(WriteSummary, Vec<PathBuf>)> {
+        let mut total = WriteSummary::default();
+        let mut paths = Vec::new();
+        for (writer, suffix) in self.writers.into_iter().zip(&self.suffixes) {
+            if let Some(writer) = writer {
+                total.add(writer.finish()?);
+                paths.push(suffixed_path(&self.base, suffix));
+            }
+        }
+        Ok((total, paths))
+    }
+}
+
+/// Where the writer thread sends result chunks: one output, one file per domain rule, or
+/// one output per query profile (`profiles`).
+pub enum ResultSink {
+    Single(Box<ResultWriter>),
+    Partitioned(PartitionedWriter),
+    Profiles(Vec<ResultWriter>),
+}
+
+impl ResultSink {
+    /// `slot` picks the file of a [`PartitionedWriter`] or the profile's output, and is
+    /// ignored otherwise.
+    pub fn write_chunk(&mut self, slot: usize, chunk: &[u8]) -> io::Result<()> {
+        match self {
+            ResultSink::Single(writer) => writer.write_chunk(chunk),
+            ResultSink::Partitioned(writer) => writer.write_chunk(slot, chunk),
+            ResultSink::Profiles(writers) => writers[slot].write_chunk(chunk),
+        }
+    }
+
+    /// Pushes buffered output of every open file to disk (`workerFlushIntervalMs`).
+    pub fn flush(&mut self) -> io::Result<()> {
+        match self {
+            ResultSink::Single(writer) => writer.flush(),
+            ResultSink::Partitioned(writer) => writer.flush(),
+            ResultSink::Profiles(writers) => writers.iter_mut().try_for_each(ResultWriter::flush),
+        }
+    }
+
+    /// Totals, plus the per-rule files created when partitioned.
+    pub fn finish(self) -> io::Result<(WriteSummary, Vec<PathBuf>)> {
+        match self {
+            ResultSink::Single(writer) => Ok((writer.finish()?, Vec::new())),
+            ResultSink::Partitioned(writer) => writer.finish(),
+            ResultSink::Profiles(writers) => {
+                let mut total = WriteSummary::default();
+                for writer in writers {
+                    total.add(writer.finish()?);
+                }
+                Ok((total, Vec::new()))
+            }
+        }
+    }
+}
+
+/// Turns a domain rule into a file name part: `*` becomes `wildcard`, and anything other
+/// than ASCII letters, digits, `-` and `_` becomes `_` (`*.evil.com` -> `wildcard_evil_com`).
+/// Dots go too, since part numbering splits the file name at its first dot.
+pub fn rule_file_suffix(rule: &str) -> String {
+    rule.replace('*', "wildcard")
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || matches!(c, '-' | '_') { c } else { '_' })
+        .collect()
+}
+
+/// Appends `_suffix` to the file stem: `matched_logs.txt.gz` -> `matched_logs_suffix.txt.gz`.
+fn suffixed_path(path: &Path, suffix: &str) -> PathBuf {
+    let name = path.file_name().and_then(|n| n.to_str()).unwrap_or_default();
+    let (stem, extensions) = name.split_at(name.find('.').unwrap_or(name.len()));
+    path.with_file_name(format!("{}_{}{}", stem, suffix, extensions))
+}
+
+/// Inserts the part number before the extensions: `matched_logs.txt.gz` -> `matched_logs.003.txt.gz`.
+fn part_path(path: &Path, part: usize) -> PathBuf {
+    let name = path.file_name().and_then(|n| n.to_str()).unwrap_or_default();
+    let (stem, extensions) = name.split_at(name.find('.').unwrap_or(name.len()));
+    path.with_file_name(format!("{}.{:03}{}", stem, part, extensions))
+}
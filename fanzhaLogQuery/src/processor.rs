@@ -1,202 +1,1290 @@
-use crate::matcher::{DomainMatcher, IPMatcher};
+use crate::dedupe::line_hash;
+use crate::matcher::{idn_to_ascii, DomainMatcher, IPMatcher, IpMiss};
 use anyhow::Result;
+use bzip2::bufread::MultiBzDecoder as BufMultiBzDecoder;
+use bzip2::read::MultiBzDecoder;
+use flate2::bufread::MultiGzDecoder as BufMultiGzDecoder;
 use flate2::read::MultiGzDecoder;
-use memchr::memchr_iter;
-use std::fs::File;
-use std::io::{BufRead, BufReader};
+use memchr::{memchr, memchr_iter, memmem};
+use serde::de::{self, Deserializer as _, IgnoredAny, MapAccess, SeqAccess, Visitor};
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::fmt;
+use std::io::{self, BufRead, BufReader, Read};
+use std::ops::{AddAssign, Range, RangeInclusive};
 use std::path::Path;
+use zstd::stream::read::Decoder as ZstdDecoder;
 
-// Constants for field indices (0-based)
-const AGGREGATED_LOG_IP_INDEX: usize = 0;
-const AGGREGATED_LOG_DOMAIN_INDEX: usize = 1;
-const NATIVE_LOG_IP_INDEX: usize = 4;
-const NATIVE_LOG_DOMAIN_INDEX: usize = 7;
+/// Buffer between a decoder and the line splitter.
+const LINE_BUFFER_CAPACITY: usize = 1024 * 1024;
+
+// Default field indices (0-based), used when the config does not override them
+pub const AGGREGATED_LOG_IP_INDEX: usize = 0;
+pub const AGGREGATED_LOG_DOMAIN_INDEX: usize = 1;
+pub const NATIVE_LOG_IP_INDEX: usize = 4;
+pub const NATIVE_LOG_DOMAIN_INDEX: usize = 7;
+pub const NATIVE_LOG_RESOLVED_IP_INDEX: usize = 9;
+pub const DEFAULT_FIELD_DELIMITER: u8 = b'|';
+/// Delimiters `autoDetectDelimiter` chooses from, along with `fieldDelimiter`.
+pub const DELIMITER_CANDIDATES: [u8; 4] = [b'|', b'\t', b' ', b','];
+/// Lines at the start of a file that `autoDetectDelimiter` looks at.
+pub const DELIMITER_SAMPLE_LINES: usize = 20;
+
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+/// gzip header flag: an extra field (`XLEN` plus subfields) follows the fixed 10 bytes
+const GZIP_FEXTRA: u8 = 0x04;
+/// Output a split candidate has to decode to before [`gzip_member_ranges`] takes it
+const GZIP_PROBE_BYTES: u64 = 64 * 1024;
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+/// `BZh` followed by the block size digit `1`-`9`
+const BZIP2_MAGIC: [u8; 3] = *b"BZh";
+
+/// Positions of the IP and domain fields within a log line, and the byte separating fields.
+#[derive(Debug, Clone)]
+pub struct FieldLayout {
+    pub ip_index: usize,
+    pub domain_index: usize,
+    /// Field holding the answer IP; the resolved-IP filter is skipped for layouts without one
+    pub resolved_ip_index: Option<usize>,
+    pub delimiter: u8,
+    /// `autoDetectDelimiter`: each file is split on the delimiter its first lines agree on
+    /// (see [`detect_delimiter`]), falling back to `delimiter`
+    pub detect_delimiter: bool,
+    /// Fields may be wrapped in double quotes and contain the delimiter (`quotedFields`)
+    pub quoted: bool,
+    /// Extra equality and range filters, sorted by field index
+    pub field_filters: Vec<FieldFilter>,
+    /// `logFormat: json`: each line is a JSON object, and the values of these keys are read
+    /// as fields 0, 1, 2, ... in place of splitting on the delimiter
+    pub json_keys: Option<Vec<String>>,
+    /// `expectedFieldCount`: lines with any other number of fields are passed on as
+    /// [`MatchedLine::malformed`] instead of going through the filters
+    pub expected_field_count: Option<usize>,
+}
+
+/// How log lines are laid out (`logFormat`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// Fields separated by `fieldDelimiter`, located by index
+    #[default]
+    Delimited,
+    /// One JSON object per line, fields located by key (`ipField`, `domainField`, `resolvedIpField`)
+    Json,
+}
+
+/// Requires the field at `index` to satisfy `condition`.
+#[derive(Debug, Clone)]
+pub struct FieldFilter {
+    pub index: usize,
+    pub condition: FieldCondition,
+}
+
+#[derive(Debug, Clone)]
+pub enum FieldCondition {
+    /// `fieldFilters`: equal to one of these values
+    OneOf(Vec<Vec<u8>>),
+    /// `numericFieldFilters`: an integer or decimal number within `min..=max`; a field that
+    /// does not parse as a number fails
+    Range { min: Option<f64>, max: Option<f64> },
+}
+
+impl FieldFilter {
+    fn passes(&self, field: &[u8]) -> bool {
+        match &self.condition {
+            FieldCondition::OneOf(values) => values.iter().any(|v| v.as_slice() == field),
+            FieldCondition::Range { min, max } => parse_number(field)
+                .is_some_and(|value| min.is_none_or(|min| value >= min) && max.is_none_or(|max| value <= max)),
+        }
+    }
+
+    fn trace_check(&self) -> TraceCheck {
+        match self.condition {
+            FieldCondition::OneOf(_) => TraceCheck::FieldFilter,
+            FieldCondition::Range { .. } => TraceCheck::NumericFieldFilter,
+        }
+    }
+}
+
+/// A finite number written as an integer or decimal (`500`, `-3`, `12.5`, `1e3`).
+fn parse_number(field: &[u8]) -> Option<f64> {
+    std::str::from_utf8(field).ok()?.parse::<f64>().ok().filter(|value| value.is_finite())
+}
+
+/// Compression format of an input file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    Gzip,
+    Zstd,
+    Bzip2,
+    None,
+}
+
+impl Compression {
+    /// Picks the format from the file extension, falling back to sniffing the magic bytes
+    /// so plain files keep working whatever they are named.
+    pub fn detect(path: &Path, data: &[u8]) -> Self {
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("gz") => Compression::Gzip,
+            Some("zst") => Compression::Zstd,
+            Some("bz2") => Compression::Bzip2,
+            _ if data.starts_with(&GZIP_MAGIC) => Compression::Gzip,
+            _ if data.starts_with(&ZSTD_MAGIC) => Compression::Zstd,
+            _ if data.starts_with(&BZIP2_MAGIC) && matches!(data.get(3), Some(b'1'..=b'9')) => Compression::Bzip2,
+            _ => Compression::None,
+        }
+    }
+
+    /// Checks the start of a file against the format's magic bytes, and that a gzip file is at
+    /// least as long as an empty member (10-byte header + 8-byte CRC/size footer).
+    pub fn check_header(self, head: &[u8], file_len: u64) -> std::result::Result<(), &'static str> {
+        match self {
+            Compression::Gzip if !head.starts_with(&GZIP_MAGIC) => Err("missing gzip magic bytes"),
+            Compression::Gzip if file_len < 18 => Err("too short for a gzip member"),
+            Compression::Zstd if !head.starts_with(&ZSTD_MAGIC) => Err("missing zstd magic bytes"),
+            Compression::Bzip2 if !head.starts_with(&BZIP2_MAGIC) => Err("missing bzip2 magic bytes"),
+            _ => Ok(()),
+        }
+    }
+
+    /// Wraps `reader` in the matching decoder, yielding a buffered line source.
+    fn decoder<'a, R: Read + 'a>(self, reader: R) -> Result<Box<dyn BufRead + 'a>> {
+        Ok(match self {
+            Compression::Gzip => Box::new(BufReader::with_capacity(LINE_BUFFER_CAPACITY, MultiGzDecoder::new(reader))),
+            Compression::Zstd => Box::new(BufReader::with_capacity(LINE_BUFFER_CAPACITY, ZstdDecoder::new(reader)?)),
+            // Multi-stream, for archives written by parallel compressors such as pbzip2
+            Compression::Bzip2 => Box::new(BufReader::with_capacity(LINE_BUFFER_CAPACITY, MultiBzDecoder::new(reader))),
+            Compression::None => Box::new(BufReader::with_capacity(LINE_BUFFER_CAPACITY, reader)),
+        })
+    }
+
+    /// Decoder over data already in memory. The slice is the decoder's input buffer as is, so
+    /// the only buffering left is the one the lines are split from. `None` for plain data,
+    /// which needs no reader at all.
+    fn slice_decoder(self, data: &[u8]) -> Result<Option<Box<dyn BufRead + '_>>> {
+        Ok(match self {
+            Compression::Gzip => Some(Box::new(BufReader::with_capacity(LINE_BUFFER_CAPACITY, BufMultiGzDecoder::new(data)))),
+            Compression::Zstd => Some(Box::new(BufReader::with_capacity(LINE_BUFFER_CAPACITY, ZstdDecoder::with_buffer(data)?))),
+            Compression::Bzip2 => Some(Box::new(BufReader::with_capacity(LINE_BUFFER_CAPACITY, BufMultiBzDecoder::new(data)))),
+            Compression::None => None,
+        })
+    }
+}
+
+/// Splits a multi-member gzip file into at most `pieces` ranges that each start at a member
+/// header, so they can be decoded independently. Member boundaries are not recorded in the
+/// format, so a candidate only counts where its header repeats the first member's (flags, `XFL`,
+/// OS byte and, with an extra field, its length and first subfield ID, e.g. BGZF's `BC`) and the
+/// first [`GZIP_PROBE_BYTES`] of output decode from it. A single-member file comes back as one range.
+///
+/// The probe keeps this cheap however large the members are, at the cost of certainty; a piece
+/// that runs into a false boundary finds out itself (see [`FileProcessor::process_aggregated_piece`]).
+/// Members need not end on a line break either (bgzip cuts them at a fixed size), so the pieces
+/// are searched with that rather than one by one.
+pub fn gzip_member_ranges(data: &[u8], pieces: usize) -> Vec<Range<usize>> {
+    let header_len = if data.get(3).is_some_and(|flags| flags & GZIP_FEXTRA != 0) { 14 } else { 10 };
+    let first = match data.get(..header_len) {
+        Some(first) if first.starts_with(&[GZIP_MAGIC[0], GZIP_MAGIC[1], 8]) => first,
+        _ => return vec![Range { start: 0, end: data.len() }],
+    };
+    // Everything but the modification time has to match
+    let is_member_header = |at: usize| {
+        data.get(at..at + header_len).is_some_and(|head| head[..4] == first[..4] && head[8..] == first[8..])
+            && member_probe(&data[at..])
+    };
+    let finder = memmem::Finder::new(&first[..4]);
+    let mut starts = vec![0];
+    for k in 1..pieces {
+        let target = (data.len() / pieces * k).max(starts[starts.len() - 1] + 1);
+        let next = finder.find_iter(&data[target..]).map(|at| target + at).find(|&at| is_member_header(at));
+        match next {
+            Some(start) => starts.push(start),
+            None => break,
+        }
+    }
+    starts.push(data.len());
+    starts.windows(2).map(|pair| pair[0]..pair[1]).collect()
+}
+
+/// Whether `data` starts with a gzip member whose first [`GZIP_PROBE_BYTES`] of output (or all of
+/// it, CRC included, if shorter) decode.
+fn member_probe(data: &[u8]) -> bool {
+    io::copy(&mut flate2::bufread::GzDecoder::new(data).take(GZIP_PROBE_BYTES), &mut io::sink()).is_ok()
+}
+
+/// Decodes the members of one piece of a split gzip file one at a time, which places each member
+/// boundary exactly. The piece's members are those starting before its end. Should the last of
+/// them run past the end, that end was no boundary after all, so the piece reports it and goes
+/// on through the members of the piece after it, up to an end it does land on. Then it reads just
+/// far enough to finish the line crossing that end.
+struct PieceMembers<'a> {
+    path: &'a Path,
+    data: &'a [u8],
+    /// Ends of this piece and of the ones after it
+    ends: &'a [usize],
+    pos: usize,
+    member: Option<flate2::bufread::GzDecoder<&'a [u8]>>,
+    tail: Option<FirstLine<BufMultiGzDecoder<&'a [u8]>>>,
+}
+
+impl Read for PieceMembers<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            if let Some(tail) = &mut self.tail {
+                return tail.read(buf);
+            }
+            if let Some(member) = &mut self.member {
+                let read = member.read(buf)?;
+                if read > 0 || buf.is_empty() {
+                    return Ok(read);
+                }
+                self.pos = self.data.len() - member.get_ref().len();
+                self.member = None;
+            }
+            while self.ends[0] < self.pos {
+                eprintln!(
+                    "{:?}: no gzip member starts at compressed byte {}; searching on into the next piece",
+                    self.path, self.ends[0]
+                );
+                self.ends = &self.ends[1..];
+            }
+            if self.pos < self.ends[0] {
+                self.member = Some(flate2::bufread::GzDecoder::new(&self.data[self.pos..]));
+            } else {
+                let rest = &self.data[self.pos..];
+                self.tail = Some(FirstLine { inner: BufMultiGzDecoder::new(rest), done: rest.is_empty() });
+            }
+        }
+    }
+}
+
+/// Reads `inner` up to and including its first `\n`, then reports the end of input.
+struct FirstLine<R> {
+    inner: R,
+    done: bool,
+}
+
+impl<R: Read> Read for FirstLine<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.done {
+            return Ok(0);
+        }
+        let read = self.inner.read(buf)?;
+        match memchr(b'\n', &buf[..read]) {
+            Some(at) => {
+                self.done = true;
+                Ok(at + 1)
+            }
+            None => Ok(read),
+        }
+    }
+}
+
+/// Reads one line like `read_until(b'\n')`, but buffers at most `max` bytes of it: the rest of a
+/// longer line is consumed and dropped, leaving `buf` empty. Returns the bytes consumed and
+/// whether the line was too long.
+fn read_line_bounded(reader: &mut dyn BufRead, buf: &mut Vec<u8>, max: usize) -> io::Result<(usize, bool)> {
+    let mut consumed = 0;
+    let mut too_long = false;
+    loop {
+        let available = match reader.fill_buf() {
+            Ok(available) => available,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        if available.is_empty() {
+            return Ok((consumed, too_long));
+        }
+        let newline = memchr(b'\n', available);
+        let chunk = &available[..newline.map_or(available.len(), |i| i + 1)];
+        if !too_long {
+            let content = chunk.len() - usize::from(newline.is_some());
+            if buf.len() + content > max {
+                too_long = true;
+                buf.clear();
+            } else {
+                buf.extend_from_slice(chunk);
+            }
+        }
+        let len = chunk.len();
+        reader.consume(len);
+        consumed += len;
+        if newline.is_some() {
+            return Ok((consumed, too_long));
+        }
+    }
+}
+
+fn warn_long_lines(path: &Path, stats: &ProcessStats, max: usize) {
+    if stats.long_lines > 0 {
+        eprintln!(
+            "Warning: skipped {} line(s) longer than maxLineBytes ({} bytes) in {:?}",
+            stats.long_lines, max, path
+        );
+    }
+}
+
+/// How the IP and domain filters combine when both are configured.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FilterLogic {
+    #[default]
+    And,
+    Or,
+}
+
+/// What `samplePercent` hashes to decide whether a matching line is kept (`sampleKey`).
+/// Hashing a field keeps or drops all lines sharing its value together.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, Serialize)]
+pub enum SampleKey {
+    /// The whole raw line
+    #[default]
+    #[serde(rename = "line")]
+    Line,
+    #[serde(rename = "sourceIP")]
+    SourceIp,
+    #[serde(rename = "domain")]
+    Domain,
+}
+
+/// Keeps a fixed share of the matching lines, chosen by hash so reruns keep the same lines.
+#[derive(Debug, Clone, Copy)]
+pub struct Sample {
+    pub key: SampleKey,
+    /// Lines kept out of every 10000 (`samplePercent` * 100)
+    pub per_10000: u64,
+}
+
+impl Sample {
+    /// A line missing the key field (a short line accepted by OR logic) is sampled by the whole line.
+    fn keeps(&self, line: &[u8], ip: Option<&[u8]>, domain: Option<&[u8]>) -> bool {
+        let key = match self.key {
+            SampleKey::Line => line,
+            SampleKey::SourceIp => ip.unwrap_or(line),
+            SampleKey::Domain => domain.unwrap_or(line),
+        };
+        line_hash(key) % 10000 < self.per_10000
+    }
+}
+
+/// Matching behaviour shared by both tasks.
+#[derive(Debug, Clone, Default)]
+pub struct MatchOptions {
+    pub logic: FilterLogic,
+    /// Keep scanning until both fields are located even after the outcome is known,
+    /// for outputs that emit the extracted fields.
+    pub capture_fields: bool,
+    /// Find out which domain rule matched (`splitByDomainRule`); like `capture_fields`,
+    /// this keeps OR logic scanning until the domain field is reached.
+    pub domain_rules: bool,
+    /// Match `example.com.` (FQDN form) like `example.com`
+    pub strip_trailing_dot: bool,
+    /// Find out which `sourceIP` rule matched (`ruleMatchCounts`); keeps OR logic scanning
+    /// like `domain_rules`.
+    pub ip_rules: bool,
+    /// Keep OR logic scanning until the domain field is located, for output that rewrites it
+    /// (`normalizeDomainCase`)
+    pub capture_domain: bool,
+    /// Check every field against the domain rules, not just the one at the domain index
+    /// (`domainAnyField`); a line matches the domain filter when any field does
+    pub domain_any_field: bool,
+    /// Compare Unicode domains in their punycode form (`idnNormalize`)
+    pub idn_normalize: bool,
+    /// Labels the domain must have on top of matching the domain rules (`minDomainLabels` /
+    /// `maxDomainLabels`); `a.b.example.com` has 4
+    pub domain_labels: Option<RangeInclusive<usize>>,
+    /// Lines longer than this are skipped without being buffered (`maxLineBytes`); `None` keeps every line
+    pub max_line_bytes: Option<usize>,
+    /// `samplePercent`: only this share of the matching lines is passed on
+    pub sample: Option<Sample>,
+}
+
+/// A line that passed the filters, along with the fields the filters looked at.
+/// A field is `None` when the line is too short to contain it, or when OR logic accepted
+/// the line before reaching it without `capture_fields`.
+pub struct MatchedLine<'a> {
+    pub raw: &'a [u8],
+    /// Byte separating the fields of `raw`: the layout's, or the one detected for its file
+    pub delimiter: u8,
+    pub ip: Option<&'a [u8]>,
+    pub domain: Option<&'a [u8]>,
+    pub resolved_ip: Option<&'a [u8]>,
+    /// Index into [`DomainMatcher::rule_names`] of the rule the domain matched; only set
+    /// with `MatchOptions::domain_rules`
+    pub domain_rule: Option<usize>,
+    /// Index into [`IPMatcher::rule_names`] of the rule the source IP matched; only set
+    /// with `MatchOptions::ip_rules`
+    pub ip_rule: Option<usize>,
+    /// The line failed `expectedFieldCount`: it is handed over for the malformed-line output,
+    /// was not filtered and does not count as a match
+    pub malformed: bool,
+}
+
+impl<'a> MatchedLine<'a> {
+    fn malformed(raw: &'a [u8], delimiter: u8) -> Self {
+        MatchedLine { raw, delimiter, ip: None, domain: None, resolved_ip: None, domain_rule: None, ip_rule: None, malformed: true }
+    }
+}
+
+/// A filter [`FileProcessor::explain_line`] reports on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TraceCheck {
+    /// One of the `fieldFilters` value lists
+    FieldFilter,
+    /// One of the `numericFieldFilters` ranges
+    NumericFieldFilter,
+    SourceIp,
+    Domain,
+    ResolvedIp,
+}
+
+/// One filter checked while walking a line.
+#[derive(Debug, Clone)]
+pub struct TraceStep {
+    pub check: TraceCheck,
+    /// Field index the filter read
+    pub index: usize,
+    /// The field as the filter saw it: unquoted, and for the domain after `stripTrailingDot`
+    /// and `idnNormalize`
+    pub value: String,
+    pub passed: bool,
+    /// The rule that decided, as written in the config: the include rule a passing field
+    /// satisfied, or the exclude / negation rule that rejected it
+    pub rule: Option<String>,
+    /// Why a source or resolved IP failed
+    pub ip_miss: Option<IpMiss>,
+    /// Label count of a domain that matched the rules but not `minDomainLabels` /
+    /// `maxDomainLabels`
+    pub domain_labels: Option<usize>,
+}
+
+/// How one line went through the filters (`--explain`).
+#[derive(Debug, Clone, Default)]
+pub struct LineTrace {
+    /// The filters taking part and the field each reads, in field order
+    pub checks: Vec<(TraceCheck, usize)>,
+    /// The checks made, in order. The walk stops once the outcome is known, so checks on
+    /// later fields may be missing
+    pub steps: Vec<TraceStep>,
+    /// Fields in the line; `None` for a `logFormat: json` line that is not a JSON object
+    pub field_count: Option<usize>,
+    /// `expectedFieldCount`; a line with another field count is not filtered at all
+    pub expected_field_count: Option<usize>,
+    pub logic: FilterLogic,
+    pub matched: bool,
+    /// The line passed the filters but `samplePercent` left it out, so `matched` is `false`
+    pub sampled_out: bool,
+    /// `domainAnyField`: the domain step, if any, is of the field that matched, or a failed
+    /// step at the domain index when no field did
+    pub domain_any_field: bool,
+}
+
+/// Line counters for one file, or summed over many.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ProcessStats {
+    /// Non-empty lines checked against the filters
+    pub lines: usize,
+    pub matches: usize,
+    /// Files that errored or were cut short by corrupt data
+    pub failed_files: usize,
+    /// Lines skipped for exceeding `maxLineBytes`
+    pub long_lines: usize,
+    /// Lines whose field count differs from `expectedFieldCount`
+    pub malformed_lines: usize,
+}
+
+impl ProcessStats {
+    /// Share of scanned lines that matched, in percent.
+    pub fn match_rate(&self) -> f64 {
+        if self.lines == 0 {
+            0.0
+        } else {
+            self.matches as f64 / self.lines as f64 * 100.0
+        }
+    }
+
+    /// Share of scanned lines that failed `expectedFieldCount`, in percent.
+    pub fn malformed_rate(&self) -> f64 {
+        if self.lines == 0 {
+            0.0
+        } else {
+            self.malformed_lines as f64 / self.lines as f64 * 100.0
+        }
+    }
+}
+
+impl AddAssign for ProcessStats {
+    fn add_assign(&mut self, other: Self) {
+        self.lines += other.lines;
+        self.matches += other.matches;
+        self.failed_files += other.failed_files;
+        self.long_lines += other.long_lines;
+        self.malformed_lines += other.malformed_lines;
+    }
+}
 
 pub struct FileProcessor {
     ip_matcher: IPMatcher,
     domain_matcher: DomainMatcher,
+    resolved_ip_matcher: IPMatcher,
+    aggregated_layout: FieldLayout,
+    native_layout: FieldLayout,
+    options: MatchOptions,
 }
 
 impl FileProcessor {
-    pub fn new(ip_matcher: IPMatcher, domain_matcher: DomainMatcher) -> Self {
+    pub fn new(
+        ip_matcher: IPMatcher,
+        domain_matcher: DomainMatcher,
+        aggregated_layout: FieldLayout,
+        native_layout: FieldLayout,
+        options: MatchOptions,
+    ) -> Self {
         Self {
             ip_matcher,
             domain_matcher,
+            resolved_ip_matcher: IPMatcher::default(),
+            aggregated_layout,
+            native_layout,
+            options,
         }
     }
 
-    pub fn process_aggregated_file<P: AsRef<Path>, F>(&self, path: P, callback: F) -> Result<usize>
+    /// Adds a filter on the answer IP field (`FieldLayout::resolved_ip_index`), combined with
+    /// the source IP and domain filters under the configured logic.
+    pub fn with_resolved_ip_matcher(mut self, matcher: IPMatcher) -> Self {
+        self.resolved_ip_matcher = matcher;
+        self
+    }
+
+    /// The positive `queryDomain` rules, indexed by [`MatchedLine::domain_rule`].
+    pub fn domain_rule_names(&self) -> &[String] {
+        self.domain_matcher.rule_names()
+    }
+
+    /// The `sourceIP` include rules, indexed by [`MatchedLine::ip_rule`].
+    pub fn ip_rule_names(&self) -> &[String] {
+        self.ip_matcher.rule_names()
+    }
+
+    pub fn process_aggregated_data<F>(&self, path: &Path, data: &[u8], compression: Compression, callback: F) -> Result<ProcessStats>
     where
-        F: FnMut(&[u8]),
+        F: FnMut(&MatchedLine),
     {
-        let file = File::open(path)?;
-        let reader = BufReader::with_capacity(2 * 1024 * 1024, file);
-        self.process_reader(reader, callback)
+        self.process_data(path, data, compression, &self.aggregated_layout, callback)
     }
 
-    pub fn process_aggregated_data<F>(&self, data: &[u8], callback: F) -> Result<usize>
+    pub fn process_native_data<F>(&self, path: &Path, data: &[u8], compression: Compression, callback: F) -> Result<ProcessStats>
     where
-        F: FnMut(&[u8]),
+        F: FnMut(&MatchedLine),
     {
-        let reader = BufReader::with_capacity(2 * 1024 * 1024, data);
-        self.process_reader(reader, callback)
+        self.process_data(path, data, compression, &self.native_layout, callback)
     }
 
-    fn process_reader<R: std::io::Read, F>(&self, reader: R, mut callback: F) -> Result<usize>
+    /// Searches piece number `piece` of a gzip file split into `ranges` by [`gzip_member_ranges`].
+    /// A line belongs to the piece it starts in: every piece but the first drops the line running
+    /// into it, and every piece but the last decodes on past its end to finish its own last line.
+    /// A piece whose end turns out not to be a member boundary covers the next piece as well.
+    pub fn process_aggregated_piece<F>(&self, path: &Path, data: &[u8], ranges: &[Range<usize>], piece: usize, callback: F) -> Result<ProcessStats>
     where
-        F: FnMut(&[u8]),
+        F: FnMut(&MatchedLine),
     {
-        let decoder = MultiGzDecoder::new(reader);
-        let mut reader = BufReader::with_capacity(1024 * 1024, decoder);
-        
-        let filter_ip = !self.ip_matcher.is_none();
-        let filter_domain = !self.domain_matcher.is_none();
-        let mut match_count = 0;
-        let mut line_buf = Vec::with_capacity(1024);
+        self.process_piece(path, data, ranges, piece, &self.aggregated_layout, callback)
+    }
 
-        loop {
-            line_buf.clear();
-            let bytes_read = reader.read_until(b'\n', &mut line_buf)?;
-            if bytes_read == 0 {
-                break;
-            }
+    pub fn process_native_piece<F>(&self, path: &Path, data: &[u8], ranges: &[Range<usize>], piece: usize, callback: F) -> Result<ProcessStats>
+    where
+        F: FnMut(&MatchedLine),
+    {
+        self.process_piece(path, data, ranges, piece, &self.native_layout, callback)
+    }
 
-            if line_buf.last() == Some(&b'\n') {
-                line_buf.pop();
-            }
-            if line_buf.last() == Some(&b'\r') {
-                line_buf.pop();
+    /// Runs one line through the filters of the aggregated (or native) layout the way the search
+    /// does, recording each check made and the decision.
+    pub fn explain_line(&self, line: &[u8], native: bool) -> LineTrace {
+        let layout = if native { &self.native_layout } else { &self.aggregated_layout };
+        let active = self.active_filters(layout);
+        let line = line.strip_suffix(b"\n").unwrap_or(line);
+        let line = line.strip_suffix(b"\r").unwrap_or(line);
+
+        let mut checks: Vec<_> = layout.field_filters.iter().map(|filter| (filter.trace_check(), filter.index)).collect();
+        checks.extend(active.ip.then_some((TraceCheck::SourceIp, layout.ip_index)));
+        checks.extend(active.domain.then_some((TraceCheck::Domain, layout.domain_index)));
+        checks.extend(layout.resolved_ip_index.filter(|_| active.resolved_ip).map(|index| (TraceCheck::ResolvedIp, index)));
+        // Stable, so a field filter stays ahead of a matcher on the same field, as in the walk
+        checks.sort_by_key(|&(_, index)| index);
+        let mut trace = LineTrace {
+            checks,
+            expected_field_count: layout.expected_field_count,
+            logic: self.options.logic,
+            domain_any_field: self.options.domain_any_field && active.domain,
+            ..LineTrace::default()
+        };
+
+        match &layout.json_keys {
+            None => {
+                let count = field_count(line, layout);
+                trace.field_count = Some(count);
+                if layout.expected_field_count.is_none_or(|expected| expected == count) {
+                    self.check_line(line, active, layout, Some(&mut trace));
+                }
             }
-            if line_buf.is_empty() {
-                continue;
+            Some(keys) => {
+                if let Some(values) = json_fields(line, keys) {
+                    trace.field_count = Some(values.len());
+                    let fields = values.iter().map(|value| value.as_ref());
+                    self.check_fields(line, fields, active, layout, Some(&mut trace));
+                }
             }
+        }
+        trace
+    }
+
+    /// Checks one line, without its line break, against the filters of the aggregated (or
+    /// native) layout, calling `callback` when it matches. Used to run a line another
+    /// processor already read through this one's filters (`profiles`).
+    pub fn match_line<F>(&self, line: &[u8], native: bool, callback: F) -> bool
+    where
+        F: FnOnce(&MatchedLine),
+    {
+        let layout = if native { &self.native_layout } else { &self.aggregated_layout };
+        let active = self.active_filters(layout);
+        let matched = match &layout.json_keys {
+            None => self.check_line(line, active, layout, None).map(|matched| callback(&matched)),
+            Some(keys) => json_fields(line, keys).and_then(|values| {
+                let fields = values.iter().map(|value| value.as_ref());
+                self.check_fields(line, fields, active, layout, None).map(|matched| callback(&matched))
+            }),
+        };
+        matched.is_some()
+    }
+
+    /// Processes a stream of aggregated-layout lines, e.g. stdin.
+    pub fn process_aggregated_reader<R: Read, F>(&self, path: &Path, reader: R, compression: Compression, callback: F) -> Result<ProcessStats>
+    where
+        F: FnMut(&MatchedLine),
+    {
+        self.process_reader(path, reader, compression, &self.aggregated_layout, callback)
+    }
 
-            if self.check_line(&line_buf, filter_ip, filter_domain, AGGREGATED_LOG_IP_INDEX, AGGREGATED_LOG_DOMAIN_INDEX) {
-                callback(&line_buf);
-                match_count += 1;
+    /// In-memory input: plain data is split in place without copying a line, compressed data
+    /// is decoded straight from the slice.
+    fn process_data<F>(&self, path: &Path, data: &[u8], compression: Compression, layout: &FieldLayout, mut callback: F) -> Result<ProcessStats>
+    where
+        F: FnMut(&MatchedLine),
+    {
+        if let Some(reader) = compression.slice_decoder(data)? {
+            return self.scan_reader(path, reader, compression, layout, callback);
+        }
+        let layout = &*file_layout(path, data, true, layout);
+        let filters = self.active_filters(layout);
+        let max_line = self.options.max_line_bytes.unwrap_or(usize::MAX);
+        let mut stats = ProcessStats::default();
+        let mut start = 0;
+        for end in memchr_iter(b'\n', data).chain((data.last() != Some(&b'\n')).then_some(data.len())) {
+            let line = &data[start..end];
+            start = end + 1;
+            if line.len() > max_line {
+                stats.long_lines += 1;
+                continue;
             }
+            self.scan_line(line, filters, layout, &mut stats, &mut callback);
         }
-        Ok(match_count)
+        warn_long_lines(path, &stats, max_line);
+        Ok(stats)
     }
 
-    pub fn process_native_file<P: AsRef<Path>, F>(&self, path: P, callback: F) -> Result<usize>
+    fn process_piece<F>(
+        &self,
+        path: &Path,
+        data: &[u8],
+        ranges: &[Range<usize>],
+        piece: usize,
+        layout: &FieldLayout,
+        callback: F,
+    ) -> Result<ProcessStats>
     where
-        F: FnMut(&[u8]),
+        F: FnMut(&MatchedLine),
     {
-        let file = File::open(path)?;
-        let reader = BufReader::with_capacity(2 * 1024 * 1024, file);
-        self.process_native_reader(reader, callback)
+        let start = ranges[piece].start;
+        let ends: Vec<usize> = ranges[piece..].iter().map(|range| range.end).collect();
+        // The line crossing the end is finished even when it is the next piece's first one,
+        // since that piece cannot tell whether the line before its start was complete
+        let members = PieceMembers { path, data, ends: &ends, pos: start, member: None, tail: None };
+        let mut reader = BufReader::with_capacity(LINE_BUFFER_CAPACITY, members);
+        if start > 0 {
+            if let Err(e) = reader.skip_until(b'\n') {
+                eprintln!("Corrupt or truncated file {:?} at compressed byte {}: {}", path, start, e);
+                return Ok(ProcessStats { failed_files: 1, ..ProcessStats::default() });
+            }
+        }
+        self.scan_reader(path, Box::new(reader), Compression::Gzip, layout, callback)
     }
 
-    pub fn process_native_data<F>(&self, data: &[u8], callback: F) -> Result<usize>
+    fn process_reader<R: Read, F>(
+        &self,
+        path: &Path,
+        reader: R,
+        compression: Compression,
+        layout: &FieldLayout,
+        callback: F,
+    ) -> Result<ProcessStats>
     where
-        F: FnMut(&[u8]),
+        F: FnMut(&MatchedLine),
     {
-        let reader = BufReader::with_capacity(2 * 1024 * 1024, data);
-        self.process_native_reader(reader, callback)
+        self.scan_reader(path, compression.decoder(reader)?, compression, layout, callback)
     }
 
-    fn process_native_reader<R: std::io::Read, F>(&self, reader: R, mut callback: F) -> Result<usize>
+    fn active_filters(&self, layout: &FieldLayout) -> ActiveFilters {
+        ActiveFilters {
+            ip: !self.ip_matcher.is_none(),
+            // Without rules every domain matches, so the label count alone decides
+            domain: !self.domain_matcher.is_none() || self.options.domain_labels.is_some(),
+            resolved_ip: !self.resolved_ip_matcher.is_none() && layout.resolved_ip_index.is_some(),
+        }
+    }
+
+    /// Decode errors (a truncated or corrupt compressed file) end the file early with a warning;
+    /// the lines decoded up to that point still count. Plain read errors are propagated.
+    fn scan_reader<F>(
+        &self,
+        path: &Path,
+        mut reader: Box<dyn BufRead + '_>,
+        compression: Compression,
+        layout: &FieldLayout,
+        mut callback: F,
+    ) -> Result<ProcessStats>
     where
-        F: FnMut(&[u8]),
+        F: FnMut(&MatchedLine),
     {
-        let decoder = MultiGzDecoder::new(reader);
-        let mut reader = BufReader::with_capacity(1024 * 1024, decoder);
-
-        let filter_ip = !self.ip_matcher.is_none();
-        let filter_domain = !self.domain_matcher.is_none();
-        let mut match_count = 0;
+        // A decode error here comes up again on the first read below
+        let layout = &*match reader.fill_buf() {
+            Ok(sample) => file_layout(path, sample, false, layout),
+            Err(_) => Cow::Borrowed(layout),
+        };
+        let filters = self.active_filters(layout);
+        let max_line = self.options.max_line_bytes.unwrap_or(usize::MAX);
+        let mut stats = ProcessStats::default();
         let mut line_buf = Vec::with_capacity(1024);
+        let mut offset: u64 = 0;
 
         loop {
             line_buf.clear();
-            let bytes_read = reader.read_until(b'\n', &mut line_buf)?;
+            let (bytes_read, too_long) = match read_line_bounded(&mut reader, &mut line_buf, max_line) {
+                Ok(read) => read,
+                Err(e) if compression != Compression::None => {
+                    // Whatever partial line sits in `line_buf` is incomplete; drop it.
+                    eprintln!(
+                        "Corrupt or truncated file {:?} at decompressed byte {}: {} (keeping {} matches read so far)",
+                        path,
+                        offset,
+                        e,
+                        stats.matches
+                    );
+                    stats.failed_files = 1;
+                    break;
+                }
+                Err(e) => return Err(e.into()),
+            };
             if bytes_read == 0 {
                 break;
             }
-
-            if line_buf.last() == Some(&b'\n') {
-                line_buf.pop();
-            }
-            if line_buf.last() == Some(&b'\r') {
-                line_buf.pop();
-            }
-            if line_buf.is_empty() {
+            offset += bytes_read as u64;
+            if too_long {
+                stats.long_lines += 1;
                 continue;
             }
 
-            if self.check_line(&line_buf, filter_ip, filter_domain, NATIVE_LOG_IP_INDEX, NATIVE_LOG_DOMAIN_INDEX) {
-                callback(&line_buf);
-                match_count += 1;
+            if line_buf.last() == Some(&b'\n') {
+                line_buf.pop();
             }
+            self.scan_line(&line_buf, filters, layout, &mut stats, &mut callback);
         }
-        Ok(match_count)
+        warn_long_lines(path, &stats, max_line);
+        Ok(stats)
     }
 
+    /// Counts and checks one line without its `\n`; a trailing `\r` is dropped and empty lines are skipped.
     #[inline(always)]
-    fn check_line(&self, line: &[u8], filter_ip: bool, filter_domain: bool, ip_idx: usize, domain_idx: usize) -> bool {
-        // If no filters, match everything (though usually we have at least one)
-        if !filter_ip && !filter_domain {
-            return true;
+    fn scan_line<F>(&self, line: &[u8], filters: ActiveFilters, layout: &FieldLayout, stats: &mut ProcessStats, callback: &mut F)
+    where
+        F: FnMut(&MatchedLine),
+    {
+        let line = line.strip_suffix(b"\r").unwrap_or(line);
+        if line.is_empty() {
+            return;
         }
+        stats.lines += 1;
 
-        let mut ip_matched = !filter_ip;
-        let mut domain_matched = !filter_domain;
+        if let Some(expected) = layout.expected_field_count {
+            if field_count(line, layout) != expected {
+                stats.malformed_lines += 1;
+                callback(&MatchedLine::malformed(line, layout.delimiter));
+                return;
+            }
+        }
 
-        let mut iter = memchr_iter(b'|', line);
-        let mut current_idx = 0;
-        let mut start = 0;
+        let matched = match &layout.json_keys {
+            None => self.check_line(line, filters, layout, None).map(|matched| callback(&matched)),
+            // A line that is not a JSON object does not match
+            Some(keys) => json_fields(line, keys).and_then(|values| {
+                let fields = values.iter().map(|value| value.as_ref());
+                self.check_fields(line, fields, filters, layout, None).map(|matched| callback(&matched))
+            }),
+        };
+        if matched.is_some() {
+            stats.matches += 1;
+        }
+    }
 
-        // Optimization: Determine max index we need to reach
-        let max_idx = if filter_ip && filter_domain {
-            std::cmp::max(ip_idx, domain_idx)
-        } else if filter_ip {
-            ip_idx
+    /// Applies the matchers to the IP, domain and resolved-IP fields while walking the line,
+    /// returning the located fields when the line matches. Scanning stops as soon as the outcome
+    /// is known: on the first failing filter under AND, on the first passing one under OR (unless
+    /// the output needs every field captured).
+    ///
+    /// Field filters are always required, whatever `filterLogic` says about the IP and domain
+    /// filters. A line too short to contain a filtered field never passes that filter: the
+    /// field filter fails, and an IP or domain filter counts as not matched.
+    ///
+    /// With `trace` (`--explain`) each check made is recorded along with the rule behind it;
+    /// the search passes `None`, which compiles the recording away.
+    #[inline(always)]
+    fn check_line<'a>(
+        &self,
+        line: &'a [u8],
+        active: ActiveFilters,
+        layout: &FieldLayout,
+        trace: Option<&mut LineTrace>,
+    ) -> Option<MatchedLine<'a>> {
+        if layout.quoted {
+            // Matchers see the field contents without the quotes
+            self.check_fields(line, quoted_fields(line, layout.delimiter).map(unquote), active, layout, trace)
         } else {
-            domain_idx
-        };
+            self.check_fields(line, fields(line, layout.delimiter), active, layout, trace)
+        }
+    }
 
-        while let Some(end) = iter.next() {
-            if current_idx == ip_idx && filter_ip {
-                let field = &line[start..end];
-                if self.ip_matcher.matches(field) {
-                    ip_matched = true;
+    #[inline(always)]
+    fn check_fields<'a>(
+        &self,
+        line: &'a [u8],
+        fields: impl Iterator<Item = &'a [u8]>,
+        active: ActiveFilters,
+        layout: &FieldLayout,
+        mut trace: Option<&mut LineTrace>,
+    ) -> Option<MatchedLine<'a>> {
+        let and_logic = self.options.logic == FilterLogic::And;
+        let filters = &layout.field_filters;
+        let resolved_index = layout
+            .resolved_ip_index
+            .filter(|_| active.resolved_ip || self.options.capture_fields);
+        // `domainAnyField`: every field is a domain candidate until one matches
+        let any_field = self.options.domain_any_field && active.domain;
+        let max_idx = std::cmp::max(layout.ip_index, layout.domain_index)
+            .max(filters.last().map_or(0, |f| f.index))
+            .max(resolved_index.unwrap_or(0));
+        let mut ip = None;
+        let mut domain = None;
+        let mut resolved_ip = None;
+        let mut domain_rule = None;
+        let mut ip_rule = None;
+        let mut ip_matched = false;
+        let mut domain_matched = false;
+        let mut resolved_matched = false;
+        let mut next_filter = 0;
+
+        for (idx, field) in fields.enumerate() {
+            // A field may carry both a value list and a range
+            while let Some(filter) = filters.get(next_filter).filter(|filter| filter.index == idx) {
+                let passed = filter.passes(field);
+                if let Some(trace) = trace.as_deref_mut() {
+                    trace.steps.push(TraceStep {
+                        check: filter.trace_check(),
+                        index: idx,
+                        value: String::from_utf8_lossy(field).into_owned(),
+                        passed,
+                        rule: None,
+                        ip_miss: None,
+                        domain_labels: None,
+                    });
+                }
+                if !passed {
+                    return None;
                 }
+                next_filter += 1;
             }
-            if current_idx == domain_idx && filter_domain {
-                let field = &line[start..end];
-                if self.domain_matcher.matches(field) {
-                    domain_matched = true;
+            // OR may settle early, but only once every field filter has been checked, and not
+            // before the field a sample is keyed on
+            let can_settle = !and_logic
+                && !self.options.capture_fields
+                && !self.options.domain_rules
+                && !self.options.ip_rules
+                && (!self.options.capture_domain || domain.is_some())
+                && self.options.sample.is_none_or(|sample| sample.key == SampleKey::Line)
+                && next_filter == filters.len();
+            if idx == layout.ip_index {
+                ip = Some(field);
+                ip_matched = active.ip && self.ip_matcher.matches(field);
+                if ip_matched && self.options.ip_rules {
+                    ip_rule = self.ip_matcher.matched_rule(field);
+                }
+                if let Some(trace) = trace.as_deref_mut().filter(|_| active.ip) {
+                    trace.steps.push(ip_step(&self.ip_matcher, TraceCheck::SourceIp, idx, field, ip_matched));
+                }
+                if active.ip && and_logic && !ip_matched {
+                    return None;
+                }
+                if ip_matched && can_settle {
+                    break;
                 }
             }
-
-            if ip_matched && domain_matched {
-                return true;
+            let checks_domain = if any_field { !domain_matched } else { idx == layout.domain_index };
+            if checks_domain {
+                let field = match field.strip_suffix(b".") {
+                    Some(stripped) if self.options.strip_trailing_dot => stripped,
+                    _ => field,
+                };
+                // Rules were converted the same way when the matcher was built
+                let ace = (self.options.idn_normalize && !field.is_ascii()).then(|| idn_to_ascii(field)).flatten();
+                let normalized = ace.as_deref().unwrap_or(field);
+                let rule_matched = active.domain && self.domain_matcher.matches(normalized);
+                // Only counted for a domain the rules accept
+                let labels = self.options.domain_labels.as_ref().filter(|_| rule_matched).map(|range| {
+                    let labels = domain_labels(normalized);
+                    (labels, range.contains(&labels))
+                });
+                domain_matched = rule_matched && labels.is_none_or(|(_, within)| within);
+                // With `domainAnyField` the field that matched, else the one at the domain index
+                if domain_matched || idx == layout.domain_index {
+                    domain = Some(field);
+                }
+                let field = normalized;
+                if domain_matched && self.options.domain_rules {
+                    domain_rule = self.domain_matcher.matched_rule(field);
+                }
+                // Any-field mode records only the field that matched
+                if let Some(trace) = trace.as_deref_mut().filter(|_| active.domain && (!any_field || domain_matched)) {
+                    let rule = match (domain_matched, rule_matched) {
+                        (true, _) => self.domain_matcher.matched_rule(field).map(|rule| self.domain_matcher.rule_names()[rule].clone()),
+                        (false, false) => self.domain_matcher.negated_by(field).map(str::to_string),
+                        (false, true) => None,
+                    };
+                    trace.steps.push(TraceStep {
+                        check: TraceCheck::Domain,
+                        index: idx,
+                        value: String::from_utf8_lossy(field).into_owned(),
+                        passed: domain_matched,
+                        rule,
+                        ip_miss: None,
+                        domain_labels: labels.filter(|&(_, within)| !within).map(|(labels, _)| labels),
+                    });
+                }
+                if active.domain && and_logic && !domain_matched && !any_field {
+                    return None;
+                }
+                if domain_matched && can_settle {
+                    break;
+                }
             }
-
-            if current_idx >= max_idx {
+            if Some(idx) == resolved_index {
+                resolved_ip = Some(field);
+                resolved_matched = active.resolved_ip && self.resolved_ip_matcher.matches(field);
+                if let Some(trace) = trace.as_deref_mut().filter(|_| active.resolved_ip) {
+                    trace.steps.push(ip_step(&self.resolved_ip_matcher, TraceCheck::ResolvedIp, idx, field, resolved_matched));
+                }
+                if active.resolved_ip && and_logic && !resolved_matched {
+                    return None;
+                }
+                if resolved_matched && can_settle {
+                    break;
+                }
+            }
+            if idx >= max_idx && (!any_field || domain_matched) {
                 break;
             }
+        }
+        if next_filter < filters.len() {
+            return None;
+        }
 
-            start = end + 1;
-            current_idx += 1;
+        // A filtered field missing from a short line counts as not matched
+        let matched = if !active.ip && !active.domain && !active.resolved_ip {
+            true
+        } else if and_logic {
+            (!active.ip || ip_matched) && (!active.domain || domain_matched) && (!active.resolved_ip || resolved_matched)
+        } else {
+            ip_matched || domain_matched || resolved_matched
+        };
+        let sampled_out = matched && self.options.sample.is_some_and(|sample| !sample.keeps(line, ip, domain));
+        if let Some(trace) = trace {
+            if any_field && !domain_matched && !matched {
+                // Every field was tried
+                trace.steps.push(TraceStep {
+                    check: TraceCheck::Domain,
+                    index: layout.domain_index,
+                    value: String::new(),
+                    passed: false,
+                    rule: None,
+                    ip_miss: None,
+                    domain_labels: None,
+                });
+            }
+            trace.matched = matched && !sampled_out;
+            trace.sampled_out = sampled_out;
+        }
+        (matched && !sampled_out).then_some(MatchedLine { raw: line, delimiter: layout.delimiter, ip, domain, resolved_ip, domain_rule, ip_rule, malformed: false })
+    }
+}
+
+/// Number of dot-separated labels in a domain; an empty domain has none.
+fn domain_labels(domain: &[u8]) -> usize {
+    if domain.is_empty() {
+        0
+    } else {
+        memchr_iter(b'.', domain).count() + 1
+    }
+}
+
+/// Number of delimited fields in `line`; an empty line has one.
+fn field_count(line: &[u8], layout: &FieldLayout) -> usize {
+    count_fields(line, layout.delimiter, layout.quoted)
+}
+
+fn count_fields(line: &[u8], delimiter: u8, quoted: bool) -> usize {
+    if quoted {
+        quoted_fields(line, delimiter).count()
+    } else {
+        memchr_iter(delimiter, line).count() + 1
+    }
+}
+
+/// `layout` for the file starting with `sample`: with `autoDetectDelimiter`, a copy split on the
+/// delimiter [`detect_delimiter`] finds, or `layout` itself when it finds none. `complete` says
+/// the sample is the whole file, so its last line counts even without a line break.
+fn file_layout<'a>(path: &Path, sample: &[u8], complete: bool, layout: &'a FieldLayout) -> Cow<'a, FieldLayout> {
+    if !layout.detect_delimiter || layout.json_keys.is_some() {
+        return Cow::Borrowed(layout);
+    }
+    match detect_delimiter(sample, complete, layout) {
+        Some(delimiter) => {
+            debug!("{:?}: detected field delimiter {:?}", path, delimiter as char);
+            Cow::Owned(FieldLayout { delimiter, ..layout.clone() })
+        }
+        None => {
+            debug!("{:?}: no single delimiter fits the first lines, using fieldDelimiter {:?}", path, layout.delimiter as char);
+            Cow::Borrowed(layout)
         }
+    }
+}
 
-        // Handle the last field if it's the one we need
-        if current_idx <= max_idx {
-             let field = &line[start..];
-             if current_idx == ip_idx && filter_ip {
-                if self.ip_matcher.matches(field) {
-                    ip_matched = true;
+/// The delimiter among [`DELIMITER_CANDIDATES`] and `layout.delimiter` that splits each of the
+/// first [`DELIMITER_SAMPLE_LINES`] lines of `sample` into the same number of fields: enough
+/// to reach the IP and domain fields, and `expectedFieldCount` when that is set. `None` when
+/// no candidate or more than one does, or `sample` holds no complete line. Without
+/// `complete` the text after the last line break is taken as a partial line and left out.
+pub fn detect_delimiter(sample: &[u8], complete: bool, layout: &FieldLayout) -> Option<u8> {
+    let end = if complete { sample.len() } else { sample.iter().rposition(|&b| b == b'\n')? };
+    let lines: Vec<&[u8]> = sample[..end]
+        .split(|&b| b == b'\n')
+        .map(|line| line.strip_suffix(b"\r").unwrap_or(line))
+        .filter(|line| !line.is_empty())
+        .take(DELIMITER_SAMPLE_LINES)
+        .collect();
+    if lines.is_empty() {
+        return None;
+    }
+    let min_fields = layout.ip_index.max(layout.domain_index) + 1;
+    let mut candidates = DELIMITER_CANDIDATES.to_vec();
+    if !candidates.contains(&layout.delimiter) {
+        candidates.push(layout.delimiter);
+    }
+    let mut consistent = candidates.into_iter().filter(|&delimiter| {
+        let count = count_fields(lines[0], delimiter, layout.quoted);
+        count >= min_fields.max(2)
+            && layout.expected_field_count.is_none_or(|expected| expected == count)
+            && lines.iter().all(|line| count_fields(line, delimiter, layout.quoted) == count)
+    });
+    let found = consistent.next()?;
+    consistent.next().is_none().then_some(found)
+}
+
+/// The trace of a source or resolved IP check, naming the rule that decided it.
+fn ip_step(matcher: &IPMatcher, check: TraceCheck, index: usize, field: &[u8], passed: bool) -> TraceStep {
+    let ip_miss = if passed { None } else { matcher.explain_miss(field) };
+    let rule = match &ip_miss {
+        None => matcher.matched_rule(field).map(|rule| matcher.rule_names()[rule].clone()),
+        Some(IpMiss::Excluded(rule)) => Some(matcher.exclude_rule_names()[*rule].clone()),
+        Some(_) => None,
+    };
+    TraceStep { check, index, value: String::from_utf8_lossy(field).into_owned(), passed, rule, ip_miss, domain_labels: None }
+}
+
+/// Which matchers take part for the layout being processed.
+#[derive(Debug, Clone, Copy)]
+struct ActiveFilters {
+    ip: bool,
+    domain: bool,
+    resolved_ip: bool,
+}
+
+/// Iterates over the delimiter-separated fields of a line.
+#[inline(always)]
+pub(crate) fn fields(line: &[u8], delimiter: u8) -> impl Iterator<Item = &[u8]> {
+    let mut start = 0;
+    memchr_iter(delimiter, line)
+        .chain(std::iter::once(line.len()))
+        .map(move |end| {
+            let field = &line[start..end];
+            start = end + 1;
+            field
+        })
+}
+
+/// Iterates over the fields of a line whose fields may be double-quoted, as in CSV: a
+/// delimiter between quotes does not split, and `""` inside quotes is an escaped quote.
+/// Fields are returned as written, quotes included; see [`unquote`].
+pub(crate) fn quoted_fields(line: &[u8], delimiter: u8) -> QuotedFields<'_> {
+    QuotedFields { line, next: Some(0), delimiter }
+}
+
+pub(crate) struct QuotedFields<'a> {
+    line: &'a [u8],
+    /// Start of the next field; `None` once the last one was returned
+    next: Option<usize>,
+    delimiter: u8,
+}
+
+impl<'a> Iterator for QuotedFields<'a> {
+    type Item = &'a [u8];
+
+    fn next(&mut self) -> Option<&'a [u8]> {
+        let start = self.next?;
+        let mut search_from = start;
+        if self.line.get(start) == Some(&b'"') {
+            // Skip to the closing quote; an unterminated quote runs to the end of the line
+            let mut pos = start + 1;
+            search_from = self.line.len();
+            while let Some(quote) = memchr::memchr(b'"', &self.line[pos..]) {
+                let quote = pos + quote;
+                if self.line.get(quote + 1) == Some(&b'"') {
+                    pos = quote + 2;
+                    continue;
                 }
+                search_from = quote + 1;
+                break;
             }
-            if current_idx == domain_idx && filter_domain {
-                if self.domain_matcher.matches(field) {
-                    domain_matched = true;
+        }
+        let end = memchr::memchr(self.delimiter, &self.line[search_from..]).map(|pos| search_from + pos);
+        self.next = end.map(|end| end + 1);
+        Some(&self.line[start..end.unwrap_or(self.line.len())])
+    }
+}
+
+/// Strips the surrounding quotes from a field returned by [`quoted_fields`]. Escaped `""`
+/// inside is left as is.
+#[inline]
+pub(crate) fn unquote(field: &[u8]) -> &[u8] {
+    match field.strip_prefix(b"\"") {
+        Some(inner) => inner.strip_suffix(b"\"").unwrap_or(inner),
+        None => field,
+    }
+}
+
+/// Reads the values of `keys` from a line holding one JSON object, in the order of `keys`.
+/// String values are borrowed from the line unless they contain escapes; numbers and booleans
+/// are taken as their text. Missing keys, and keys holding null, an object or an array, read
+/// as empty. `None` when the line is not a JSON object.
+fn json_fields<'a>(line: &'a [u8], keys: &[String]) -> Option<Vec<Cow<'a, [u8]>>> {
+    serde_json::Deserializer::from_slice(line).deserialize_map(JsonFieldsVisitor { keys }).ok()
+}
+
+struct JsonFieldsVisitor<'k> {
+    keys: &'k [String],
+}
+
+impl<'de> Visitor<'de> for JsonFieldsVisitor<'_> {
+    type Value = Vec<Cow<'de, [u8]>>;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a JSON object")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+        let mut values = vec![Cow::Borrowed(&b""[..]); self.keys.len()];
+        while let Some(JsonText(key)) = map.next_key()? {
+            match self.keys.iter().position(|k| k.as_bytes() == key.as_ref()) {
+                Some(i) => values[i] = map.next_value::<JsonText>()?.0,
+                None => {
+                    map.next_value::<IgnoredAny>()?;
                 }
             }
         }
+        Ok(values)
+    }
+}
+
+/// A JSON key or scalar value as text, borrowed from the input where possible.
+struct JsonText<'de>(Cow<'de, [u8]>);
+
+impl<'de> Deserialize<'de> for JsonText<'de> {
+    fn deserialize<D: de::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_any(JsonTextVisitor).map(JsonText)
+    }
+}
+
+struct JsonTextVisitor;
+
+impl<'de> Visitor<'de> for JsonTextVisitor {
+    type Value = Cow<'de, [u8]>;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a JSON value")
+    }
+
+    fn visit_borrowed_str<E: de::Error>(self, v: &'de str) -> Result<Self::Value, E> {
+        Ok(Cow::Borrowed(v.as_bytes()))
+    }
+
+    fn visit_str<E: de::Error>(self, v: &str) -> Result<Self::Value, E> {
+        Ok(Cow::Owned(v.as_bytes().to_vec()))
+    }
+
+    fn visit_u64<E: de::Error>(self, v: u64) -> Result<Self::Value, E> {
+        Ok(Cow::Owned(v.to_string().into_bytes()))
+    }
+
+    fn visit_i64<E: de::Error>(self, v: i64) -> Result<Self::Value, E> {
+        Ok(Cow::Owned(v.to_string().into_bytes()))
+    }
+
+    fn visit_f64<E: de::Error>(self, v: f64) -> Result<Self::Value, E> {
+        Ok(Cow::Owned(v.to_string().into_bytes()))
+    }
+
+    fn visit_bool<E: de::Error>(self, v: bool) -> Result<Self::Value, E> {
+        Ok(Cow::Borrowed(if v { b"true" } else { b"false" }))
+    }
+
+    fn visit_unit<E: de::Error>(self) -> Result<Self::Value, E> {
+        Ok(Cow::Borrowed(b""))
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+        while map.next_entry::<IgnoredAny, IgnoredAny>()?.is_some() {}
+        Ok(Cow::Borrowed(b""))
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+        while seq.next_element::<IgnoredAny>()?.is_some() {}
+        Ok(Cow::Borrowed(b""))
+    }
+}
 
-        ip_matched && domain_matched
+/// Fields as written in the line, honouring quotes when `quoted` is set.
+pub(crate) fn split_fields(line: &[u8], delimiter: u8, quoted: bool) -> Box<dyn Iterator<Item = &[u8]> + '_> {
+    if quoted {
+        Box::new(quoted_fields(line, delimiter))
+    } else {
+        Box::new(fields(line, delimiter))
     }
 }
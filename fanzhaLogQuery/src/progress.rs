@@ -0,0 +1,189 @@
+use crate::status;
+use indicatif::{ProgressBar, ProgressStyle};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// Receives the [`ProgressEvent`]s of a search. It is called from the worker threads as well
+/// as from a ticker thread, possibly at the same time.
+pub type ProgressCallback = Arc<dyn Fn(ProgressEvent) + Send + Sync>;
+
+/// How often [`ProgressEvent::Tick`] is sent while a task runs.
+const TICK_INTERVAL: Duration = Duration::from_secs(1);
+/// How often the plain status line is printed when the status stream is not a terminal.
+const STATUS_LINE_INTERVAL: Duration = Duration::from_secs(120);
+
+#[derive(Debug, Clone, Copy)]
+pub enum ProgressEvent {
+    /// An input file was searched to the end, failed or could not be read
+    FileCompleted(ProgressCounts),
+    /// Sent every second while the task runs, also when no file finished since the last one
+    Tick(ProgressCounts),
+    /// Every worker has stopped; sent once per task, before its summary is printed
+    Finished(ProgressCounts),
+}
+
+impl ProgressEvent {
+    pub fn counts(&self) -> &ProgressCounts {
+        match self {
+            ProgressEvent::FileCompleted(counts) | ProgressEvent::Tick(counts) | ProgressEvent::Finished(counts) => counts,
+        }
+    }
+}
+
+/// Where one search task stands.
+#[derive(Debug, Clone, Copy)]
+pub struct ProgressCounts {
+    /// `aggregated` or `native`, as in the manifest
+    pub task: &'static str,
+    /// Name of the task in the built-in status lines
+    pub label: &'static str,
+    pub processed_files: usize,
+    pub total_files: usize,
+    /// Size as read of the completed files; a remote file counts once downloaded
+    pub processed_bytes: u64,
+    /// Size of the local input files when the task started; remote files are not included
+    pub total_bytes: u64,
+    pub elapsed: Duration,
+}
+
+/// Counts the completed files of one task and passes the events on to the callback.
+pub(crate) struct ProgressTracker {
+    task: &'static str,
+    label: &'static str,
+    total_files: usize,
+    total_bytes: u64,
+    started: Instant,
+    processed_files: AtomicUsize,
+    processed_bytes: AtomicU64,
+    callback: Option<ProgressCallback>,
+}
+
+impl ProgressTracker {
+    pub fn new(
+        task: &'static str,
+        label: &'static str,
+        total_files: usize,
+        total_bytes: u64,
+        callback: Option<ProgressCallback>,
+    ) -> Self {
+        ProgressTracker {
+            task,
+            label,
+            total_files,
+            total_bytes,
+            started: Instant::now(),
+            processed_files: AtomicUsize::new(0),
+            processed_bytes: AtomicU64::new(0),
+            callback,
+        }
+    }
+
+    pub fn processed_files(&self) -> usize {
+        self.processed_files.load(Ordering::Relaxed)
+    }
+
+    pub fn file_completed(&self, bytes: u64) {
+        self.processed_files.fetch_add(1, Ordering::Relaxed);
+        self.processed_bytes.fetch_add(bytes, Ordering::Relaxed);
+        self.send(ProgressEvent::FileCompleted);
+    }
+
+    pub fn finish(&self) {
+        self.send(ProgressEvent::Finished);
+    }
+
+    fn send(&self, event: fn(ProgressCounts) -> ProgressEvent) {
+        if let Some(callback) = &self.callback {
+            callback(event(self.counts()));
+        }
+    }
+
+    fn counts(&self) -> ProgressCounts {
+        ProgressCounts {
+            task: self.task,
+            label: self.label,
+            processed_files: self.processed_files.load(Ordering::Relaxed),
+            total_files: self.total_files,
+            processed_bytes: self.processed_bytes.load(Ordering::Relaxed),
+            total_bytes: self.total_bytes,
+            elapsed: self.started.elapsed(),
+        }
+    }
+}
+
+/// Spawns the thread sending [`ProgressEvent::Tick`]; `None` without a callback. The thread
+/// exits once `done` is set or every file is processed; unpark it after setting `done` so it
+/// does not finish out its current wait.
+pub(crate) fn spawn_ticker(tracker: Arc<ProgressTracker>, done: Arc<AtomicBool>) -> Option<JoinHandle<()>> {
+    tracker.callback.as_ref()?;
+    Some(thread::spawn(move || loop {
+        thread::park_timeout(TICK_INTERVAL);
+        if tracker.processed_files() >= tracker.total_files || done.load(Ordering::Relaxed) {
+            break;
+        }
+        tracker.send(ProgressEvent::Tick);
+    }))
+}
+
+/// The progress display of the binary. It renders a live bar when the status stream is a
+/// terminal and otherwise prints a plain status line every two minutes, so redirected output
+/// stays readable.
+pub fn console_progress() -> ProgressCallback {
+    let terminal = status::is_terminal();
+    let tasks: Mutex<HashMap<&'static str, ConsoleTask>> = Mutex::new(HashMap::new());
+    Arc::new(move |event| {
+        let counts = *event.counts();
+        let mut tasks = tasks.lock().unwrap();
+        let task = tasks.entry(counts.task).or_insert_with(|| ConsoleTask::new(&counts, terminal));
+        match (&task.bar, event) {
+            (Some(bar), ProgressEvent::Finished(_)) => bar.finish_and_clear(),
+            (Some(bar), _) => bar.set_position(counts.processed_files as u64),
+            (None, ProgressEvent::Tick(_)) if task.next_report <= Instant::now() => {
+                task.next_report = Instant::now() + STATUS_LINE_INTERVAL;
+                print_status_line(&counts);
+            }
+            (None, _) => {}
+        }
+        if let ProgressEvent::Finished(_) = event {
+            // A later --follow batch of the same task starts a new display
+            tasks.remove(counts.task);
+        }
+    })
+}
+
+struct ConsoleTask {
+    bar: Option<ProgressBar>,
+    next_report: Instant,
+}
+
+impl ConsoleTask {
+    fn new(counts: &ProgressCounts, terminal: bool) -> Self {
+        let bar = terminal.then(|| {
+            let bar = ProgressBar::new(counts.total_files as u64);
+            bar.set_style(
+                ProgressStyle::with_template(
+                    "{prefix} [{bar:40.cyan/blue}] {pos}/{len} ({percent}%) | {per_sec} | 已耗时 {elapsed} | 剩余 {eta}",
+                )
+                .expect("valid progress template")
+                .progress_chars("=> "),
+            );
+            bar.set_prefix(counts.label.to_string());
+            bar
+        });
+        ConsoleTask { bar, next_report: Instant::now() + STATUS_LINE_INTERVAL.saturating_sub(counts.elapsed) }
+    }
+}
+
+fn print_status_line(counts: &ProgressCounts) {
+    let progress_pct = (counts.processed_files as f64 / counts.total_files as f64 * 100.0) as usize;
+    let files_per_sec = if counts.elapsed.as_secs() > 0 {
+        counts.processed_files as f64 / counts.elapsed.as_secs() as f64
+    } else {
+        0.0
+    };
+    status!("{} 进度: {}/{} ({}%) | 速度: {:.2} 文件/秒 | 已耗时: {:?}",
+        counts.label, counts.processed_files, counts.total_files, progress_pct, files_per_sec, counts.elapsed);
+}
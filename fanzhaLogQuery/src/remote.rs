@@ -0,0 +1,61 @@
+use crate::config::Config;
+use anyhow::{anyhow, bail, Result};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use std::io::Read;
+use std::path::Path;
+use std::time::Duration;
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(30);
+/// Longest wait for the next bytes of a response, not for the whole download
+const READ_TIMEOUT: Duration = Duration::from_secs(300);
+/// Largest buffer reserved up front from a `Content-Length` header (1 GB); bigger bodies grow as they arrive
+const MAX_PREALLOCATED_BYTES: usize = 1024 * 1024 * 1024;
+
+/// Whether an input is an HTTP(S) URL from `remoteFiles` rather than a local path.
+pub fn is_remote(path: &Path) -> bool {
+    path.to_str().is_some_and(|path| path.starts_with("http://") || path.starts_with("https://"))
+}
+
+/// Downloads `remoteFiles` straight into memory, where they are searched like a local file
+/// that was read in; nothing is written to local disk.
+pub struct RemoteClient {
+    agent: ureq::Agent,
+    /// `Basic` credentials from `remoteUser` / `remotePassword`
+    authorization: Option<String>,
+}
+
+impl RemoteClient {
+    /// `None` when the config lists no remote files.
+    pub fn from_config(config: &Config) -> Option<Self> {
+        if config.remote_files.is_empty() {
+            return None;
+        }
+        let authorization = config.remote_user.as_ref().map(|user| {
+            let credentials = format!("{}:{}", user, config.remote_password.as_deref().unwrap_or_default());
+            format!("Basic {}", STANDARD.encode(credentials))
+        });
+        let agent = ureq::AgentBuilder::new().timeout_connect(CONNECT_TIMEOUT).timeout_read(READ_TIMEOUT).build();
+        Some(RemoteClient { agent, authorization })
+    }
+
+    /// The whole body of `url`. Responses other than 2xx, and bodies shorter than their
+    /// `Content-Length`, are errors.
+    pub fn fetch(&self, url: &str) -> Result<Vec<u8>> {
+        let mut request = self.agent.get(url);
+        if let Some(authorization) = &self.authorization {
+            request = request.set("Authorization", authorization);
+        }
+        let response = request.call().map_err(|e| match e {
+            ureq::Error::Status(code, response) => anyhow!("HTTP {} {}", code, response.status_text()),
+            ureq::Error::Transport(e) => anyhow!(e),
+        })?;
+        let expected: Option<usize> = response.header("Content-Length").and_then(|len| len.parse().ok());
+        let mut data = Vec::with_capacity(expected.unwrap_or(0).min(MAX_PREALLOCATED_BYTES));
+        response.into_reader().read_to_end(&mut data)?;
+        if let Some(expected) = expected.filter(|&expected| expected != data.len()) {
+            bail!("connection closed after {} of {} bytes", data.len(), expected);
+        }
+        Ok(data)
+    }
+}
@@ -0,0 +1,211 @@
+//! The annotated example config printed by `--print-config-schema`.
+//!
+//! The keys are read from the derived `Deserialize` of [`Config`], so the template lists
+//! exactly the keys a config file accepts, in declaration order; type, default and a one-line
+//! description of each come from [`key_docs`].
+
+use crate::columnar::DEFAULT_ROW_GROUP_ROWS;
+use crate::config::{
+    Config, DEFAULT_CONFIRM_THRESHOLD_BYTES, DEFAULT_IO_CHANNEL_CAPACITY, DEFAULT_MAX_LINE_BYTES, DEFAULT_WORKER_FLUSH_BYTES,
+    DEFAULT_WORKER_FLUSH_INTERVAL_MS, DEFAULT_WRITE_CHANNEL_CAPACITY,
+};
+use crate::output::DEFAULT_WRITER_BUFFER_BYTES;
+use crate::processor::{
+    AGGREGATED_LOG_DOMAIN_INDEX, AGGREGATED_LOG_IP_INDEX, DEFAULT_FIELD_DELIMITER, NATIVE_LOG_DOMAIN_INDEX, NATIVE_LOG_IP_INDEX,
+    NATIVE_LOG_RESOLVED_IP_INDEX,
+};
+use crate::sort::DEFAULT_SORT_BUFFER_BYTES;
+use serde::de::{self, value, Deserialize, Deserializer, Visitor};
+use std::fmt::Write;
+
+/// Keys every config file must set; the template leaves them uncommented.
+const REQUIRED_KEYS: [&str; 2] = ["logDirectory", "isQueryNativeLog"];
+
+/// What the template says about one config key.
+#[derive(Debug, Clone)]
+pub struct KeyDoc {
+    pub key: &'static str,
+    /// Accepted YAML values
+    pub kind: &'static str,
+    /// Value used when the key is left out
+    pub default: String,
+    /// Value written after the key, as YAML
+    pub example: &'static str,
+    pub description: &'static str,
+}
+
+fn doc(key: &'static str, kind: &'static str, default: impl ToString, example: &'static str, description: &'static str) -> KeyDoc {
+    KeyDoc { key, kind, default: default.to_string(), example, description }
+}
+
+/// Names of the keys [`Config`] accepts, as written in a config file.
+pub fn config_keys() -> &'static [&'static str] {
+    let mut fields = StructFields(None);
+    let _ = Config::deserialize(&mut fields);
+    fields.0.expect("Config deserializes from a struct")
+}
+
+/// Captures the field names a derived `Deserialize` passes to `deserialize_struct`, then
+/// stops the deserialization.
+struct StructFields(Option<&'static [&'static str]>);
+
+impl<'de> Deserializer<'de> for &mut StructFields {
+    type Error = value::Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value, Self::Error> {
+        Err(de::Error::custom("not a struct"))
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        fields: &'static [&'static str],
+        _visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        self.0 = Some(fields);
+        Err(de::Error::custom("field names captured"))
+    }
+
+    serde::forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string bytes byte_buf option unit
+        unit_struct newtype_struct seq tuple tuple_struct map enum identifier ignored_any
+    }
+}
+
+/// Type, default, example and description of each config key.
+pub fn key_docs() -> Vec<KeyDoc> {
+    let none = "无";
+    let off = "false";
+    vec![
+        doc("logDirectory", "字符串", "(必填)", "\"/data/alipms/dns/fanzhaDnsLog_all/\"", "汇总日志文件根目录, 支持通配符 (如 \"/data/site-*/dns/\")"),
+        doc("followSymlinks", "布尔", off, "true", "遍历目录时跟随符号链接; 同一个文件只处理一次"),
+        doc("maxDepth", "整数", "不限制", "2", "目录遍历的最大深度, 直接位于根目录下的文件深度为 1"),
+        doc("pruneDirectories", "字符串或字符串列表", none, "[\"tmp\", \".*\"]", "遍历时跳过的目录名 (glob 模式), 连同其子目录"),
+        doc("remoteFiles", "字符串或字符串列表", none, "[\"https://objects.internal/dns/20250626/fanzha_00.log.gz\"]", "通过 HTTP(S) 读取的汇总日志文件, 与 logDirectory 下的文件一起检索"),
+        doc("remoteUser", "字符串", none, "\"reader\"", "remoteFiles 的 HTTP Basic 认证用户名"),
+        doc("remotePassword", "字符串", none, "\"secret\"", "remoteFiles 的 HTTP Basic 认证密码, 也可用环境变量 DNSLOG_REMOTE_PASSWORD"),
+        doc("workerPoolSize", "整数", "CPU 核心数", "8", "计算线程数"),
+        doc("aggregatedWorkerPoolSize", "整数", "workerPoolSize", "4", "汇总日志任务的计算线程数"),
+        doc("nativeWorkerPoolSize", "整数", "workerPoolSize", "8", "原始日志任务的计算线程数"),
+        doc("autoScaleWorkers", "布尔", off, "true", "从 2 个计算线程开始, 读取队列持续排满时逐个增加到 workerPoolSize"),
+        doc("executionBackend", "threads | rayon", "threads", "rayon", "计算调度方式: 单 IO 线程 + 固定计算线程, 或 rayon 工作窃取"),
+        doc("splitGzipMinBytes", "整数 (字节)", "不切分", "536870912", "不小于该大小的多成员 gzip 文件按成员切分后并行解压"),
+        doc("mmapInputs", "布尔", off, "true", "用内存映射代替整体读入内存访问输入文件"),
+        doc("coreIds", "整数列表或范围字符串", "不绑定", "\"8-23,40-55\"", "计算线程绑定的 CPU 核心, 第 i 个线程绑定第 i 个核心"),
+        doc("queryDomain", "字符串或字符串列表", none, "[\"www.test.com\", \"*.test.com\"]", "域名规则: 精确、通配 (*)、正则 (re:)、排除 (!)"),
+        doc("sourceIP", "字符串或字符串列表", none, "[\"192.168.1.5\", \"192.168.2.0/24\"]", "源IP规则: 精确IP、网段、IP范围 (a-b)"),
+        doc("stripTrailingDot", "布尔", "true", "false", "匹配前去掉域名末尾的一个点"),
+        doc("idnNormalize", "布尔", off, "true", "Unicode 域名与规则统一转换为 punycode 后比较"),
+        doc("domainAnyField", "布尔", off, "true", "用 queryDomain 规则检查每一行的所有字段, 任一字段命中即可"),
+        doc("minDomainLabels", "整数", "不限制", "6", "域名层级数下限"),
+        doc("maxDomainLabels", "整数", "不限制", "20", "域名层级数上限"),
+        doc("matchRegistrableDomain", "布尔", off, "true", "按可注册域名 (eTLD+1) 匹配 queryDomain 规则"),
+        doc("publicSuffixList", "字符串", crate::suffix::DEFAULT_PUBLIC_SUFFIX_LIST, "\"/usr/share/publicsuffix/public_suffix_list.dat\"", "matchRegistrableDomain 使用的公共后缀列表文件"),
+        doc("queryDomainFile", "字符串", none, "\"/etc/dnslog/domains.txt\"", "追加 queryDomain 规则的文件, 每行一条"),
+        doc("sourceIPFile", "字符串", none, "\"/etc/dnslog/ips.txt\"", "追加 sourceIP 规则的文件, 每行一条"),
+        doc("excludeSourceIP", "字符串或字符串列表", none, "[\"192.168.2.10\"]", "排除的源IP, 格式同 sourceIP"),
+        doc("geoDatabase", "字符串", none, "\"/usr/share/GeoIP/GeoLite2-Country.mmdb\"", "sourceCountry / excludeCountry 使用的 MaxMind 数据库 (.mmdb)"),
+        doc("sourceCountry", "字符串或字符串列表", none, "[\"US\", \"RU\"]", "只保留这些国家 (ISO 3166 两位代码) 的源IP"),
+        doc("excludeCountry", "字符串或字符串列表", none, "[\"CN\"]", "排除这些国家的源IP"),
+        doc("resolvedIP", "字符串或字符串列表", none, "[\"203.0.113.0/24\"]", "解析结果IP规则, 格式同 sourceIP"),
+        doc("ipFieldEncoding", "dotted | uint32", "dotted", "uint32", "源IP字段的写法: 文本或十进制整数 (IPv4)"),
+        doc("stripSourcePort", "布尔", off, "true", "匹配前去掉源IP字段中的端口"),
+        doc("sourcePort", "整数或整数列表", none, "[53, 5353]", "按源端口过滤, 不带端口的记录不会命中"),
+        doc("queryTime_hour", "字符串列表 (YYYYMMDDHH)", none, "[\"2025062619\"]", "查询的小时, 与 queryTime_day 不能同时为空"),
+        doc("queryTime_day", "字符串列表 (YYYYMMDD)", none, "[\"20250626\"]", "查询的日期, 与 queryTime_hour 不能同时为空"),
+        doc("queryTimeStart", "字符串 (YYYYMMDDHHMMSS)", none, "\"20251209143000\"", "原始日志时间窗口起点 (含)"),
+        doc("queryTimeEnd", "字符串 (YYYYMMDDHHMMSS)", none, "\"20251209154500\"", "原始日志时间窗口终点 (含)"),
+        doc("isQueryNativeLog", "\"yes\" | \"no\"", "(必填)", "\"yes\"", "是否同时检索原始日志"),
+        doc("nativeLogLoc", "字符串", none, "\"/data/dnsLog_bakup/\"", "原始日志文件存放目录"),
+        doc("nativeFileTimestampRegex", "字符串 (正则)", "按 \"_\" 分隔取第 3 段", "'^dns-(?P<ts>\\d{8})'", "从原始日志文件名提取时间戳的正则, 用 ts 分组捕获"),
+        doc("nativeLogResultLoc", "字符串", "自动生成", "\"./\"", "原始日志结果目录, \"-\" 为标准输出"),
+        doc("aggregatedLogResultLoc", "字符串", "自动生成", "\"./\"", "汇总日志结果目录, \"-\" 为标准输出"),
+        doc("aggregatedIpIndex", "整数", AGGREGATED_LOG_IP_INDEX, "0", "汇总日志源IP的字段序号 (从 0 开始)"),
+        doc("aggregatedDomainIndex", "整数", AGGREGATED_LOG_DOMAIN_INDEX, "1", "汇总日志域名的字段序号"),
+        doc("nativeIpIndex", "整数", NATIVE_LOG_IP_INDEX, "4", "原始日志源IP的字段序号"),
+        doc("resolvedIpIndex", "整数", NATIVE_LOG_RESOLVED_IP_INDEX, "9", "原始日志解析结果IP的字段序号"),
+        doc("aggregatedResolvedIpIndex", "整数", none, "3", "汇总日志解析结果IP的字段序号"),
+        doc("nativeDomainIndex", "整数", NATIVE_LOG_DOMAIN_INDEX, "7", "原始日志域名的字段序号"),
+        doc("fieldDelimiter", "单个字符", format!("\"{}\"", DEFAULT_FIELD_DELIMITER as char), "\"|\"", "字段分隔符, 制表符写作 \"\\t\""),
+        doc("autoDetectDelimiter", "布尔", off, "true", "按每个文件开头几行自动选择字段分隔符 (| 制表符 空格 ,), 无法确定时使用 fieldDelimiter"),
+        doc("logFormat", "delimited | json", "delimited", "json", "日志格式: 按分隔符拆分, 或每行一个 JSON 对象"),
+        doc("ipField", "字符串", none, "\"src\"", "logFormat json 时源IP的键名"),
+        doc("domainField", "字符串", none, "\"qname\"", "logFormat json 时域名的键名"),
+        doc("resolvedIpField", "字符串", none, "\"answer\"", "logFormat json 时解析结果IP的键名"),
+        doc("quotedFields", "布尔", off, "true", "字段可能带双引号 (CSV 风格), 引号内的分隔符不拆分"),
+        doc("fieldFilters", "字段序号 -> 字符串或字符串列表", none, "{8: [\"TXT\", \"NULL\"]}", "附加字段过滤: 字段须等于列出的取值之一, 两个任务都生效"),
+        doc("aggregatedFieldFilters", "字段序号 -> 字符串或字符串列表", "fieldFilters", "{2: \"A\"}", "汇总日志单独的附加字段过滤"),
+        doc("nativeFieldFilters", "字段序号 -> 字符串或字符串列表", "fieldFilters", "{8: [\"TXT\", \"NULL\"]}", "原始日志单独的附加字段过滤"),
+        doc("numericFieldFilters", "{index, min, max} 列表", none, "[{index: 11, min: 500}]", "数值范围过滤: 字段按数字解析后须在 [min, max] 之内"),
+        doc("aggregatedNumericFieldFilters", "{index, min, max} 列表", "numericFieldFilters", "[{index: 3, max: 100}]", "汇总日志单独的数值范围过滤"),
+        doc("nativeNumericFieldFilters", "{index, min, max} 列表", "numericFieldFilters", "[{index: 11, min: 500}]", "原始日志单独的数值范围过滤"),
+        doc("expectedFieldCount", "整数", "不检查", "12", "每行应有的字段数, 字段数不等的行写入 malformed_lines 文件"),
+        doc("aggregatedExpectedFieldCount", "整数", "expectedFieldCount", "5", "汇总日志每行应有的字段数"),
+        doc("nativeExpectedFieldCount", "整数", "expectedFieldCount", "12", "原始日志每行应有的字段数"),
+        doc("fileExtensions", "字符串或字符串列表", "[\".gz\"]", "[\".gz\", \".zst\", \".bz2\", \".log\"]", "待检索的日志文件后缀"),
+        doc("excludeFileRegex", "字符串 (正则)", none, "'\\.meta\\.gz$'", "按文件名排除的正则"),
+        doc("fileOrder", "name | mtime | timestamp", "name", "timestamp", "文件处理顺序: 按路径, 或按修改时间 / 路径中的时间从新到旧"),
+        doc("strictInputs", "布尔", off, "true", "遇到无效或读取失败的输入文件时以退出码 1 报错"),
+        doc("countOnly", "布尔", off, "true", "仅统计匹配条数, 不写出结果文件"),
+        doc("outputFormat", "raw | jsonl | parquet", "raw", "jsonl", "结果输出格式"),
+        doc("parquetColumns", "{name, index, type} 列表", none, "[{name: time, index: 2, type: timestamp}]", "outputFormat parquet 时附加的列, type 为 string / int64 / float64 / timestamp"),
+        doc("parquetRowGroupRows", "整数", DEFAULT_ROW_GROUP_ROWS, "100000", "Parquet 每个行组的行数"),
+        doc("lineTerminator", "lf | crlf", "lf", "crlf", "结果行的换行符"),
+        doc("outputFields", "整数列表", "整行", "[0, 1]", "只输出这些序号的字段"),
+        doc("prependSourceFile", "布尔", off, "true", "在每条结果前加上来源日志文件的路径"),
+        doc("prependSourceTimestamp", "布尔", off, "true", "在来源路径后再加上文件的时间, 需要 prependSourceFile"),
+        doc("normalizeTimestampField", "整数", none, "2", "将该序号的 Unix 时间戳字段改写为 RFC 3339 (UTC) 后输出"),
+        doc("normalizeDomainCase", "布尔", off, "true", "输出前将域名字段改为小写"),
+        doc("outputCompression", "none | gzip", "none", "gzip", "结果文件压缩方式"),
+        doc("outputEncryption", "布尔", off, "true", "用 ChaCha20-Poly1305 加密结果文件, 用 --decrypt 还原"),
+        doc("outputPassphraseFile", "字符串", "环境变量 DNSLOG_OUTPUT_PASSPHRASE", "\"/etc/dnslog/passphrase\"", "outputEncryption 的口令文件 (取第一行)"),
+        doc("outputToStdout", "布尔", off, "true", "两个任务的结果都输出到标准输出"),
+        doc("filterLogic", "and | or", "and", "or", "域名、源IP与解析结果IP条件的组合方式"),
+        doc("samplePercent", "小数 (0.01 ~ 100)", "不抽样", "1", "只保留满足条件的记录中约该百分比的部分"),
+        doc("sampleKey", "line | sourceIP | domain", "line", "sourceIP", "抽样时按哪个值的哈希决定是否保留"),
+        doc("outputMaxBytes", "整数 (字节)", "不拆分", "1073741824", "单个结果文件的最大字节数, 超过后切换到新的编号文件"),
+        doc("outputAppend", "布尔", off, "true", "追加到已有的结果文件末尾"),
+        doc("onExistingOutput", "overwrite | error | timestamp", "overwrite", "error", "结果目录中已有结果文件时的处理方式"),
+        doc("ruleMatchCounts", "布尔", off, "true", "按规则统计命中数"),
+        doc("sortByTimestampField", "整数", "不排序", "2", "任务结束后按该序号的时间字段对结果文件排序"),
+        doc("sortBufferBytes", "整数 (字节)", DEFAULT_SORT_BUFFER_BYTES, "268435456", "排序时在内存中缓存的数据量, 超过后分段归并"),
+        doc("maxLineBytes", "整数 (字节)", DEFAULT_MAX_LINE_BYTES, "1048576", "单行最大字节数, 超过的行视为损坏记录跳过"),
+        doc("confirmLargeRuns", "布尔", off, "true", "输入总大小超过 confirmThresholdBytes 时先询问是否继续"),
+        doc("confirmThresholdBytes", "整数 (字节)", DEFAULT_CONFIRM_THRESHOLD_BYTES, "500000000000", "confirmLargeRuns 询问的阈值 (压缩后大小)"),
+        doc("splitByDomainRule", "布尔", off, "true", "按命中的域名规则拆分结果文件"),
+        doc("maxMatches", "整数", "不限制", "1000", "每个任务最多保留的匹配条数"),
+        doc("perFileReport", "布尔", off, "true", "在结果目录中写出逐文件统计 CSV"),
+        doc("dedupe", "布尔", off, "true", "完全相同的匹配行只输出一次"),
+        doc("dedupeScope", "global | worker", "global", "worker", "去重范围: 所有线程共享, 或每个线程各自去重"),
+        doc("quiet", "布尔", off, "true", "不输出进度与统计信息, 等同命令行 --quiet"),
+        doc("reportPeakMemory", "布尔", off, "true", "运行结束时输出进程内存峰值 (仅 Linux)"),
+        doc("ioChannelCapacity", "整数", DEFAULT_IO_CHANNEL_CAPACITY, "4", "读取线程预读的文件数"),
+        doc("writeChannelCapacity", "整数", DEFAULT_WRITE_CHANNEL_CAPACITY, "1024", "写入线程的缓冲队列长度"),
+        doc("workerFlushBytes", "整数 (字节)", DEFAULT_WORKER_FLUSH_BYTES, "131072", "工作线程本地结果缓冲达到该大小后交给写入线程"),
+        doc("workerFlushIntervalMs", "整数 (毫秒)", DEFAULT_WORKER_FLUSH_INTERVAL_MS, "500", "按时间刷新工作线程与写入线程缓冲的间隔, 0 为不按时间刷新"),
+        doc("writerBufferBytes", "整数 (字节)", DEFAULT_WRITER_BUFFER_BYTES, "1048576", "每个结果文件的写缓冲大小"),
+        doc("profiles", "{name, queryDomain, sourceIP, ...} 列表", "不启用", "[{name: phishing, queryDomain: [\"*.evil.com\"]}]", "多个查询配置, 每个输入文件只读取一次"),
+    ]
+}
+
+/// An example `config.yaml` listing every key with its type, default and description. Keys
+/// other than the required ones are commented out, to be enabled as needed.
+pub fn config_template() -> String {
+    let docs = key_docs();
+    let mut out = String::new();
+    let _ = writeln!(out, "# fanzha_log_query 配置模板 (由 --print-config-schema 生成), 列出所有支持的配置项");
+    let _ = writeln!(out, "# 每项依次为: 类型与默认值、说明、示例; 除必填项外均已注释, 去掉行首 \"# \" 即可启用");
+    let _ = writeln!(out, "# 部分配置项可用 DNSLOG_ 开头的环境变量覆盖, 详见 rust_walkthrough.md");
+    for key in config_keys() {
+        let _ = writeln!(out);
+        let Some(doc) = docs.iter().find(|doc| doc.key == *key) else {
+            let _ = writeln!(out, "# {}: (暂无说明)", key);
+            continue;
+        };
+        let _ = writeln!(out, "# {} ({}, 默认: {})", doc.key, doc.kind, doc.default);
+        let _ = writeln!(out, "# {}", doc.description);
+        let comment = if REQUIRED_KEYS.contains(key) { "" } else { "# " };
+        let _ = writeln!(out, "{}{}: {}", comment, doc.key, doc.example);
+    }
+    out
+}
@@ -0,0 +1,2072 @@
+use crate::config::{parse_native_timestamp, Config, TimeRange};
+use crate::dedupe::{DedupeScope, Deduper, SharedSeen};
+use crate::geo::CountryFilter;
+use crate::failures::{print_failure_summary, FailureKind, FailureLog, FileFailure};
+use crate::manifest::{input_files, write_manifests, write_per_file_report, FileReport, RuleCounts, TaskRecord};
+use crate::matcher::{idn_to_ascii, DomainMatcher, IPMatcher};
+use crate::memory::MemorySampler;
+use crate::output::{
+    write_record, ExistingOutput, FileChecksum, OutputEncoding, OutputFormat, PartitionedWriter, RecordOptions, ResultSink, ResultWriter, SourceFile, WriteSummary,
+};
+use crate::processor::{gzip_member_ranges, Compression, FileProcessor, MatchedLine, ProcessStats};
+use crate::progress::{spawn_ticker, ProgressCallback, ProgressTracker};
+use crate::remote::{is_remote, RemoteClient};
+use crate::sort::{sort_result_file, SortOptions};
+use crate::suffix::{RegistrableDomains, DEFAULT_PUBLIC_SUFFIX_LIST};
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, Local, NaiveDateTime};
+use rayon::prelude::*;
+use regex::Regex;
+use serde::Deserialize;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader, BufWriter, ErrorKind, IsTerminal, Read, Write};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::time::{Duration, Instant, SystemTime};
+use std::thread;
+use walkdir::WalkDir;
+use crossbeam_channel::{bounded, RecvTimeoutError, Sender};
+use memmap2::Mmap;
+
+/// Set by [`request_shutdown`]; the IO thread and workers stop picking up new files once set.
+static SHUTDOWN: AtomicBool = AtomicBool::new(false);
+
+/// Asks a running query to stop after the files in hand, keeping the results saved so far.
+/// Returns whether a shutdown had already been requested.
+pub fn request_shutdown() -> bool {
+    SHUTDOWN.swap(true, Ordering::SeqCst)
+}
+
+pub fn shutdown_requested() -> bool {
+    SHUTDOWN.load(Ordering::SeqCst)
+}
+
+/// Outcome of [`run_query`].
+#[derive(Debug)]
+pub struct QueryReport {
+    /// One entry per task that found input files
+    pub tasks: Vec<TaskRecord>,
+    /// `manifest.json` files written next to the results
+    pub manifests: Vec<PathBuf>,
+    pub interrupted: bool,
+    pub elapsed: Duration,
+    /// Largest resident memory of the process during the run, with `reportPeakMemory`
+    pub peak_memory_bytes: Option<u64>,
+}
+
+impl QueryReport {
+    pub fn total_matches(&self) -> usize {
+        self.tasks.iter().map(|t| t.matches).sum()
+    }
+
+    pub fn total_lines(&self) -> usize {
+        self.tasks.iter().map(|t| t.lines_scanned).sum()
+    }
+
+    pub fn failed_files(&self) -> usize {
+        self.tasks.iter().map(|t| t.failed_files).sum()
+    }
+
+    /// Process exit code for this outcome, see [`exit_code`].
+    pub fn exit_code(&self) -> u8 {
+        exit_code(self.total_matches(), self.failed_files(), self.interrupted)
+    }
+}
+
+/// Exit code of a run that found matches and read every file.
+pub const EXIT_MATCHES: u8 = 0;
+/// Exit code of a complete run that found nothing.
+pub const EXIT_NO_MATCHES: u8 = 3;
+/// Exit code when some input files could not be read to the end, whether or not anything matched.
+pub const EXIT_PARTIAL_FAILURE: u8 = 4;
+/// Exit code when the run was stopped with Ctrl-C; the saved results are incomplete.
+pub const EXIT_INTERRUPTED: u8 = 130;
+
+/// Maps a run outcome to the binary's exit code. Fatal errors exit with 1 and usage errors
+/// with 2 before any of these apply. Interruption wins over partial failure, which wins over
+/// the match count.
+pub fn exit_code(matches: usize, failed_files: usize, interrupted: bool) -> u8 {
+    if interrupted {
+        EXIT_INTERRUPTED
+    } else if failed_files > 0 {
+        EXIT_PARTIAL_FAILURE
+    } else if matches == 0 {
+        EXIT_NO_MATCHES
+    } else {
+        EXIT_MATCHES
+    }
+}
+
+/// Builds the shared matcher set described by the config.
+pub fn build_processor(config: &Config) -> Result<FileProcessor> {
+    let ip_encoding = config.ip_field_encoding.unwrap_or_default();
+    let mut ip_matcher = IPMatcher::with_encoding(&config.source_ip, &config.exclude_source_ip, ip_encoding)
+        .context("invalid sourceIP / excludeSourceIP")?
+        .with_ports(config.strip_source_port.unwrap_or(false), &config.source_port);
+    if let Some(database) = &config.geo_database {
+        ip_matcher = ip_matcher.with_countries(CountryFilter::open(database, &config.source_country, &config.exclude_country)?);
+    }
+    // Rules get the same normalization as the domain field
+    let domain_rules: Vec<String> = config.query_domain.iter().map(|rule| normalize_domain_rule(rule, config)).collect();
+    let mut domain_matcher = DomainMatcher::new(&domain_rules)?;
+    if config.match_registrable_domain.unwrap_or(false) {
+        let list = config.public_suffix_list.as_deref().unwrap_or(DEFAULT_PUBLIC_SUFFIX_LIST);
+        domain_matcher = domain_matcher.with_registrable_domains(RegistrableDomains::open(list)?);
+    }
+    let resolved_ip_matcher = IPMatcher::new(&config.resolved_ip, &[]).context("invalid resolvedIP")?;
+    Ok(FileProcessor::new(
+        ip_matcher,
+        domain_matcher,
+        config.aggregated_layout(),
+        config.native_layout(),
+        config.match_options(),
+    )
+    .with_resolved_ip_matcher(resolved_ip_matcher))
+}
+
+/// Applies `stripTrailingDot` (one trailing dot, `example.com.`) and `idnNormalize` (Unicode
+/// labels to punycode) to a plain or wildcard rule; `re:` rules are left as written.
+fn normalize_domain_rule(rule: &str, config: &Config) -> String {
+    let (negation, pattern) = match rule.strip_prefix('!') {
+        Some(pattern) => ("!", pattern),
+        None => ("", rule),
+    };
+    if pattern.starts_with("re:") {
+        return rule.to_string();
+    }
+    let mut pattern = pattern;
+    if config.strip_trailing_dot() {
+        pattern = pattern.strip_suffix('.').unwrap_or(pattern);
+    }
+    if config.idn_normalize.unwrap_or(false) && !pattern.is_ascii() {
+        match idn_to_ascii(pattern.as_bytes()).and_then(|ace| String::from_utf8(ace).ok()) {
+            Some(ace) => return format!("{}{}", negation, ace),
+            None => eprintln!("Warning: domain rule {:?} is not a valid IDN, comparing it as written", rule),
+        }
+    }
+    format!("{}{}", negation, pattern)
+}
+
+/// Runs the aggregated-log task and, when `isQueryNativeLog` is "yes", the native-log task,
+/// then writes the run manifests. `progress` hears about every completed input file; the
+/// binary passes [`console_progress`](crate::progress::console_progress).
+pub fn run_query(config: &Config, progress: Option<ProgressCallback>) -> Result<QueryReport> {
+    let start_time = Instant::now();
+    let started_at = Local::now();
+    let config = &config.with_run_stamp(&started_at);
+    check_existing_outputs(config)?;
+    warn_missing_cores(config);
+    let sampler = start_memory_sampler(config);
+    let profiles = Profiles::from_config(config)?.map(Arc::new);
+    // With profiles every line is read once and the profiles do the filtering
+    let processor = match &profiles {
+        Some(_) => Arc::new(build_processor(&config.without_filters())?),
+        None => Arc::new(build_processor(config)?),
+    };
+    let mut records = run_tasks(config, &processor, profiles.as_ref(), progress.as_ref(), None)?;
+
+    if let Some(options) = config.sort_options() {
+        if SHUTDOWN.load(Ordering::SeqCst) {
+            status!("已中断，跳过结果排序。");
+        } else {
+            sort_results(&mut records, &options)?;
+        }
+    }
+
+    // Written only now so the manifest covers both tasks; the result files are already closed
+    let manifests = save_manifests(config, profiles.as_deref(), started_at, &records)?;
+    print_failure_summary(&records);
+
+    Ok(QueryReport {
+        tasks: records,
+        manifests,
+        interrupted: SHUTDOWN.load(Ordering::SeqCst),
+        elapsed: start_time.elapsed(),
+        peak_memory_bytes: finish_memory_sampler(sampler),
+    })
+}
+
+/// `onExistingOutput: error`: fails before anything is read when a result directory of this
+/// run already holds a result file, manifest, per-file report or malformed-line file.
+fn check_existing_outputs(config: &Config) -> Result<()> {
+    if config.on_existing_output != Some(ExistingOutput::Error) || config.count_only.unwrap_or(false) {
+        return Ok(());
+    }
+    let configs = if config.profiles.is_empty() { vec![config.clone()] } else { config.profile_configs() };
+    let mut tasks = vec![TaskKind::Aggregated];
+    if config.is_query_native_log.to_lowercase() == "yes" {
+        tasks.push(TaskKind::Native);
+    }
+    for config in &configs {
+        for &task in &tasks {
+            if config.result_loc_is_stdout(task == TaskKind::Aggregated) {
+                continue;
+            }
+            let output_path = get_output_path(config, task.output_name(), task == TaskKind::Aggregated);
+            let Some(dir) = output_path.parent() else { continue };
+            let Ok(entries) = fs::read_dir(dir) else { continue };
+            // Prefixes also cover the numbered parts of outputMaxBytes and the splitByDomainRule files
+            let prefixes = [
+                format!("matched_{}_logs", task.output_name()),
+                format!("per_file_report_{}", task.output_name()),
+                format!("malformed_lines_{}", task.output_name()),
+                "manifest".to_string(),
+            ];
+            for entry in entries.flatten() {
+                let name = entry.file_name();
+                if prefixes.iter().any(|prefix| name.to_string_lossy().starts_with(prefix.as_str())) {
+                    bail!(
+                        "{} already exists and onExistingOutput is error; move the earlier results away or use onExistingOutput timestamp",
+                        entry.path().display()
+                    );
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Warns about `coreIds` this machine does not have; workers given one of them run unbound.
+fn warn_missing_cores(config: &Config) {
+    let Some(requested) = config.core_ids.as_ref().filter(|ids| !ids.is_empty()) else {
+        return;
+    };
+    let Some(available) = core_affinity::get_core_ids() else {
+        eprintln!("Warning: cannot list the CPU cores of this machine, coreIds is ignored");
+        return;
+    };
+    let missing: Vec<String> = requested
+        .iter()
+        .filter(|&&id| !available.iter().any(|core| core.id == id))
+        .map(usize::to_string)
+        .collect();
+    if !missing.is_empty() {
+        eprintln!(
+            "Warning: coreIds {} do not exist on this machine ({} cores), workers given them are not bound",
+            missing.join(","),
+            available.len()
+        );
+    }
+}
+
+/// Starts the `reportPeakMemory` sampler, warning where memory cannot be measured.
+fn start_memory_sampler(config: &Config) -> Option<MemorySampler> {
+    if !config.report_peak_memory.unwrap_or(false) {
+        return None;
+    }
+    let sampler = MemorySampler::start();
+    if sampler.is_none() {
+        eprintln!("Warning: reportPeakMemory needs /proc/self/status, peak memory will not be reported");
+    }
+    sampler
+}
+
+fn finish_memory_sampler(sampler: Option<MemorySampler>) -> Option<u64> {
+    let peak = sampler?.finish();
+    status!("内存峰值 (RSS): {}", format_bytes(peak));
+    Some(peak)
+}
+
+/// How often `--follow` looks for new input files when no interval is given.
+pub const DEFAULT_FOLLOW_INTERVAL: Duration = Duration::from_secs(10);
+
+/// [`run_query`], then keeps watching the input directories until [`request_shutdown`]
+/// (`--follow`). Every `interval` the inputs are listed again; a file that appeared since is
+/// searched once its size and modification time have stayed the same over one interval, so a
+/// rotation still being written is not read half-way. Matches of each batch are appended to
+/// the result files of the first pass, and the manifest is rewritten after every batch with
+/// one task entry per batch. Stopping while idle is not an interruption.
+pub fn run_follow_query(config: &Config, interval: Duration, progress: Option<ProgressCallback>) -> Result<QueryReport> {
+    if config.sort_options().is_some() {
+        bail!("sortByTimestampField cannot be combined with --follow");
+    }
+    if config.output_max_bytes.is_some() {
+        bail!("outputMaxBytes cannot be combined with --follow");
+    }
+    if !config.profiles.is_empty() {
+        bail!("profiles cannot be combined with --follow");
+    }
+    if config.output_format.unwrap_or_default() == OutputFormat::Parquet {
+        bail!("outputFormat parquet cannot be combined with --follow");
+    }
+    let start_time = Instant::now();
+    let started_at = Local::now();
+    let config = &config.with_run_stamp(&started_at);
+    check_existing_outputs(config)?;
+    warn_missing_cores(config);
+    let sampler = start_memory_sampler(config);
+    let processor = Arc::new(build_processor(config)?);
+    let mut watch = FollowWatch::default();
+    let mut records = run_tasks(config, &processor, None, progress.as_ref(), Some(&mut watch))?;
+    let mut manifests = save_manifests(config, None, started_at, &records)?;
+
+    let mut tasks = vec![TaskKind::Aggregated];
+    if config.is_query_native_log.to_lowercase() == "yes" {
+        tasks.push(TaskKind::Native);
+    }
+    if !SHUTDOWN.load(Ordering::SeqCst) {
+        status!("\n跟踪模式: 每 {:?} 检查一次新文件，按 Ctrl-C 结束。", interval);
+    }
+    while sleep_unless_shutdown(interval) {
+        let mut searched = false;
+        for &task in &tasks {
+            let files = watch.ready(task, list_inputs(config, task)?);
+            if files.is_empty() || SHUTDOWN.load(Ordering::SeqCst) {
+                continue;
+            }
+            status!("\n{}: 发现 {} 个新的{}文件，追加检索结果...", task.label(), files.len(), task.log_name());
+            records.extend(run_search_pipeline(config, &processor, None, progress.as_ref(), task, files, true)?);
+            searched = true;
+        }
+        if searched {
+            manifests = save_manifests(config, None, started_at, &records)?;
+        }
+    }
+    print_failure_summary(&records);
+
+    Ok(QueryReport {
+        interrupted: records.iter().any(|record| record.interrupted),
+        tasks: records,
+        manifests,
+        elapsed: start_time.elapsed(),
+        peak_memory_bytes: finish_memory_sampler(sampler),
+    })
+}
+
+/// Sleeps for `interval`, waking early on shutdown. Returns false once a shutdown was requested.
+fn sleep_unless_shutdown(interval: Duration) -> bool {
+    let deadline = Instant::now() + interval;
+    while !SHUTDOWN.load(Ordering::SeqCst) {
+        let now = Instant::now();
+        if now >= deadline {
+            return true;
+        }
+        thread::sleep((deadline - now).min(Duration::from_millis(200)));
+    }
+    false
+}
+
+/// Input files `--follow` knows about, per task.
+#[derive(Default)]
+pub struct FollowWatch {
+    /// Files searched already, including everything the first pass listed
+    seen: HashSet<(TaskKind, PathBuf)>,
+    /// New files found on the last poll, with their size and modification time then
+    pending: HashMap<(TaskKind, PathBuf), (u64, Option<SystemTime>)>,
+}
+
+impl FollowWatch {
+    pub fn mark_seen(&mut self, task: TaskKind, files: &[PathBuf]) {
+        self.seen.extend(files.iter().map(|path| (task, path.clone())));
+    }
+
+    /// The files of `listed` not searched yet whose size and modification time match the
+    /// previous poll. Empty files are left waiting for their first write.
+    pub fn ready(&mut self, task: TaskKind, listed: Vec<PathBuf>) -> Vec<PathBuf> {
+        let mut ready = Vec::new();
+        for path in listed {
+            let key = (task, path);
+            if self.seen.contains(&key) {
+                continue;
+            }
+            let Ok(metadata) = fs::metadata(&key.1) else {
+                continue;
+            };
+            let state = (metadata.len(), metadata.modified().ok());
+            if state.0 > 0 && self.pending.get(&key) == Some(&state) {
+                self.pending.remove(&key);
+                ready.push(key.1.clone());
+                self.seen.insert(key);
+            } else {
+                self.pending.insert(key, state);
+            }
+        }
+        ready
+    }
+}
+
+/// Runs the aggregated-log task and, when `isQueryNativeLog` is "yes", the native-log task.
+/// With `watch`, the files each task listed are recorded as seen for `--follow`.
+fn run_tasks(
+    config: &Config,
+    processor: &Arc<FileProcessor>,
+    profiles: Option<&Arc<Profiles>>,
+    progress: Option<&ProgressCallback>,
+    mut watch: Option<&mut FollowWatch>,
+) -> Result<Vec<TaskRecord>> {
+    let mut records = Vec::new();
+
+    // Task 1: Aggregated Logs
+    records.extend(run_task(config, processor, profiles, progress, TaskKind::Aggregated, watch.as_deref_mut())?);
+
+    // Task 2: Native Logs
+    if SHUTDOWN.load(Ordering::SeqCst) {
+        status!("已中断，跳过原始日志检索。");
+    } else if config.is_query_native_log.to_lowercase() == "yes" {
+        records.extend(run_task(config, processor, profiles, progress, TaskKind::Native, watch)?);
+    } else {
+        status!("配置中 'isQueryNativeLog' 为 'no'，跳过原始日志检索。");
+    }
+    Ok(records)
+}
+
+/// With profiles each profile's results get a manifest of their own, listing its filters.
+fn save_manifests(
+    config: &Config,
+    profiles: Option<&Profiles>,
+    started_at: DateTime<Local>,
+    records: &[TaskRecord],
+) -> Result<Vec<PathBuf>> {
+    let manifests = match profiles {
+        None => write_manifests(config, started_at, Local::now(), records)?,
+        Some(profiles) => {
+            let mut manifests = Vec::new();
+            for profile in &profiles.configs {
+                let tasks = records.iter().filter(|record| record.profile == profile.profile_name);
+                manifests.extend(write_manifests(profile, started_at, Local::now(), tasks)?);
+            }
+            manifests
+        }
+    };
+    for path in &manifests {
+        status!("运行清单已保存: {}", path.display());
+    }
+    Ok(manifests)
+}
+
+/// Second pass for `sortByTimestampField`: sorts every result file the tasks wrote, replacing
+/// their checksums with those of the sorted files.
+fn sort_results(records: &mut [TaskRecord], options: &SortOptions) -> Result<()> {
+    for record in records {
+        let files = if record.split_outputs.is_empty() {
+            record.output.iter().cloned().collect::<Vec<_>>()
+        } else {
+            record.split_outputs.clone()
+        };
+        for path in files {
+            let sort_time = Instant::now();
+            let (lines, sha256) = sort_result_file(&path, options)?;
+            status!("结果已按时间字段排序: {} ({} 行, 耗时 {:?})", path.display(), lines, sort_time.elapsed());
+            status!("排序后 SHA-256 {}  {}", sha256, path.display());
+            match record.output_sha256.iter_mut().find(|checksum| checksum.path == path) {
+                Some(checksum) => checksum.sha256 = sha256,
+                None => record.output_sha256.push(FileChecksum { path, sha256 }),
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Filters stdin as a single aggregated-layout stream and writes matches to stdout.
+pub fn run_stdin_query(config: &Config) -> Result<ProcessStats> {
+    let sampler = start_memory_sampler(config);
+    let processor = build_processor(config)?;
+    let stats = run_stdin_search(config, &processor)?;
+    finish_memory_sampler(sampler);
+    Ok(stats)
+}
+
+/// Workers started when `autoScaleWorkers` is on; more are added up to `workerPoolSize`.
+const AUTO_SCALE_INITIAL_WORKERS: usize = 2;
+const AUTO_SCALE_SAMPLE_INTERVAL: Duration = Duration::from_millis(250);
+/// Consecutive full-channel samples before another worker is added.
+const AUTO_SCALE_FULL_SAMPLES: u32 = 2;
+
+/// How the compute side of a search task is scheduled (`executionBackend`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ExecutionBackend {
+    /// A sequential IO thread feeding a fixed pool of worker threads; kindest to HDDs.
+    #[default]
+    Threads,
+    /// Files handed to a rayon work-stealing pool, which keeps all cores busy when file sizes vary a lot.
+    Rayon,
+}
+
+/// Order in which the input files of a task are searched (`fileOrder`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FileOrder {
+    /// Path order
+    #[default]
+    Name,
+    /// Most recently modified first
+    Mtime,
+    /// Newest time in the path first, see [`path_timestamp`]
+    Timestamp,
+}
+
+/// Which of the two search tasks a pipeline run belongs to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TaskKind {
+    Aggregated,
+    Native,
+}
+
+impl TaskKind {
+    fn label(self) -> &'static str {
+        match self {
+            TaskKind::Aggregated => "任务1",
+            TaskKind::Native => "任务2",
+        }
+    }
+
+    fn log_name(self) -> &'static str {
+        match self {
+            TaskKind::Aggregated => "汇总日志",
+            TaskKind::Native => "原始日志",
+        }
+    }
+
+    fn output_name(self) -> &'static str {
+        match self {
+            TaskKind::Aggregated => "aggregated",
+            TaskKind::Native => "native",
+        }
+    }
+}
+
+fn run_task(
+    config: &Config,
+    processor: &Arc<FileProcessor>,
+    profiles: Option<&Arc<Profiles>>,
+    progress: Option<&ProgressCallback>,
+    task: TaskKind,
+    watch: Option<&mut FollowWatch>,
+) -> Result<Vec<TaskRecord>> {
+    let label = task.label();
+    status!("\n--- [{}: 开始检索{}] ---", label, task.log_name());
+    let task_time = Instant::now();
+
+    let (files, routing) = match profiles {
+        None => (list_inputs(config, task)?, None),
+        Some(profiles) => {
+            let (files, routing) = ProfileRouting::new(profiles, task)?;
+            (files, Some(routing))
+        }
+    };
+    if let Some(watch) = watch {
+        watch.mark_seen(task, &files);
+    }
+    if files.is_empty() {
+        status!("{}: 未找到符合条件的{}文件。", label, task.log_name());
+        return Ok(Vec::new());
+    }
+    status!("{}: 发现 {} 个待处理的{}文件...", label, files.len(), task.log_name());
+
+    let records = run_search_pipeline(config, processor, routing, progress, task, files, false)?;
+
+    status!("--- [{}: 结束, 耗时: {:?}] ---", label, task_time.elapsed());
+    Ok(records)
+}
+
+/// The query profiles of a `profiles` run, each with its own matchers.
+struct Profiles {
+    configs: Vec<Config>,
+    processors: Vec<Arc<FileProcessor>>,
+}
+
+impl Profiles {
+    fn from_config(config: &Config) -> Result<Option<Self>> {
+        if config.profiles.is_empty() {
+            return Ok(None);
+        }
+        let configs = config.profile_configs();
+        let processors = configs
+            .iter()
+            .map(|profile| {
+                let name = profile.profile_name.as_deref().unwrap_or_default();
+                build_processor(profile).map(Arc::new).with_context(|| format!("invalid profile {:?}", name))
+            })
+            .collect::<Result<_>>()?;
+        Ok(Some(Profiles { configs, processors }))
+    }
+}
+
+/// Which profiles a task hands each input file to: those whose time window selected it.
+struct ProfileRouting {
+    profiles: Arc<Profiles>,
+    inputs: HashMap<PathBuf, Vec<usize>>,
+    /// Files of each profile that could not be opened or read
+    read_failures: Vec<AtomicUsize>,
+}
+
+impl ProfileRouting {
+    /// Lists the inputs of every profile; returns the union, in path order, with the routing.
+    fn new(profiles: &Arc<Profiles>, task: TaskKind) -> Result<(Vec<PathBuf>, Self)> {
+        let mut selected: BTreeMap<PathBuf, Vec<usize>> = BTreeMap::new();
+        for (i, config) in profiles.configs.iter().enumerate() {
+            for path in list_inputs(config, task)? {
+                selected.entry(path).or_default().push(i);
+            }
+        }
+        let files = selected.keys().cloned().collect();
+        let routing = ProfileRouting {
+            profiles: Arc::clone(profiles),
+            inputs: selected.into_iter().collect(),
+            read_failures: profiles.configs.iter().map(|_| AtomicUsize::new(0)).collect(),
+        };
+        Ok((files, routing))
+    }
+
+    /// The profiles `path` is searched for.
+    fn selected(&self, path: &Path) -> &[usize] {
+        self.inputs.get(path).map_or(&[], Vec::as_slice)
+    }
+}
+
+/// The input files of a task, as selected by the directory, time and extension filters.
+fn list_inputs(config: &Config, task: TaskKind) -> Result<Vec<PathBuf>> {
+    let walk = WalkOptions::from_config(config)?;
+    let selection = FileSelection::from_config(config)?;
+    let files = match task {
+        TaskKind::Aggregated => {
+            let roots = search_roots(&config.log_directory)?;
+            let mut files = find_files(&roots, &walk, &config.query_time_day, &config.query_time_hour, &selection);
+            // Listed explicitly, so the time and extension filters do not apply
+            files.extend(config.remote_files.iter().map(PathBuf::from));
+            files
+        }
+        TaskKind::Native => {
+            let native_loc = config.native_log_loc.as_ref().expect("nativeLogLoc required");
+            let time_range = config.query_time_range()?;
+            let timestamp_regex = config.native_file_timestamp_regex()?;
+            find_files_native(
+                Path::new(native_loc),
+                &walk,
+                &config.query_time_day,
+                &config.query_time_hour,
+                &selection,
+                time_range.as_ref(),
+                timestamp_regex.as_ref(),
+            )
+        }
+    };
+    Ok(files)
+}
+
+/// The summary goes to stderr so it never mixes with the piped data.
+fn run_stdin_search(config: &Config, processor: &FileProcessor) -> Result<ProcessStats> {
+    let mut reader = BufReader::with_capacity(1024 * 1024, std::io::stdin().lock());
+    let compression = Compression::detect(Path::new("-"), reader.fill_buf()?);
+    let mut out = BufWriter::with_capacity(config.writer_buffer_bytes(), std::io::stdout().lock());
+    let record_options = config.record_options();
+    let source = config.prepend_source_file.unwrap_or(false).then_some(SourceFile { path: "-", timestamp: None });
+
+    // Single stream, so both scopes behave the same here
+    let mut deduper = Deduper::new(config.dedupe.unwrap_or(false), DedupeScope::Worker, &Arc::new(SharedSeen::new()));
+    let mut duplicates = 0;
+    let mut record = Vec::with_capacity(1024);
+    let mut write_error = None;
+    let mut stats = processor.process_aggregated_reader(Path::new("-"), reader, compression, |matched| {
+        if write_error.is_some() || matched.malformed {
+            return;
+        }
+        if !deduper.first_seen(matched.raw) {
+            duplicates += 1;
+            return;
+        }
+        record.clear();
+        write_record(&mut record, matched, source.as_ref(), &record_options);
+        if let Err(e) = out.write_all(&record) {
+            write_error = Some(e);
+        }
+    })?;
+
+    match write_error {
+        // The reader went away (e.g. `| head`); nothing left to deliver
+        Some(e) if e.kind() == ErrorKind::BrokenPipe => return Ok(stats),
+        Some(e) => return Err(e.into()),
+        None => {}
+    }
+    match out.flush() {
+        Err(e) if e.kind() != ErrorKind::BrokenPipe => return Err(e.into()),
+        _ => {}
+    }
+
+    stats.matches -= duplicates;
+    if duplicates > 0 {
+        status!("标准输入: 去重跳过 {} 条重复记录。", duplicates);
+    }
+    status!("标准输入: 共扫描 {} 行，匹配 {} 条 ({:.2}%)。",
+        stats.lines, stats.matches, stats.match_rate());
+    if stats.long_lines > 0 {
+        status!("标准输入: 跳过 {} 行超过 maxLineBytes 的异常记录。", stats.long_lines);
+    }
+    if stats.malformed_lines > 0 {
+        status!("标准输入: {} 行字段数不等于 expectedFieldCount ({:.2}%)，未参与匹配。",
+            stats.malformed_lines, stats.malformed_rate());
+    }
+    Ok(stats)
+}
+
+/// Runs the IO -> compute -> writer pipeline over `files` and returns what the task read and
+/// wrote: one record, or one per profile with `routing`. A `follow_batch` (`--follow`) always
+/// adds to the result files rather than replacing them, as does any run with `outputAppend`.
+fn run_search_pipeline(
+    config: &Config,
+    processor: &Arc<FileProcessor>,
+    routing: Option<ProfileRouting>,
+    progress: Option<&ProgressCallback>,
+    task: TaskKind,
+    files: Vec<PathBuf>,
+    follow_batch: bool,
+) -> Result<Vec<TaskRecord>> {
+    let label = task.label();
+    let mut files = files;
+    let timestamp_regex = if task == TaskKind::Native { config.native_file_timestamp_regex()? } else { None };
+    order_inputs(&mut files, config.file_order.unwrap_or_default(), timestamp_regex.as_ref());
+    let (files, invalid) = validate_inputs(files);
+    if !invalid.is_empty() {
+        for failure in &invalid {
+            eprintln!("Invalid input file {:?}: {}", failure.path, failure.error);
+        }
+        if config.strict_inputs.unwrap_or(false) {
+            bail!("{} input file(s) failed validation and strictInputs is set", invalid.len());
+        }
+        status!("{}: {} 个文件格式无效，已跳过。", label, invalid.len());
+    }
+    let total_files = files.len();
+    let inputs = input_files(&files);
+    let total_bytes: u64 = inputs.iter().map(|input| input.bytes).sum();
+    let remote_files = files.iter().filter(|path| is_remote(path)).count();
+    if remote_files > 0 {
+        status!("{}: 计划处理 {} 个文件 (其中 {} 个远程文件, 大小下载后才知道), 本地文件总大小 {}。",
+            label, total_files, remote_files, format_bytes(total_bytes));
+    } else {
+        status!("{}: 计划处理 {} 个文件, 文件总大小 {}。", label, total_files, format_bytes(total_bytes));
+    }
+    let threshold = config.confirm_threshold_bytes();
+    if config.confirm_large_runs.unwrap_or(false) && !follow_batch && total_bytes > threshold {
+        confirm_large_run(label, total_bytes, threshold)?;
+    }
+    let count_only = config.count_only.unwrap_or(false);
+    let append = follow_batch || config.output_append.unwrap_or(false);
+
+    // splitByDomainRule: one slot per rule plus a last one for lines no rule matched (OR logic)
+    let split_slots = config
+        .split_by_domain_rule
+        .unwrap_or(false)
+        .then(|| processor.domain_rule_names().len() + 1)
+        .filter(|_| !count_only);
+
+    // Channel for async writing; skipped entirely in count-only mode
+    let mut writer = None;
+    let mut result_path = None;
+    // With profiles, the result file of each profile
+    let mut profile_paths = Vec::new();
+    let mut quarantine = None;
+    if !count_only {
+        let output_encoding = config.output_encoding()?;
+        let output_path = if config.result_loc_is_stdout(task == TaskKind::Aggregated) {
+            None
+        } else {
+            if config.expected_field_count(task == TaskKind::Aggregated).is_some() {
+                let path = malformed_output_path(config, task);
+                // Malformed lines stay text, also when the results are Parquet
+                let encoding = OutputEncoding { parquet: None, ..output_encoding.clone() };
+                quarantine = Some(Arc::new(Quarantine::new(path, encoding, append)));
+            }
+            let output_path = get_output_path(config, task.output_name(), task == TaskKind::Aggregated);
+            if let Some(parent) = output_path.parent().filter(|_| routing.is_none()) {
+                fs::create_dir_all(parent)?;
+            }
+            Some(output_path)
+        };
+        result_path = output_path.clone();
+        let mut output = match (&routing, split_slots, output_path) {
+            (Some(routing), _, _) => {
+                let profiles = &routing.profiles;
+                let mut writers = Vec::with_capacity(profiles.configs.len());
+                for profile in &profiles.configs {
+                    let path = get_output_path(profile, task.output_name(), task == TaskKind::Aggregated);
+                    if let Some(parent) = path.parent() {
+                        fs::create_dir_all(parent)?;
+                    }
+                    writers.push(ResultWriter::open(Some(path.clone()), output_encoding.clone(), config.output_max_bytes, append)?);
+                    profile_paths.push(path);
+                }
+                ResultSink::Profiles(writers)
+            }
+            (None, Some(_), Some(base)) => {
+                let mut names = processor.domain_rule_names().to_vec();
+                names.push("other".to_string());
+                ResultSink::Partitioned(PartitionedWriter::new(base, &names, output_encoding, config.output_max_bytes, append))
+            }
+            (None, _, output_path) => {
+                ResultSink::Single(Box::new(ResultWriter::open(output_path, output_encoding, config.output_max_bytes, append)?))
+            }
+        };
+
+        let (tx, rx) = bounded::<(usize, Vec<u8>)>(config.write_channel_capacity());
+        let flush_interval = config.worker_flush_interval();
+
+        // Spawn writer thread
+        let writer_handle = thread::spawn(move || -> Result<(WriteSummary, Vec<PathBuf>)> {
+            // Written since the last flush, and when that flush was
+            let mut unflushed = false;
+            let mut last_flush = Instant::now();
+            loop {
+                let received = match flush_interval {
+                    Some(interval) => rx.recv_timeout(interval),
+                    None => rx.recv().map_err(|_| RecvTimeoutError::Disconnected),
+                };
+                let written = match received {
+                    Ok((slot, chunk)) => output.write_chunk(slot, &chunk).map(|()| unflushed = true),
+                    Err(RecvTimeoutError::Timeout) => Ok(()),
+                    Err(RecvTimeoutError::Disconnected) => break,
+                };
+                // Low-volume results reach the file without waiting for the buffer to fill
+                let due = flush_interval.is_some_and(|interval| unflushed && last_flush.elapsed() >= interval);
+                let flushed = if due { output.flush() } else { Ok(()) };
+                match written.and(flushed) {
+                    Ok(()) => {}
+                    // Downstream of stdout closed (e.g. `| head`): stop quietly
+                    Err(e) if e.kind() == ErrorKind::BrokenPipe => return Ok(Default::default()),
+                    Err(e) => return Err(e.into()),
+                }
+                if due {
+                    unflushed = false;
+                    last_flush = Instant::now();
+                }
+            }
+            match output.finish() {
+                Ok(finished) => Ok(finished),
+                Err(e) if e.kind() == ErrorKind::BrokenPipe => Ok(Default::default()),
+                Err(e) => Err(e.into()),
+            }
+        });
+        writer = Some((tx, writer_handle));
+    }
+    let tx = writer.as_ref().map(|(tx, _)| tx.clone());
+    let mut split_outputs = Vec::new();
+
+    // Progress tracking
+    let tracker = Arc::new(ProgressTracker::new(task.output_name(), label, total_files, total_bytes, progress.cloned()));
+    let pipeline_done = Arc::new(AtomicBool::new(false));
+    let progress_handle = spawn_ticker(Arc::clone(&tracker), Arc::clone(&pipeline_done));
+
+    // Set once maxMatches is reached; stops the IO thread and workers like SHUTDOWN does
+    let max_matches = config.max_matches;
+    let limit_reached = Arc::new(AtomicBool::new(false));
+
+    let pool_size = config.worker_pool_size(task == TaskKind::Aggregated);
+    let auto_scale = config.auto_scale_workers.unwrap_or(false);
+    let per_file_report = config.per_file_report.unwrap_or(false);
+    let worker = Arc::new(FileWorker {
+        tx,
+        split_slots,
+        processor: Arc::clone(processor),
+        profiles: routing.map(Arc::new),
+        quarantine: quarantine.clone(),
+        task,
+        record_options: config.record_options(),
+        source_prefix: SourcePrefix::from_config(config)?,
+        max_matches,
+        per_file_report,
+        rule_counts: config.rule_match_counts.unwrap_or(false),
+        split_gzip_min_bytes: config.split_gzip_min_bytes,
+        split_pieces: pool_size,
+        flush_bytes: config.worker_flush_bytes(),
+        flush_interval: config.worker_flush_interval(),
+        mmap: config.mmap_inputs.unwrap_or(false),
+        remote: RemoteClient::from_config(config),
+        dedupe: config.dedupe.unwrap_or(false),
+        dedupe_scope: config.dedupe_scope.unwrap_or_default(),
+        shared_seen: Arc::new(SharedSeen::new()),
+        progress: Arc::clone(&tracker),
+        limit_reached: Arc::clone(&limit_reached),
+        duplicates: AtomicUsize::new(0),
+        written_matches: AtomicUsize::new(0),
+        over_limit: AtomicUsize::new(0),
+        read_failures: AtomicUsize::new(0),
+        failures: FailureLog::default(),
+    });
+
+    let backend = config.execution_backend.unwrap_or_default();
+    let (worker_count, totals) = match backend {
+        ExecutionBackend::Threads => run_thread_workers(config, &worker, files, pool_size, auto_scale),
+        ExecutionBackend::Rayon => run_rayon_workers(config, &worker, &files, pool_size)?,
+    };
+    let WorkerTotals { stats: mut total_stats, file_reports, ip_rules, domain_rules, profiles: mut profile_stats } = totals;
+    if let Some(routing) = &worker.profiles {
+        for (stats, failures) in profile_stats.iter_mut().zip(&routing.read_failures) {
+            stats.failed_files += failures.load(Ordering::Relaxed);
+        }
+        // Every line read was passed on; the task's matches are those of its profiles
+        total_stats.matches = profile_stats.iter().map(|stats| stats.matches).sum();
+    }
+
+    // Match counts from here on are of distinct lines kept within maxMatches
+    let duplicates = worker.duplicates.load(Ordering::Relaxed);
+    total_stats.matches -= duplicates + worker.over_limit.load(Ordering::Relaxed);
+    total_stats.failed_files += worker.read_failures.load(Ordering::Relaxed) + invalid.len();
+    let mut failures = invalid.clone();
+    failures.extend(worker.failures.take());
+    // The worker state holds a sender; it has to go before the writer can finish
+    let routing = worker.profiles.clone();
+    drop(worker);
+    let malformed_output = match &quarantine {
+        Some(quarantine) => quarantine.finish()?,
+        None => None,
+    };
+
+    // Drop main thread's senders to close channel, then wait for writer and progress ticker
+    pipeline_done.store(true, Ordering::Relaxed);
+    if let Some(handle) = &progress_handle {
+        handle.thread().unpark();
+    }
+    tracker.finish();
+    let mut checksums = Vec::new();
+    if let Some((tx, writer_handle)) = writer {
+        drop(tx);
+        let (summary, files) = writer_handle.join().unwrap()?;
+        status!("{}: 结果已保存，共写入 {} 条记录。", label, total_stats.matches);
+        if split_slots.is_some() {
+            status!("{}: 结果按域名规则拆分为 {} 个文件。", label, files.len());
+        }
+        if config.output_max_bytes.is_some() && summary.parts > 0 {
+            status!("{}: 结果共分为 {} 个文件，总计 {} 字节。", label, summary.parts, summary.bytes);
+        }
+        print_checksums(label, &summary.checksums);
+        checksums = summary.checksums;
+        split_outputs = files;
+    } else {
+        status!("{}: 仅统计模式，共匹配 {} 条记录。", label, total_stats.matches);
+    }
+    if auto_scale && backend == ExecutionBackend::Threads {
+        status!("{}: 自动扩展计算线程, 最终使用 {}/{} 个。", label, worker_count, pool_size);
+    }
+    if duplicates > 0 {
+        status!("{}: 去重跳过 {} 条重复记录。", label, duplicates);
+    }
+    status!("{}: 共扫描 {} 行，匹配 {} 条 ({:.2}%)。",
+        label, total_stats.lines, total_stats.matches, total_stats.match_rate());
+    if total_stats.long_lines > 0 {
+        status!("{}: 跳过 {} 行超过 maxLineBytes 的异常记录 (详见上方警告)。", label, total_stats.long_lines);
+    }
+    if total_stats.malformed_lines > 0 {
+        let saved = malformed_output.as_ref().map(|path| format!("，已写入 {}", path.display())).unwrap_or_default();
+        status!("{}: {} 行字段数不等于 expectedFieldCount ({:.2}%)，未参与匹配{}。",
+            label, total_stats.malformed_lines, total_stats.malformed_rate(), saved);
+    }
+    if total_stats.failed_files > 0 {
+        status!("{}: {} 个文件无法完整读取 (运行结束时汇总列出)。", label, total_stats.failed_files);
+    }
+    print_rule_counts(label, "sourceIP", &ip_rules);
+    print_rule_counts(label, "queryDomain", &domain_rules);
+    if let Some(handle) = progress_handle {
+        let _ = handle.join();
+    }
+
+    if per_file_report {
+        let output_path = get_output_path(config, task.output_name(), task == TaskKind::Aggregated);
+        let report_dir = output_path.parent().unwrap_or(Path::new("."));
+        let report_path = write_per_file_report(report_dir, task.output_name(), &config.run_stamp_suffix(), file_reports)?;
+        status!("{}: 逐文件统计已保存: {}", label, report_path.display());
+    }
+
+    let truncated = limit_reached.load(Ordering::Relaxed);
+    if truncated {
+        status!("{}: 已达到 maxMatches 上限 ({} 条)，提前结束，共处理 {}/{} 个文件。",
+            label, max_matches.unwrap_or_default(), tracker.processed_files(), total_files);
+    }
+
+    let interrupted = SHUTDOWN.load(Ordering::SeqCst);
+    if interrupted {
+        status!("{}: 已中断，共处理 {}/{} 个文件，已保存的结果截至最后一个完整处理的文件。",
+            label, tracker.processed_files(), total_files);
+    }
+
+    if let Some(routing) = routing {
+        let mut records = Vec::new();
+        for (i, profile) in routing.profiles.configs.iter().enumerate() {
+            let name = profile.profile_name.clone().unwrap_or_default();
+            let stats = profile_stats.get(i).copied().unwrap_or_default();
+            let output = profile_paths.get(i).cloned();
+            status!("{}: 查询配置 {}: 扫描 {} 行，匹配 {} 条 ({:.2}%){}。", label, name, stats.lines, stats.matches,
+                stats.match_rate(), output.as_ref().map(|path| format!("，结果: {}", path.display())).unwrap_or_default());
+            let selects = |path: &PathBuf| routing.selected(path).contains(&i);
+            let inputs = inputs.iter().filter(|input| selects(&input.path)).cloned().collect();
+            let mut record = TaskRecord::new(task.output_name(), output, inputs, stats, interrupted, truncated);
+            record.profile = Some(name);
+            record.malformed_output = malformed_output.clone();
+            record.skipped_inputs = invalid.iter().map(|failure| &failure.path).filter(|path| selects(path)).cloned().collect();
+            record.failed_files += record.skipped_inputs.len();
+            record.file_failures = failures.iter().filter(|failure| selects(&failure.path)).cloned().collect();
+            // Each profile writes to a result directory of its own
+            let dir = record.output.as_ref().and_then(|path| path.parent());
+            record.output_sha256 = checksums.iter().filter(|checksum| checksum.path.parent() == dir).cloned().collect();
+            records.push(record);
+        }
+        return Ok(records);
+    }
+
+    let mut record = TaskRecord::new(task.output_name(), result_path, inputs, total_stats, interrupted, truncated);
+    record.malformed_output = malformed_output;
+    record.skipped_inputs = invalid.into_iter().map(|failure| failure.path).collect();
+    record.file_failures = failures;
+    record.split_outputs = split_outputs;
+    record.output_sha256 = checksums;
+    record.ip_rule_matches = ip_rules;
+    record.domain_rule_matches = domain_rules;
+    Ok(vec![record])
+}
+
+/// Prints the SHA-256 of each result file in `sha256sum` layout, for evidence handling.
+fn print_checksums(label: &str, checksums: &[FileChecksum]) {
+    for checksum in checksums {
+        status!("{}: SHA-256 {}  {}", label, checksum.sha256, checksum.path.display());
+    }
+}
+
+/// Prints the `ruleMatchCounts` breakdown of one rule list, most matches first.
+fn print_rule_counts(label: &str, key: &str, counts: &RuleCounts) {
+    if counts.is_empty() {
+        return;
+    }
+    let ranked = counts.ranked();
+    status!("{}: 各 {} 规则命中数 ({}/{} 条规则有命中):", label, key, ranked.len(), counts.0.len());
+    for count in ranked {
+        status!("  {:>10}  {}", count.matches, count.rule);
+    }
+}
+
+/// What one compute worker accumulates; summed over all workers at the end of a task.
+#[derive(Default)]
+struct WorkerTotals {
+    stats: ProcessStats,
+    file_reports: Vec<FileReport>,
+    /// Matches per `sourceIP` / `queryDomain` rule, with `ruleMatchCounts`
+    ip_rules: RuleCounts,
+    domain_rules: RuleCounts,
+    /// Lines and matches per profile, with `profiles`
+    profiles: Vec<ProcessStats>,
+}
+
+impl WorkerTotals {
+    fn merge(&mut self, other: WorkerTotals) {
+        self.stats += other.stats;
+        self.file_reports.extend(other.file_reports);
+        self.ip_rules.merge(other.ip_rules);
+        self.domain_rules.merge(other.domain_rules);
+        if self.profiles.len() < other.profiles.len() {
+            self.profiles.resize(other.profiles.len(), ProcessStats::default());
+        }
+        for (total, stats) in self.profiles.iter_mut().zip(other.profiles) {
+            *total += stats;
+        }
+    }
+}
+
+/// What `prependSourceFile` / `prependSourceTimestamp` put in front of each result line.
+struct SourcePrefix {
+    with_timestamp: bool,
+    /// `queryTime_day` / `queryTime_hour` values, looked up in aggregated log paths
+    time_prefixes: Vec<String>,
+    timestamp_regex: Option<Regex>,
+}
+
+impl SourcePrefix {
+    fn from_config(config: &Config) -> Result<Option<Self>> {
+        if !config.prepend_source_file.unwrap_or(false) {
+            return Ok(None);
+        }
+        let mut time_prefixes: Vec<String> =
+            config.query_time_day.iter().chain(&config.query_time_hour).flatten().cloned().collect();
+        // Prefer the most specific value, e.g. the hour over its day
+        time_prefixes.sort_by_key(|prefix| std::cmp::Reverse(prefix.len()));
+        Ok(Some(SourcePrefix {
+            with_timestamp: config.prepend_source_timestamp.unwrap_or(false),
+            time_prefixes,
+            timestamp_regex: config.native_file_timestamp_regex()?,
+        }))
+    }
+
+    /// The time the file was selected by: the native file-name timestamp, or the time prefix
+    /// found in an aggregated log path.
+    fn timestamp<'a>(&self, task: TaskKind, path: &'a str) -> Option<&'a str> {
+        if !self.with_timestamp {
+            return None;
+        }
+        match task {
+            TaskKind::Aggregated => self
+                .time_prefixes
+                .iter()
+                .find_map(|prefix| find_time_prefix(path, prefix).map(|start| &path[start..start + prefix.len()])),
+            TaskKind::Native => {
+                let name = Path::new(path).file_name().and_then(|n| n.to_str())?;
+                native_file_timestamp(name, self.timestamp_regex.as_ref())
+            }
+        }
+    }
+}
+
+/// The malformed-line output of a task (`expectedFieldCount`), shared by the workers. Lines
+/// are rare enough to be written under a lock; the file is only created for the first one.
+struct Quarantine {
+    path: PathBuf,
+    encoding: OutputEncoding,
+    append: bool,
+    state: Mutex<QuarantineState>,
+}
+
+#[derive(Default)]
+struct QuarantineState {
+    writer: Option<ResultWriter>,
+    /// First write error; later lines are dropped and the task fails once the workers are done
+    error: Option<std::io::Error>,
+}
+
+impl Quarantine {
+    fn new(path: PathBuf, encoding: OutputEncoding, append: bool) -> Self {
+        Quarantine { path, encoding, append, state: Mutex::new(QuarantineState::default()) }
+    }
+
+    /// Appends whole lines, each with its line terminator.
+    fn write(&self, lines: &[u8]) {
+        let mut state = self.state.lock().unwrap();
+        if state.error.is_some() {
+            return;
+        }
+        let result = match &mut state.writer {
+            Some(writer) => writer.write_chunk(lines),
+            None => self.open().and_then(|writer| state.writer.insert(writer).write_chunk(lines)),
+        };
+        if let Err(e) = result {
+            state.error = Some(e);
+        }
+    }
+
+    fn open(&self) -> std::io::Result<ResultWriter> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        ResultWriter::open(Some(self.path.clone()), self.encoding.clone(), None, self.append)
+    }
+
+    /// Closes the file; `None` when no line was written.
+    fn finish(&self) -> Result<Option<PathBuf>> {
+        let mut state = self.state.lock().unwrap();
+        if let Some(e) = state.error.take() {
+            return Err(e).with_context(|| format!("failed to write {:?}", self.path));
+        }
+        match state.writer.take() {
+            Some(writer) => {
+                writer.finish().with_context(|| format!("failed to write {:?}", self.path))?;
+                Ok(Some(self.path.clone()))
+            }
+            None => Ok(None),
+        }
+    }
+}
+
+/// The bytes of an input file: read into memory, or mapped with `mmapInputs`.
+enum InputData {
+    Read(Vec<u8>),
+    Mapped(Mmap),
+}
+
+impl std::ops::Deref for InputData {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match self {
+            InputData::Read(buffer) => buffer,
+            InputData::Mapped(map) => map,
+        }
+    }
+}
+
+/// One unit of compute work: a whole input file, or one member-aligned piece of a large gzip
+/// file (`splitGzipMinBytes`), whose lines are those starting within it.
+struct WorkItem {
+    path: PathBuf,
+    data: Arc<InputData>,
+    /// Index of the piece in [`SplitFile::ranges`]
+    piece: usize,
+    /// Shared by the pieces of a split file
+    split: Option<Arc<SplitFile>>,
+}
+
+/// Counters of a file split into pieces; whichever piece finishes last reports the file.
+struct SplitFile {
+    ranges: Vec<Range<usize>>,
+    remaining: AtomicUsize,
+    stats: Mutex<ProcessStats>,
+}
+
+/// Matches a worker has not yet handed to the writer thread, one buffer per output slot.
+struct LocalBuffers {
+    slots: Vec<Vec<u8>>,
+    /// When the oldest match in the buffers was added
+    pending_since: Option<Instant>,
+}
+
+/// Per-task state shared by all compute workers, whichever backend runs them.
+struct FileWorker {
+    tx: Option<Sender<(usize, Vec<u8>)>>,
+    /// Number of output slots with `splitByDomainRule`; the last one takes lines no rule matched
+    split_slots: Option<usize>,
+    processor: Arc<FileProcessor>,
+    /// With `profiles`: every line `processor` reads is checked against the profiles the file
+    /// was selected for, and a match goes to the output slot of that profile
+    profiles: Option<Arc<ProfileRouting>>,
+    /// Where `expectedFieldCount` rejects go; `None` when they are only counted
+    quarantine: Option<Arc<Quarantine>>,
+    task: TaskKind,
+    record_options: RecordOptions,
+    /// Set with `prependSourceFile`
+    source_prefix: Option<SourcePrefix>,
+    max_matches: Option<usize>,
+    per_file_report: bool,
+    /// `ruleMatchCounts`
+    rule_counts: bool,
+    split_gzip_min_bytes: Option<u64>,
+    /// Most pieces a split file is cut into, one per worker
+    split_pieces: usize,
+    /// `workerFlushBytes`: size at which a local result buffer goes to the writer
+    flush_bytes: usize,
+    /// `workerFlushIntervalMs`: age at which buffered matches go to the writer whatever their size
+    flush_interval: Option<Duration>,
+    /// `mmapInputs`
+    mmap: bool,
+    /// Set with `remoteFiles`
+    remote: Option<RemoteClient>,
+    dedupe: bool,
+    dedupe_scope: DedupeScope,
+    shared_seen: Arc<SharedSeen>,
+    progress: Arc<ProgressTracker>,
+    limit_reached: Arc<AtomicBool>,
+    duplicates: AtomicUsize,
+    written_matches: AtomicUsize,
+    over_limit: AtomicUsize,
+    /// Files that could not be opened or read
+    read_failures: AtomicUsize,
+    /// What went wrong with each file that failed
+    failures: FailureLog,
+}
+
+impl FileWorker {
+    fn deduper(&self) -> Deduper {
+        Deduper::new(self.dedupe, self.dedupe_scope, &self.shared_seen)
+    }
+
+    /// One local result buffer per output slot.
+    fn buffers(&self) -> LocalBuffers {
+        let slots = match &self.profiles {
+            Some(routing) => routing.profiles.configs.len(),
+            None => self.split_slots.unwrap_or(1),
+        };
+        LocalBuffers { slots: (0..slots).map(|_| Vec::with_capacity(self.flush_bytes)).collect(), pending_since: None }
+    }
+
+    /// Empty totals for one worker, with a zeroed counter per rule under `ruleMatchCounts`
+    /// and per profile with `profiles`.
+    fn totals(&self) -> WorkerTotals {
+        let profiles = self.profiles.as_ref().map_or(0, |routing| routing.profiles.configs.len());
+        let mut totals = WorkerTotals { profiles: vec![ProcessStats::default(); profiles], ..WorkerTotals::default() };
+        if self.rule_counts {
+            totals.ip_rules = RuleCounts::new(self.processor.ip_rule_names());
+            totals.domain_rules = RuleCounts::new(self.processor.domain_rule_names());
+        }
+        totals
+    }
+
+    /// Appends a match to the local buffer of `slot`, handing the buffer to the writer once it
+    /// reaches `workerFlushBytes`, and all buffers once the oldest match in them is
+    /// `workerFlushIntervalMs` old. The age is checked as matches come in and at the end of
+    /// each file.
+    fn buffer_match(&self, buffers: &mut LocalBuffers, slot: usize, matched: &MatchedLine, source: Option<&SourceFile>) {
+        let Some(tx) = &self.tx else {
+            return;
+        };
+        let local_buffer = &mut buffers.slots[slot];
+        write_record(local_buffer, matched, source, &self.record_options);
+
+        if local_buffer.len() >= self.flush_bytes {
+            let new_buf = std::mem::replace(local_buffer, Vec::with_capacity(self.flush_bytes));
+            // A failed writer reports its own error when joined
+            let _ = tx.send((slot, new_buf));
+            if buffers.slots.iter().all(Vec::is_empty) {
+                buffers.pending_since = None;
+                return;
+            }
+        }
+        if let Some(interval) = self.flush_interval {
+            if buffers.pending_since.get_or_insert_with(Instant::now).elapsed() >= interval {
+                self.flush_buffers(buffers);
+            }
+        }
+    }
+
+    /// Hands every non-empty local buffer to the writer.
+    fn flush_buffers(&self, buffers: &mut LocalBuffers) {
+        buffers.pending_since = None;
+        let Some(tx) = &self.tx else {
+            return;
+        };
+        for (slot, local_buffer) in buffers.slots.iter_mut().enumerate() {
+            if !local_buffer.is_empty() {
+                let new_buf = std::mem::replace(local_buffer, Vec::with_capacity(self.flush_bytes));
+                let _ = tx.send((slot, new_buf));
+            }
+        }
+    }
+
+    /// Files not yet started are skipped on shutdown or once maxMatches is reached.
+    fn should_stop(&self) -> bool {
+        SHUTDOWN.load(Ordering::Relaxed) || self.limit_reached.load(Ordering::Relaxed)
+    }
+
+    /// Reads a whole input file into memory, maps it with `mmapInputs` or downloads it for
+    /// `remoteFiles`, reporting (and skipping) files that cannot be read.
+    fn read_input(&self, path: &Path) -> Option<InputData> {
+        let buffer = if is_remote(path) { self.download(path) } else { self.read_local(path) };
+        if buffer.is_none() {
+            self.progress.file_completed(0);
+            self.read_failures.fetch_add(1, Ordering::Relaxed);
+            if let Some(routing) = &self.profiles {
+                for &i in routing.selected(path) {
+                    routing.read_failures[i].fetch_add(1, Ordering::Relaxed);
+                }
+            }
+        }
+        buffer
+    }
+
+    fn read_local(&self, path: &Path) -> Option<InputData> {
+        match File::open(path) {
+            Ok(mut file) => {
+                if self.mmap {
+                    if let Some(map) = map_input(&file) {
+                        return Some(InputData::Mapped(map));
+                    }
+                }
+                let mut buffer = Vec::with_capacity(10 * 1024 * 1024); // Start with 10MB
+                match file.read_to_end(&mut buffer) {
+                    Ok(_) => Some(InputData::Read(buffer)),
+                    Err(e) => {
+                        eprintln!("Error reading file {:?}: {}", path, e);
+                        self.failures.record(path, FailureKind::Read, e);
+                        None
+                    }
+                }
+            }
+            Err(e) => {
+                eprintln!("Error opening file {:?}: {}", path, e);
+                self.failures.record(path, FailureKind::Open, e);
+                None
+            }
+        }
+    }
+
+    fn download(&self, path: &Path) -> Option<InputData> {
+        let remote = self.remote.as_ref().expect("remote inputs come with a client");
+        let url = path.to_string_lossy();
+        match remote.fetch(&url) {
+            Ok(data) => Some(InputData::Read(data)),
+            Err(e) => {
+                eprintln!("Error downloading {}: {:#}", url, e);
+                self.failures.record(path, FailureKind::Read, format!("{:#}", e));
+                None
+            }
+        }
+    }
+
+    /// Cuts a large multi-member gzip file into member-aligned pieces for different workers
+    /// (`splitGzipMinBytes`); any other file stays a single item.
+    fn work_items(&self, path: PathBuf, data: InputData) -> Vec<WorkItem> {
+        let ranges = match self.split_gzip_min_bytes {
+            Some(min)
+                if data.len() as u64 >= min
+                    && self.split_pieces > 1
+                    && Compression::detect(&path, &data) == Compression::Gzip =>
+            {
+                gzip_member_ranges(&data, self.split_pieces)
+            }
+            _ => vec![Range { start: 0, end: data.len() }],
+        };
+        let pieces = ranges.len();
+        let split = (pieces > 1).then(|| {
+            Arc::new(SplitFile { ranges, remaining: AtomicUsize::new(pieces), stats: Mutex::new(ProcessStats::default()) })
+        });
+        let data = Arc::new(data);
+        (0..pieces)
+            .map(|piece| WorkItem { path: path.clone(), data: Arc::clone(&data), piece, split: split.clone() })
+            .collect()
+    }
+
+    /// Searches one file (or piece of one) already read into memory, sending its matches to the writer.
+    fn process_file(
+        &self,
+        item: WorkItem,
+        deduper: &mut Deduper,
+        local_buffers: &mut LocalBuffers,
+        totals: &mut WorkerTotals,
+    ) {
+        let WorkItem { path, data: file_data, piece, split } = item;
+        let data = &file_data[..];
+        // In count-only mode the processor's own counter is enough
+        let compression = Compression::detect(&path, data);
+        let path_text = path.to_string_lossy();
+        let source = self.source_prefix.as_ref().map(|prefix| SourceFile {
+            path: &path_text,
+            timestamp: prefix.timestamp(self.task, &path_text),
+        });
+        let profiles = self.profiles.as_ref().map(|routing| (routing, routing.selected(&path)));
+        let native = self.task == TaskKind::Native;
+        let mut malformed = Vec::new();
+        let mut on_match = |matched: &MatchedLine| {
+            if matched.malformed {
+                if let Some(quarantine) = &self.quarantine {
+                    malformed.extend_from_slice(matched.raw);
+                    malformed.extend_from_slice(self.record_options.line_terminator.as_bytes());
+                    if malformed.len() >= self.flush_bytes {
+                        quarantine.write(&malformed);
+                        malformed.clear();
+                    }
+                }
+                return;
+            }
+            if let Some((routing, selected)) = profiles {
+                for &i in selected {
+                    routing.profiles.processors[i].match_line(matched.raw, native, |matched| {
+                        totals.profiles[i].matches += 1;
+                        self.buffer_match(local_buffers, i, matched, source.as_ref());
+                    });
+                }
+                return;
+            }
+            if !deduper.first_seen(matched.raw) {
+                self.duplicates.fetch_add(1, Ordering::Relaxed);
+                return;
+            }
+            if let Some(max) = self.max_matches {
+                // Files in hand are still scanned to the end, but nothing past the limit is kept
+                let kept = self.written_matches.fetch_add(1, Ordering::Relaxed) + 1;
+                if kept >= max {
+                    self.limit_reached.store(true, Ordering::Relaxed);
+                }
+                if kept > max {
+                    self.over_limit.fetch_add(1, Ordering::Relaxed);
+                    return;
+                }
+            }
+            if let Some(rule) = matched.ip_rule {
+                totals.ip_rules.add(rule);
+            }
+            if let Some(rule) = matched.domain_rule {
+                totals.domain_rules.add(rule);
+            }
+            let slot = match self.split_slots {
+                Some(slots) => matched.domain_rule.unwrap_or(slots - 1),
+                None => 0,
+            };
+            self.buffer_match(local_buffers, slot, matched, source.as_ref());
+        };
+        let result = match (self.task, &split) {
+            (TaskKind::Aggregated, None) => self.processor.process_aggregated_data(&path, data, compression, &mut on_match),
+            (TaskKind::Native, None) => self.processor.process_native_data(&path, data, compression, &mut on_match),
+            (TaskKind::Aggregated, Some(split)) => {
+                self.processor.process_aggregated_piece(&path, data, &split.ranges, piece, &mut on_match)
+            }
+            (TaskKind::Native, Some(split)) => self.processor.process_native_piece(&path, data, &split.ranges, piece, &mut on_match),
+        };
+
+        if let Some(quarantine) = self.quarantine.as_ref().filter(|_| !malformed.is_empty()) {
+            quarantine.write(&malformed);
+        }
+        self.flush_buffers(local_buffers);
+
+        let mut stats = match result {
+            Ok(stats) => {
+                if stats.failed_files > 0 {
+                    self.failures.record(&path, FailureKind::Decode, "corrupt or truncated compressed data");
+                }
+                Some(stats)
+            }
+            Err(e) => {
+                eprintln!("Error processing file {:?}: {}", path, e);
+                self.failures.record(&path, FailureKind::Process, format!("{:#}", e));
+                None
+            }
+        };
+        // A split file is counted once, by the last of its pieces to finish
+        if let Some(split) = &split {
+            let mut file_stats = split.stats.lock().unwrap();
+            *file_stats += stats.unwrap_or(ProcessStats { failed_files: 1, ..ProcessStats::default() });
+            if split.remaining.fetch_sub(1, Ordering::AcqRel) > 1 {
+                return;
+            }
+            file_stats.failed_files = file_stats.failed_files.min(1);
+            stats = Some(*file_stats);
+        }
+        match stats {
+            Some(stats) => {
+                totals.stats += stats;
+                if self.per_file_report {
+                    totals.file_reports.push(FileReport { path, bytes: file_data.len() as u64, stats });
+                }
+            }
+            None => totals.stats.failed_files += 1,
+        }
+        // Profile matches were counted line by line; the file adds its lines and failures
+        if let Some((_, selected)) = profiles {
+            let stats = stats.unwrap_or(ProcessStats { failed_files: 1, ..ProcessStats::default() });
+            for &i in selected {
+                totals.profiles[i] += ProcessStats { matches: 0, ..stats };
+            }
+        }
+
+        self.progress.file_completed(file_data.len() as u64);
+    }
+}
+
+/// Maps `file` read-only; `None` (so the caller reads it instead) when mapping is not possible,
+/// e.g. for pipes or empty files.
+fn map_input(file: &File) -> Option<Mmap> {
+    if file.metadata().ok()?.len() == 0 {
+        return None;
+    }
+    // SAFETY: the mapping is only read. A file truncated by another process while mapped
+    // faults on access, which config.yaml warns about for mmapInputs.
+    let map = unsafe { Mmap::map(file) }.ok()?;
+    #[cfg(unix)]
+    let _ = map.advise(memmap2::Advice::Sequential);
+    Some(map)
+}
+
+fn bind_to_core(core_id: Option<usize>) {
+    if let Some(core_id) = core_id {
+        if let Some(core_ids) = core_affinity::get_core_ids() {
+            if let Some(core) = core_ids.into_iter().find(|c| c.id == core_id) {
+                core_affinity::set_for_current(core);
+            }
+        }
+    }
+}
+
+/// `threads` backend: one sequential IO thread feeding a fixed (or auto-scaled) pool of workers.
+/// Returns the number of workers used and their summed totals.
+fn run_thread_workers(
+    config: &Config,
+    worker: &Arc<FileWorker>,
+    files: Vec<PathBuf>,
+    pool_size: usize,
+    auto_scale: bool,
+) -> (usize, WorkerTotals) {
+    // IO-Compute Separation Model
+    // 1. Channel for memory-resident file data (Bounded to limit memory usage)
+    //    ioChannelCapacity (default 4) files at most sit in memory waiting for CPU.
+    //    If files are avg 100MB, max usage ~400MB + current processing file.
+    let (data_tx, data_rx) = bounded::<WorkItem>(config.io_channel_capacity());
+
+    // 2. Spawn IO Thread (Read file to memory)
+    //    This thread does SEQUENTIAL disk read, maximizing HDD throughput.
+    let io_worker = Arc::clone(worker);
+    let io_handle = thread::spawn(move || {
+        'files: for path in files {
+            if io_worker.should_stop() {
+                break;
+            }
+            if let Some(buffer) = io_worker.read_input(&path) {
+                for item in io_worker.work_items(path, buffer) {
+                    // Send to workers (will block if channel is full, throttling IO)
+                    if data_tx.send(item).is_err() {
+                        break 'files;
+                    }
+                }
+            }
+        }
+    });
+
+    // 3. Spawn Compute Workers (CPU Bound)
+    let core_ids = config.core_ids.clone();
+    let spawn_worker = |i: usize| {
+        let data_rx = data_rx.clone();
+        let worker = Arc::clone(worker);
+        let core_id_to_bind = core_ids.as_ref().and_then(|ids| ids.get(i).cloned());
+
+        thread::spawn(move || {
+            bind_to_core(core_id_to_bind);
+
+            let mut deduper = worker.deduper();
+            let mut totals = worker.totals();
+            let mut local_buffers = worker.buffers();
+
+            while let Ok(item) = data_rx.recv() {
+                // Files already read are dropped on shutdown; the one in hand is always finished
+                if worker.should_stop() {
+                    break;
+                }
+                // Large buffer is dropped as soon as the file (all of its pieces) is done
+                worker.process_file(item, &mut deduper, &mut local_buffers, &mut totals);
+            }
+            totals
+        })
+    };
+
+    let initial_workers = if auto_scale { AUTO_SCALE_INITIAL_WORKERS.min(pool_size) } else { pool_size };
+    let mut handles: Vec<_> = (0..initial_workers).map(spawn_worker).collect();
+
+    // Auto-scaling: add a worker whenever the IO channel has stayed full for a couple of
+    // samples, i.e. the readers are waiting on compute rather than the other way round
+    if auto_scale {
+        let mut full_samples = 0;
+        while handles.len() < pool_size && !io_handle.is_finished() && !SHUTDOWN.load(Ordering::Relaxed) {
+            thread::sleep(AUTO_SCALE_SAMPLE_INTERVAL);
+            if data_rx.is_full() {
+                full_samples += 1;
+            } else {
+                full_samples = 0;
+            }
+            if full_samples >= AUTO_SCALE_FULL_SAMPLES {
+                handles.push(spawn_worker(handles.len()));
+                full_samples = 0;
+            }
+        }
+    }
+
+    // Only workers hold the receiver now, so the IO thread unblocks if they all stop early
+    drop(data_rx);
+
+    // Wait for IO thread
+    io_handle.join().unwrap();
+
+    // Wait for workers and sum results
+    let worker_count = handles.len();
+    let mut totals = WorkerTotals::default();
+    for handle in handles {
+        totals.merge(handle.join().unwrap());
+    }
+    (worker_count, totals)
+}
+
+/// `rayon` backend: every file is its own task on a work-stealing pool, so a few huge files
+/// no longer leave the other workers idle. Each task reads its file itself, so at most
+/// `workerPoolSize` files are in memory; reads run in parallel, which suits SSDs better than HDDs.
+fn run_rayon_workers(
+    config: &Config,
+    worker: &Arc<FileWorker>,
+    files: &[PathBuf],
+    pool_size: usize,
+) -> Result<(usize, WorkerTotals)> {
+    let core_ids = config.core_ids.clone();
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(pool_size)
+        .start_handler(move |i| bind_to_core(core_ids.as_ref().and_then(|ids| ids.get(i).cloned())))
+        .build()
+        .context("failed to start the rayon worker pool")?;
+
+    let results: Vec<WorkerTotals> = pool.install(|| {
+        files
+            .par_iter()
+            // One file per task, so stealing balances uneven file sizes
+            .with_max_len(1)
+            .map_init(
+                || (worker.deduper(), worker.buffers()),
+                |(deduper, local_buffers), path| {
+                    let mut totals = worker.totals();
+                    if worker.should_stop() {
+                        return totals;
+                    }
+                    if let Some(data) = worker.read_input(path) {
+                        let mut items = worker.work_items(path.clone(), data);
+                        if items.len() == 1 {
+                            worker.process_file(items.remove(0), deduper, local_buffers, &mut totals);
+                        } else {
+                            // Pieces of a split file are tasks of their own, so idle threads steal them
+                            let pieces: Vec<WorkerTotals> = items
+                                .into_par_iter()
+                                .map(|item| {
+                                    let mut piece_totals = worker.totals();
+                                    worker.process_file(item, &mut worker.deduper(), &mut worker.buffers(), &mut piece_totals);
+                                    piece_totals
+                                })
+                                .collect();
+                            for piece_totals in pieces {
+                                totals.merge(piece_totals);
+                            }
+                        }
+                    }
+                    totals
+                },
+            )
+            .collect()
+    });
+
+    let mut totals = WorkerTotals::default();
+    for result in results {
+        totals.merge(result);
+    }
+    Ok((pool_size, totals))
+}
+
+/// Asks on the terminal before a run larger than `confirmThresholdBytes`. Without a terminal
+/// to ask on (cron, pipes) the run is refused rather than started unattended.
+fn confirm_large_run(label: &str, total_bytes: u64, threshold: u64) -> Result<()> {
+    let stdin = std::io::stdin();
+    if !stdin.is_terminal() {
+        bail!(
+            "{} bytes to scan exceeds confirmThresholdBytes ({}) and there is no terminal to confirm on",
+            total_bytes,
+            threshold
+        );
+    }
+    eprint!("{}: 待处理数据 {} 超过确认阈值 {}，是否继续? [y/N] ", label, format_bytes(total_bytes), format_bytes(threshold));
+    std::io::stderr().flush()?;
+    let mut answer = String::new();
+    stdin.lock().read_line(&mut answer)?;
+    if !matches!(answer.trim().to_ascii_lowercase().as_str(), "y" | "yes") {
+        bail!("run cancelled at the confirmLargeRuns prompt");
+    }
+    Ok(())
+}
+
+/// Human-readable size in decimal units, e.g. `1.25 GB`.
+fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KB", "MB", "GB", "TB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1000.0 && unit < UNITS.len() - 1 {
+        value /= 1000.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} B", bytes)
+    } else {
+        format!("{:.2} {}", value, UNITS[unit])
+    }
+}
+
+/// Splits out compressed files whose header does not match their extension (e.g. a plain file
+/// renamed to `.gz`), so they are reported once here instead of failing inside a worker.
+/// Zero-length files, such as the placeholders log rotation leaves behind, hold no lines
+/// whatever their extension; they are dropped silently, with a note under `--debug`.
+fn validate_inputs(files: Vec<PathBuf>) -> (Vec<PathBuf>, Vec<FileFailure>) {
+    let mut valid = Vec::with_capacity(files.len());
+    let mut invalid = Vec::new();
+    for path in files {
+        // Remote files are only checked once downloaded
+        if is_remote(&path) {
+            valid.push(path);
+            continue;
+        }
+        if fs::metadata(&path).is_ok_and(|metadata| metadata.len() == 0) {
+            debug!("{:?}: skipped, the file is empty", path);
+            continue;
+        }
+        let compression = Compression::detect(&path, &[]);
+        if compression == Compression::None {
+            valid.push(path);
+            continue;
+        }
+        let check = File::open(&path).and_then(|mut file| {
+            let file_len = file.metadata()?.len();
+            let mut head = Vec::with_capacity(4);
+            (&mut file).take(4).read_to_end(&mut head)?;
+            Ok(compression.check_header(&head, file_len).map_err(str::to_string))
+        });
+        match check {
+            Ok(Ok(())) => valid.push(path),
+            Ok(Err(error)) => invalid.push(FileFailure { path, kind: FailureKind::Decode, error }),
+            Err(e) => invalid.push(FileFailure { path, kind: FailureKind::Open, error: e.to_string() }),
+        }
+    }
+    (valid, invalid)
+}
+
+/// Expands a glob pattern such as `/data/site-*/dns/` into the matching directories, so only those
+/// trees are walked. A path without glob metacharacters is used as is.
+fn search_roots(pattern: &str) -> Result<Vec<PathBuf>> {
+    if !pattern.contains(['*', '?', '[']) {
+        return Ok(vec![PathBuf::from(pattern)]);
+    }
+    let roots = glob::glob(pattern)
+        .with_context(|| format!("invalid logDirectory pattern {:?}", pattern))?
+        .filter_map(|entry| entry.ok())
+        .filter(|path| path.is_dir())
+        .collect();
+    Ok(roots)
+}
+
+/// How the input directories are walked.
+struct WalkOptions {
+    follow_symlinks: bool,
+    /// Levels below each root; files directly in a root are at depth 1
+    max_depth: Option<usize>,
+    /// Directory names never descended into
+    prune: Vec<glob::Pattern>,
+}
+
+impl WalkOptions {
+    fn from_config(config: &Config) -> Result<Self> {
+        Ok(WalkOptions {
+            follow_symlinks: config.follow_symlinks.unwrap_or(false),
+            max_depth: config.max_depth,
+            prune: config.prune_patterns()?,
+        })
+    }
+
+    /// Whether WalkDir should skip this directory and everything below it. Roots are never pruned.
+    fn prunes(&self, entry: &walkdir::DirEntry) -> bool {
+        entry.depth() > 0
+            && entry.file_type().is_dir()
+            && entry
+                .file_name()
+                .to_str()
+                .is_some_and(|name| self.prune.iter().any(|p| p.matches(name)))
+    }
+}
+
+/// Which walked files a task considers, judged by file name before any time filter: first
+/// the extension allowlist (`fileExtensions`), then the exclusion regex (`excludeFileRegex`).
+struct FileSelection {
+    suffixes: Vec<String>,
+    exclude: Option<Regex>,
+}
+
+impl FileSelection {
+    fn from_config(config: &Config) -> Result<Self> {
+        Ok(FileSelection {
+            suffixes: config.file_extensions(),
+            exclude: config.exclude_file_regex()?,
+        })
+    }
+
+    fn selects(&self, name: &str) -> bool {
+        self.suffixes.iter().any(|s| name.ends_with(s.as_str()))
+            && !self.exclude.as_ref().is_some_and(|re| re.is_match(name))
+    }
+}
+
+/// Lists the regular files under `roots`. A file reachable through several paths (symlinked
+/// directories, overlapping roots) is returned once, under the first path found; with
+/// `followSymlinks` WalkDir reports loops as errors, which are skipped.
+fn walk_files(roots: &[PathBuf], options: &WalkOptions) -> Vec<PathBuf> {
+    let mut seen = HashSet::new();
+    let mut files = Vec::new();
+    for root in roots {
+        let mut walker = WalkDir::new(root).follow_links(options.follow_symlinks);
+        if let Some(depth) = options.max_depth {
+            walker = walker.max_depth(depth);
+        }
+        let entries = walker.into_iter().filter_entry(|entry| !options.prunes(entry));
+        for entry in entries.filter_map(|e| e.ok()) {
+            let path = entry.path();
+            if !path.is_file() {
+                continue;
+            }
+            let canonical = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+            if seen.insert(canonical) {
+                files.push(entry.into_path());
+            }
+        }
+    }
+    files
+}
+
+/// Files under `roots` whose path holds one of the day or hour prefixes, in path order. Each
+/// file is listed once however many prefixes it matches (a day and one of its hours, or a
+/// value given twice).
+fn find_files(roots: &[PathBuf], walk: &WalkOptions, days: &Option<Vec<String>>, hours: &Option<Vec<String>>, selection: &FileSelection) -> Vec<PathBuf> {
+    let mut files = BTreeSet::new();
+    let mut search_prefixes = Vec::new();
+    
+    if let Some(ds) = days {
+        search_prefixes.extend(ds.clone());
+    }
+    if let Some(hs) = hours {
+        search_prefixes.extend(hs.clone());
+    }
+
+    for path in walk_files(roots, walk) {
+        if let Some(path_str) = path.to_str() {
+            let name = path.file_name().and_then(|n| n.to_str()).unwrap_or_default();
+            if selection.selects(name) {
+                // Check if the path holds any of the time prefixes, in a directory like
+                // ".../20250626/access.log.gz" or in the file name ("access_2025062619.log.gz")
+                for prefix in &search_prefixes {
+                    if find_time_prefix(path_str, prefix).is_some() {
+                        files.insert(path);
+                        break;
+                    }
+                }
+            }
+        }
+    }
+    files.into_iter().collect()
+}
+
+/// Byte offset of the first place `prefix` (a `queryTime_day` / `queryTime_hour` value) appears
+/// in `path` as a time: at the start of a path component or right after a `_`, `-` or `.`, and
+/// not followed by a letter. More digits may follow, since a day also prefixes its hours and
+/// timestamps. `.../20250626/a.log` and `.../dns_2025062619.log` match `20250626`;
+/// `.../id20250626/a.log` and `.../20250626abc/a.log` do not.
+pub fn find_time_prefix(path: &str, prefix: &str) -> Option<usize> {
+    if prefix.is_empty() {
+        return None;
+    }
+    let bytes = path.as_bytes();
+    path.match_indices(prefix).map(|(start, _)| start).find(|&start| {
+        let end = start + prefix.len();
+        let starts_field = start == 0 || matches!(bytes[start - 1], b'/' | b'\\' | b'_' | b'-' | b'.');
+        let ends_field = bytes.get(end).is_none_or(|&b| !b.is_ascii_alphabetic());
+        starts_field && ends_field
+    })
+}
+
+/// Native counterpart of [`find_files`], selecting on the timestamp in the file name; also
+/// in path order with each file listed once.
+fn find_files_native(
+    dir: &Path,
+    walk: &WalkOptions,
+    days: &Option<Vec<String>>,
+    hours: &Option<Vec<String>>,
+    selection: &FileSelection,
+    range: Option<&TimeRange>,
+    timestamp_regex: Option<&Regex>,
+) -> Vec<PathBuf> {
+    let mut files = BTreeSet::new();
+    let mut search_prefixes = Vec::new();
+    if let Some(ds) = days { search_prefixes.extend(ds.clone()); }
+    if let Some(hs) = hours { search_prefixes.extend(hs.clone()); }
+
+    for path in walk_files(&[dir.to_path_buf()], walk) {
+        if let Some(name) = path.file_name().and_then(|n| n.to_str()) {
+            if selection.selects(name) {
+                let timestamp = native_file_timestamp(name, timestamp_regex);
+
+                if let Some(range) = range {
+                    // With an explicit window the day/hour prefixes are optional extra filters
+                    let parsed = timestamp.and_then(parse_native_timestamp);
+                    let Some(parsed) = parsed else {
+                        eprintln!("Skipping file with unparseable timestamp {:?}", path);
+                        continue;
+                    };
+                    if !range.contains(&parsed) {
+                        continue;
+                    }
+                    if search_prefixes.is_empty() {
+                        files.insert(path.clone());
+                        continue;
+                    }
+                }
+
+                if let Some(timestamp) = timestamp {
+                    for prefix in &search_prefixes {
+                        if timestamp.starts_with(prefix) {
+                            files.insert(path.clone());
+                            break;
+                        }
+                    }
+                }
+            }
+        }
+    }
+    files.into_iter().collect()
+}
+
+/// Puts `files`, listed in path order, into `fileOrder`. Files without a modification time or
+/// timestamp (such as `remoteFiles`) go last, still in path order.
+fn order_inputs(files: &mut [PathBuf], order: FileOrder, timestamp_regex: Option<&Regex>) {
+    match order {
+        FileOrder::Name => {}
+        FileOrder::Mtime => {
+            files.sort_by_cached_key(|path| std::cmp::Reverse(fs::metadata(path).and_then(|meta| meta.modified()).ok()))
+        }
+        FileOrder::Timestamp => files.sort_by_cached_key(|path| std::cmp::Reverse(path_timestamp(path, timestamp_regex))),
+    }
+}
+
+/// Time a file holds logs of, for `fileOrder: timestamp`: the native file name timestamp when
+/// there is one, otherwise the last run of 8 to 14 digits in the path that parses as one, so
+/// `.../20250626/dns_2025062619.log` gives 2025-06-26 19:00.
+fn path_timestamp(path: &Path, regex: Option<&Regex>) -> Option<NaiveDateTime> {
+    let name = path.file_name()?.to_str()?;
+    if let Some(timestamp) = native_file_timestamp(name, regex).and_then(parse_native_timestamp) {
+        return Some(timestamp);
+    }
+    path.to_str()?
+        .split(|c: char| !c.is_ascii_digit())
+        .filter(|run| (8..=14).contains(&run.len()))
+        .filter_map(parse_native_timestamp)
+        .next_back()
+}
+
+/// Extracts the timestamp from a native file name: the `ts` group of `nativeFileTimestampRegex`
+/// when it matches, otherwise the third `_`-separated part, as in `250_132228145205_20251209151802_1.gz`.
+fn native_file_timestamp<'a>(name: &'a str, regex: Option<&Regex>) -> Option<&'a str> {
+    if let Some(ts) = regex.and_then(|re| re.captures(name)).and_then(|caps| caps.name("ts")) {
+        return Some(ts.as_str());
+    }
+    name.split('_').nth(2)
+}
+
+fn get_output_path(config: &Config, task_type: &str, is_aggregated: bool) -> PathBuf {
+    let base_dir = if is_aggregated {
+        config.aggregated_log_result_loc.clone().unwrap_or_else(|| "./".to_string())
+    } else {
+        config.native_log_result_loc.clone().unwrap_or_else(|| "./".to_string())
+    };
+
+    let date_part = if let Some(days) = &config.query_time_day {
+        days.first().cloned().unwrap_or_else(|| "unknown".to_string())
+    } else {
+        "unknown".to_string()
+    };
+
+    let domain_part = if config.query_domain.is_empty() {
+        "all_domains".to_string()
+    } else if config.query_domain.len() == 1 {
+        // `re:` rules may hold path separators and wildcards, so only name-safe characters stay
+        config.query_domain[0]
+            .replace("*", "wildcard")
+            .replace("!", "not_")
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.') { c } else { '_' })
+            .collect()
+    } else {
+        "multi_domains".to_string()
+    };
+
+    let ip_part = if config.source_ip.is_empty() {
+        "all_ips".to_string()
+    } else if config.source_ip.len() == 1 {
+        config.source_ip[0].replace("/", "_")
+    } else {
+        "multi_ips".to_string()
+    };
+
+    let mut dir_name = format!("{}_{}_{}_results", 
+        domain_part, 
+        ip_part, 
+        date_part
+    );
+    if let Some(profile) = &config.profile_name {
+        dir_name = format!("{}_{}", profile, dir_name);
+    }
+
+    let extension = if config.output_format.unwrap_or_default() == OutputFormat::Parquet { "parquet" } else { "txt" };
+    let name = format!("matched_{}_logs{}.{}{}", task_type, config.run_stamp_suffix(), extension, output_suffix(config));
+    Path::new(&base_dir).join(dir_name).join(name)
+}
+
+/// `malformed_lines_{task}.txt` in the task's result directory. Named per task, since both
+/// tasks may share that directory.
+fn malformed_output_path(config: &Config, task: TaskKind) -> PathBuf {
+    let output_path = get_output_path(config, task.output_name(), task == TaskKind::Aggregated);
+    let name = format!("malformed_lines_{}{}.txt{}", task.output_name(), config.run_stamp_suffix(), output_suffix(config));
+    output_path.with_file_name(name)
+}
+
+/// Extensions added to result file names by `outputCompression` and `outputEncryption`.
+fn output_suffix(config: &Config) -> String {
+    let extension = config.output_compression.unwrap_or_default().extension();
+    let encrypted = if config.output_encryption.unwrap_or(false) { ".enc" } else { "" };
+    format!("{}{}", extension, encrypted)
+}
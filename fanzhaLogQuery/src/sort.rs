@@ -0,0 +1,218 @@
+use crate::output::{OutputCompression, OutputWriter};
+use crate::processor::{split_fields, unquote};
+use anyhow::{Context, Result};
+use flate2::read::MultiGzDecoder;
+use std::cmp::{Ordering, Reverse};
+use std::collections::BinaryHeap;
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+/// Lines held in memory before a sorted run is spilled to disk, when `sortBufferBytes` is not set.
+pub const DEFAULT_SORT_BUFFER_BYTES: usize = 256 * 1024 * 1024;
+/// Rough per-line bookkeeping cost added to the line length when filling the buffer.
+const LINE_OVERHEAD_BYTES: usize = 48;
+
+/// How `sortByTimestampField` orders a result file.
+#[derive(Debug, Clone)]
+pub struct SortOptions {
+    /// Field of the written result line holding the timestamp
+    pub field: usize,
+    pub delimiter: u8,
+    pub quoted: bool,
+    pub compression: OutputCompression,
+    pub buffer_bytes: usize,
+}
+
+/// Timestamp field of one line. A missing field sorts first, then numbers (epoch seconds or
+/// milliseconds, optionally with a fraction) by value, then anything else (RFC 3339,
+/// `2025-06-26 08:00:00`) byte-wise. Ranking the three kinds apart keeps the order total when a
+/// column mixes them.
+#[derive(Debug)]
+struct SortKey(Vec<u8>);
+
+impl SortKey {
+    fn of(line: &[u8], options: &SortOptions) -> Self {
+        let field = split_fields(line, options.delimiter, options.quoted).nth(options.field).unwrap_or_default();
+        let field = if options.quoted { unquote(field) } else { field };
+        SortKey(field.trim_ascii().to_vec())
+    }
+}
+
+impl Ord for SortKey {
+    fn cmp(&self, other: &Self) -> Ordering {
+        match (self.0.is_empty(), other.0.is_empty()) {
+            (true, true) => return Ordering::Equal,
+            (true, false) => return Ordering::Less,
+            (false, true) => return Ordering::Greater,
+            (false, false) => {}
+        }
+        match (number_parts(&self.0), number_parts(&other.0)) {
+            (Some((a_int, a_frac)), Some((b_int, b_frac))) => {
+                a_int.len().cmp(&b_int.len()).then_with(|| a_int.cmp(b_int)).then_with(|| a_frac.cmp(b_frac))
+            }
+            (Some(_), None) => Ordering::Less,
+            (None, Some(_)) => Ordering::Greater,
+            (None, None) => self.0.cmp(&other.0),
+        }
+    }
+}
+
+impl PartialOrd for SortKey {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+// Equal by value, so `1.50` equals `1.5`
+impl PartialEq for SortKey {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for SortKey {}
+
+/// Splits `1700000000.250` into its integer digits without leading zeros (`1700000000`) and
+/// its fraction digits without trailing zeros (`25`); `None` unless the key is such a number.
+fn number_parts(key: &[u8]) -> Option<(&[u8], &[u8])> {
+    let (int, frac) = match key.iter().position(|&b| b == b'.') {
+        Some(dot) if dot + 1 < key.len() => (&key[..dot], &key[dot + 1..]),
+        Some(_) => return None,
+        None => (key, &[][..]),
+    };
+    if int.is_empty() || !int.iter().all(u8::is_ascii_digit) || !frac.iter().all(u8::is_ascii_digit) {
+        return None;
+    }
+    let start = int.iter().position(|&b| b != b'0').unwrap_or(int.len());
+    let end = frac.iter().rposition(|&b| b != b'0').map_or(0, |last| last + 1);
+    Some((&int[start..], &frac[..end]))
+}
+
+/// Sorted runs spilled next to the result file; removed however the sort ends.
+struct SpillRuns {
+    target: PathBuf,
+    paths: Vec<PathBuf>,
+}
+
+impl SpillRuns {
+    fn spill(&mut self, lines: &mut Vec<Vec<u8>>, options: &SortOptions) -> Result<()> {
+        let path = sibling_path(&self.target, &format!("sort{}.tmp", self.paths.len()));
+        self.paths.push(path.clone());
+        let file = File::create(&path).with_context(|| format!("failed to create {:?}", path))?;
+        let mut out = BufWriter::with_capacity(1024 * 1024, file);
+        for line in sorted(std::mem::take(lines), options) {
+            out.write_all(&line)?;
+            out.write_all(b"\n")?;
+        }
+        out.flush().with_context(|| format!("failed to write {:?}", path))?;
+        Ok(())
+    }
+}
+
+impl Drop for SpillRuns {
+    fn drop(&mut self) {
+        for path in &self.paths {
+            let _ = fs::remove_file(path);
+        }
+    }
+}
+
+/// Sorts a finished result file in place by its timestamp field, keeping the original order
+/// of equal timestamps. At most about `buffer_bytes` of lines are held in memory; larger files
+/// are sorted in runs spilled beside the result file and merged. Returns the number of lines.
+/// Lines are split on `\n` only, so the `\r` of `lineTerminator: crlf` results is carried along.
+/// Also returns the SHA-256 of the sorted file.
+pub fn sort_result_file(path: &Path, options: &SortOptions) -> Result<(usize, String)> {
+    let file = File::open(path).with_context(|| format!("failed to open {:?}", path))?;
+    let reader: Box<dyn BufRead> = match options.compression {
+        OutputCompression::None => Box::new(BufReader::with_capacity(1024 * 1024, file)),
+        OutputCompression::Gzip => Box::new(BufReader::with_capacity(1024 * 1024, MultiGzDecoder::new(file))),
+    };
+
+    let mut runs = SpillRuns { target: path.to_path_buf(), paths: Vec::new() };
+    let mut buffer = Vec::new();
+    let mut buffered_bytes = 0;
+    let mut lines = 0;
+    for line in reader.split(b'\n') {
+        let line = line.with_context(|| format!("failed to read {:?}", path))?;
+        buffered_bytes += line.len() + LINE_OVERHEAD_BYTES;
+        buffer.push(line);
+        lines += 1;
+        if buffered_bytes >= options.buffer_bytes {
+            runs.spill(&mut buffer, options)?;
+            buffered_bytes = 0;
+        }
+    }
+
+    let sorted_path = sibling_path(path, "sorting");
+    let mut out = OutputWriter::create(&sorted_path, &options.compression.into())
+        .with_context(|| format!("failed to create {:?}", sorted_path))?;
+    let written = if runs.paths.is_empty() {
+        sorted(buffer, options).try_for_each(|line| write_line(&mut out, &line))
+    } else {
+        if !buffer.is_empty() {
+            runs.spill(&mut buffer, options)?;
+        }
+        merge_runs(&runs.paths, &mut out, options)
+    };
+    let digest = written.and_then(|()| Ok(out.finish()?)).with_context(|| format!("failed to write {:?}", sorted_path))?;
+    fs::rename(&sorted_path, path).with_context(|| format!("failed to replace {:?}", path))?;
+    Ok((lines, digest.expect("created files are hashed")))
+}
+
+fn sorted(lines: Vec<Vec<u8>>, options: &SortOptions) -> impl Iterator<Item = Vec<u8>> {
+    let mut keyed: Vec<(SortKey, Vec<u8>)> = lines.into_iter().map(|line| (SortKey::of(&line, options), line)).collect();
+    // Stable, so lines with equal timestamps keep their order
+    keyed.sort_by(|a, b| a.0.cmp(&b.0));
+    keyed.into_iter().map(|(_, line)| line)
+}
+
+/// k-way merge of the spilled runs; ties go to the earlier run, which keeps the sort stable.
+fn merge_runs(paths: &[PathBuf], out: &mut OutputWriter, options: &SortOptions) -> Result<()> {
+    let mut readers = Vec::with_capacity(paths.len());
+    for path in paths {
+        let file = File::open(path).with_context(|| format!("failed to open {:?}", path))?;
+        readers.push(BufReader::with_capacity(256 * 1024, file));
+    }
+
+    let mut heap = BinaryHeap::with_capacity(readers.len());
+    for (run, reader) in readers.iter_mut().enumerate() {
+        if let Some(line) = next_line(reader)? {
+            heap.push(Reverse((SortKey::of(&line, options), run, line)));
+        }
+    }
+    while let Some(Reverse((_, run, line))) = heap.pop() {
+        write_line(out, &line)?;
+        if let Some(next) = next_line(&mut readers[run])? {
+            heap.push(Reverse((SortKey::of(&next, options), run, next)));
+        }
+    }
+    Ok(())
+}
+
+fn next_line(reader: &mut BufReader<File>) -> Result<Option<Vec<u8>>> {
+    let mut line = Vec::new();
+    if reader.read_until(b'\n', &mut line)? == 0 {
+        return Ok(None);
+    }
+    if line.last() == Some(&b'\n') {
+        line.pop();
+    }
+    Ok(Some(line))
+}
+
+fn write_line(out: &mut OutputWriter, line: &[u8]) -> Result<()> {
+    out.write_all(line)?;
+    out.write_all(b"\n")?;
+    Ok(())
+}
+
+/// `matched_logs.txt.gz` -> `matched_logs.txt.gz.<suffix>`, in the same directory so the
+/// final rename stays on one file system.
+fn sibling_path(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(".");
+    name.push(suffix);
+    path.with_file_name(name)
+}
@@ -0,0 +1,68 @@
+use std::io::IsTerminal;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Set when result data is streamed to stdout, so status messages must not mix with it.
+static TO_STDERR: AtomicBool = AtomicBool::new(false);
+/// Set by `--quiet` / `quiet`: status messages and progress are dropped; errors and warnings,
+/// which go straight to stderr, are not affected.
+static QUIET: AtomicBool = AtomicBool::new(false);
+/// Set by `--debug`: per-file diagnostics, such as skipped empty files or the delimiter
+/// `autoDetectDelimiter` chose, are printed to stderr.
+static DEBUG: AtomicBool = AtomicBool::new(false);
+
+pub fn route_to_stderr() {
+    TO_STDERR.store(true, Ordering::Relaxed);
+}
+
+pub fn to_stderr() -> bool {
+    TO_STDERR.load(Ordering::Relaxed)
+}
+
+pub fn set_quiet() {
+    QUIET.store(true, Ordering::Relaxed);
+}
+
+pub fn quiet() -> bool {
+    QUIET.load(Ordering::Relaxed)
+}
+
+pub fn set_debug() {
+    DEBUG.store(true, Ordering::Relaxed);
+}
+
+pub fn debug() -> bool {
+    DEBUG.load(Ordering::Relaxed)
+}
+
+/// Whether the stream status messages go to is attached to a terminal.
+pub fn is_terminal() -> bool {
+    if to_stderr() {
+        std::io::stderr().is_terminal()
+    } else {
+        std::io::stdout().is_terminal()
+    }
+}
+
+/// `println!` for progress and summary messages: stdout normally, stderr while results
+/// are written to stdout, nowhere when quiet.
+#[macro_export]
+macro_rules! status {
+    ($($arg:tt)*) => {
+        if $crate::status::quiet() {
+        } else if $crate::status::to_stderr() {
+            eprintln!($($arg)*);
+        } else {
+            println!($($arg)*);
+        }
+    };
+}
+
+/// `eprintln!` for diagnostics only wanted with `--debug`; `--quiet` does not affect them.
+#[macro_export]
+macro_rules! debug {
+    ($($arg:tt)*) => {
+        if $crate::status::debug() {
+            eprintln!($($arg)*);
+        }
+    };
+}
@@ -0,0 +1,66 @@
+use anyhow::{Context, Result};
+use publicsuffix::{List, Psl};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::sync::Mutex;
+
+const SHARD_COUNT: usize = 64;
+/// Names cached per shard; a full shard starts over, which caps the cache at about a million names
+const SHARD_CAPACITY: usize = 16 * 1024;
+
+/// Where the `publicsuffix` package of Debian and Ubuntu installs the list.
+pub const DEFAULT_PUBLIC_SUFFIX_LIST: &str = "/usr/share/publicsuffix/public_suffix_list.dat";
+
+/// Cuts domains down to their registrable domain (eTLD+1) by the Public Suffix List, for
+/// `matchRegistrableDomain`: `a.b.foo.co.uk` becomes `foo.co.uk`. A name under no listed
+/// suffix falls under the list's implicit `*` rule (`a.b.internal` becomes `b.internal`); a
+/// name that is itself a suffix, or has a single label, is kept whole.
+pub struct RegistrableDomains {
+    list: List,
+    /// Where the registrable domain starts in each name seen, sharded to keep lock contention
+    /// low; DNS logs repeat the same names heavily, so most lines skip the list lookup
+    cache: Vec<Mutex<HashMap<Box<[u8]>, usize>>>,
+}
+
+impl RegistrableDomains {
+    /// Loads the list (`public_suffix_list.dat`) at `path`.
+    pub fn open(path: &str) -> Result<Self> {
+        let text = fs::read_to_string(path).with_context(|| format!("failed to read public suffix list {:?}", path))?;
+        let list: List = text.parse().map_err(|e| anyhow::anyhow!("invalid public suffix list {:?}: {:?}", path, e))?;
+        Ok(RegistrableDomains { list, cache: (0..SHARD_COUNT).map(|_| Mutex::new(HashMap::new())).collect() })
+    }
+
+    /// The registrable domain of `domain`, as a suffix of it.
+    pub fn registrable<'a>(&self, domain: &'a [u8]) -> &'a [u8] {
+        let mut hasher = DefaultHasher::new();
+        domain.hash(&mut hasher);
+        let shard = &self.cache[(hasher.finish() as usize) % SHARD_COUNT];
+        if let Some(&start) = shard.lock().unwrap().get(domain) {
+            return &domain[start..];
+        }
+        // Looked up outside the lock; a concurrent miss on the same name just repeats the work
+        let start = self.start(domain);
+        let mut shard = shard.lock().unwrap();
+        if shard.len() >= SHARD_CAPACITY {
+            shard.clear();
+        }
+        shard.insert(domain.into(), start);
+        &domain[start..]
+    }
+
+    /// Offset of the registrable domain in `domain`; 0 when there is none.
+    fn start(&self, domain: &[u8]) -> usize {
+        // The list holds lower-case rules; lowering ASCII keeps every offset in place
+        let lower = domain.to_ascii_lowercase();
+        self.list.domain(&lower).map_or(0, |registrable| domain.len() - registrable.as_bytes().len())
+    }
+}
+
+impl fmt::Debug for RegistrableDomains {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RegistrableDomains").finish_non_exhaustive()
+    }
+}
@@ -0,0 +1,65 @@
+mod common;
+
+use common::{merged, TempDir};
+use fanzha_log_query::processor::detect_delimiter;
+use fanzha_log_query::{run_query, Config};
+use std::fs;
+
+fn config(extra: serde_json::Value) -> Config {
+    common::config(merged(serde_json::json!({ "autoDetectDelimiter": true }), extra))
+}
+
+#[test]
+fn picks_the_delimiter_giving_a_consistent_field_count() {
+    let layout = config(serde_json::json!({})).aggregated_layout();
+    assert_eq!(detect_delimiter(b"10.0.0.1\ta.com\tA\n10.0.0.2\tb.com\tAAAA\n", true, &layout), Some(b'\t'));
+    assert_eq!(detect_delimiter(b"10.0.0.1 a.com A\n10.0.0.2 b.com A\n", true, &layout), Some(b' '));
+    // The last line counts only when the sample is the whole file
+    assert_eq!(detect_delimiter(b"10.0.0.1|a.com\n10.0.0.2|b.com|x", true, &layout), None);
+    assert_eq!(detect_delimiter(b"10.0.0.1|a.com\n10.0.0.2|b.com|x", false, &layout), Some(b'|'));
+    // Both '|' and ' ' split every line evenly
+    assert_eq!(detect_delimiter(b"10.0.0.1|a.com|2025-06-26 08:00\n10.0.0.2|b.com|2025-06-26 09:00\n", true, &layout), None);
+    // Too few fields to reach the domain
+    assert_eq!(detect_delimiter(b"10.0.0.1;a.com\n", true, &layout), None);
+
+    // expectedFieldCount settles the tie
+    let layout = config(serde_json::json!({ "expectedFieldCount": 3 })).aggregated_layout();
+    assert_eq!(detect_delimiter(b"10.0.0.1|a.com|2025-06-26 08:00\n10.0.0.2|b.com|2025-06-26 09:00\n", true, &layout), Some(b'|'));
+}
+
+#[test]
+fn each_file_is_split_on_its_own_delimiter() {
+    let dir = TempDir::new("auto_delimiter");
+    let (day, config) = common::log_day(&dir, serde_json::json!({
+        "autoDetectDelimiter": true,
+        "outputFields": [1, 0],
+        "workerPoolSize": 1,
+    }));
+    fs::write(day.join("a.log"), "10.0.0.1|www.test.com|A\n10.0.0.2|other.org|A\n").unwrap();
+    fs::write(day.join("b.log"), "10.0.0.3\tmail.test.com\tA\n10.0.0.4\tother.org\tA\n").unwrap();
+    fs::write(day.join("c.log"), "10.0.0.5 ftp.test.com A\n10.0.0.6 other.org A\n").unwrap();
+    // Nothing fits, so fieldDelimiter '|' is used and the domain is not found
+    fs::write(day.join("d.log"), "10.0.0.7;dns.test.com\n").unwrap();
+
+    let report = run_query(&config, None).unwrap();
+    assert_eq!(report.tasks[0].matches, 3);
+    let output = fs::read_to_string(report.tasks[0].output.as_ref().unwrap()).unwrap();
+    // Projected fields are joined with the delimiter of the file they came from
+    assert_eq!(output, "www.test.com|10.0.0.1\nmail.test.com\t10.0.0.3\nftp.test.com 10.0.0.5\n");
+}
+
+#[test]
+fn conflicting_options_are_rejected() {
+    let dir = TempDir::new("auto_delimiter_conflicts");
+    let path = dir.0.join("config.yaml");
+    let base = "logDirectory: /tmp\nqueryTime_day: [\"20250626\"]\nisQueryNativeLog: \"no\"\nautoDetectDelimiter: true\n";
+    for (extra, key) in [
+        ("logFormat: json\nipField: src\ndomainField: qname\n", "autoDetectDelimiter"),
+        ("sortByTimestampField: 2\n", "sortByTimestampField"),
+        ("profiles: [{name: a, queryDomain: [\"*.test.com\"]}]\n", "profiles"),
+    ] {
+        fs::write(&path, format!("{}{}", base, extra)).unwrap();
+        let error = Config::load(path.to_str().unwrap()).unwrap_err();
+        assert!(format!("{:#}", error).contains(key), "{:#}", error);
+    }
+}
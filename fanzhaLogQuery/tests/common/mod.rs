@@ -0,0 +1,63 @@
+//! Fixtures shared by the integration tests (`mod common;`). Each test crate uses only some
+//! of them.
+#![allow(dead_code)]
+
+use fanzha_log_query::{build_processor, Config, FileProcessor};
+use serde_json::Value;
+use std::fs;
+use std::path::PathBuf;
+
+/// Removes the fixture tree however the test ends.
+pub struct TempDir(pub PathBuf);
+
+impl TempDir {
+    /// An empty `fanzha_{name}_{pid}` directory under the system temp dir.
+    pub fn new(name: &str) -> Self {
+        let dir = std::env::temp_dir().join(format!("fanzha_{}_{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        TempDir(dir)
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+/// `base` with the keys of `extra` added, replacing those it already has.
+pub fn merged(mut base: Value, extra: Value) -> Value {
+    base.as_object_mut().unwrap().extend(extra.as_object().unwrap().clone());
+    base
+}
+
+/// A config searching aggregated logs of 2025-06-26 under a directory that does not exist,
+/// with `extra` on top.
+pub fn config(extra: Value) -> Config {
+    let base = serde_json::json!({
+        "logDirectory": "/nonexistent",
+        "queryTime_day": ["20250626"],
+        "isQueryNativeLog": "no",
+    });
+    serde_json::from_value(merged(base, extra)).unwrap()
+}
+
+/// Creates `agg/20250626` under `dir` for the aggregated logs of that day, and returns it along
+/// with a config searching its `.log` files for `*.test.com` into `dir/out`, with `extra` on top.
+pub fn log_day(dir: &TempDir, extra: Value) -> (PathBuf, Config) {
+    let day = dir.0.join("agg/20250626");
+    fs::create_dir_all(&day).unwrap();
+    let base = serde_json::json!({
+        "logDirectory": dir.0.join("agg").to_string_lossy(),
+        "aggregatedLogResultLoc": dir.0.join("out").to_string_lossy(),
+        "queryDomain": ["*.test.com"],
+        "fileExtensions": [".log"],
+    });
+    (day, config(merged(base, extra)))
+}
+
+/// The processor of [`config`]`(extra)`.
+pub fn processor(extra: Value) -> FileProcessor {
+    build_processor(&config(extra)).unwrap()
+}
@@ -0,0 +1,20 @@
+mod common;
+
+use common::TempDir;
+use fanzha_log_query::Config;
+use std::fs;
+
+#[test]
+fn load_errors_name_the_config_file() {
+    let dir = TempDir::new("config_load");
+    let dir = &dir.0;
+
+    let missing = dir.join("missing.yaml");
+    let error = Config::load(missing.to_str().unwrap()).unwrap_err();
+    assert!(error.to_string().contains("missing.yaml"), "{}", error);
+
+    let empty = dir.join("empty.yaml");
+    fs::write(&empty, "# nothing configured yet\n\n").unwrap();
+    let error = Config::load(empty.to_str().unwrap()).unwrap_err();
+    assert!(error.to_string().ends_with("is empty"), "{}", error);
+}
@@ -0,0 +1,40 @@
+use fanzha_log_query::schema::{config_keys, config_template, key_docs};
+use fanzha_log_query::Config;
+use std::collections::HashSet;
+
+#[test]
+fn every_config_key_is_documented_once() {
+    let keys = config_keys();
+    assert!(keys.contains(&"logDirectory") && keys.contains(&"profiles"));
+    let docs = key_docs();
+    let documented: HashSet<&str> = docs.iter().map(|doc| doc.key).collect();
+    assert_eq!(documented.len(), docs.len(), "a key is documented twice");
+    let missing: Vec<_> = keys.iter().filter(|key| !documented.contains(*key)).collect();
+    assert!(missing.is_empty(), "keys without a description: {:?}", missing);
+    let stale: Vec<_> = documented.iter().filter(|key| !keys.contains(key)).collect();
+    assert!(stale.is_empty(), "descriptions of keys Config does not accept: {:?}", stale);
+}
+
+#[test]
+fn template_parses_with_every_example_enabled() {
+    let template = config_template();
+    let config: Config = serde_yaml::from_str(&template).unwrap();
+    assert_eq!(config.is_query_native_log, "yes");
+    assert!(config.query_domain.is_empty());
+
+    // Uncommenting the example lines must give a config of the right types
+    let keys = config_keys();
+    let enabled: String = template
+        .lines()
+        .map(|line| match line.strip_prefix("# ") {
+            Some(rest) if keys.iter().any(|key| rest.starts_with(&format!("{}: ", key))) => rest,
+            _ => line,
+        })
+        .map(|line| format!("{}\n", line))
+        .collect();
+    let config: Config = serde_yaml::from_str(&enabled).unwrap();
+    assert_eq!(config.query_domain, ["www.test.com", "*.test.com"]);
+    assert_eq!(config.core_ids.as_deref().map(<[usize]>::len), Some(32));
+    assert_eq!(config.profiles.len(), 1);
+    assert_eq!(config.worker_flush_interval_ms, Some(500));
+}
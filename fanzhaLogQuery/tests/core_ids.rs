@@ -0,0 +1,28 @@
+use fanzha_log_query::Config;
+
+fn core_ids(value: serde_json::Value) -> Result<Option<Vec<usize>>, serde_json::Error> {
+    let config: Config = serde_json::from_value(serde_json::json!({
+        "logDirectory": "/var/log/dns",
+        "queryTime_day": ["20250626"],
+        "isQueryNativeLog": "no",
+        "coreIds": value,
+    }))?;
+    Ok(config.core_ids)
+}
+
+#[test]
+fn core_ids_accept_a_list_or_a_range_string() {
+    assert_eq!(core_ids(serde_json::json!([0, 2, 4])).unwrap(), Some(vec![0, 2, 4]));
+    assert_eq!(core_ids(serde_json::json!("8-11, 40-41")).unwrap(), Some(vec![8, 9, 10, 11, 40, 41]));
+    assert_eq!(core_ids(serde_json::json!("3,5-5,")).unwrap(), Some(vec![3, 5]));
+    assert_eq!(core_ids(serde_json::json!("")).unwrap(), Some(vec![]));
+    assert_eq!(core_ids(serde_json::Value::Null).unwrap(), None);
+}
+
+#[test]
+fn core_id_ranges_that_cannot_be_expanded_are_rejected() {
+    for spec in ["23-8", "8-x", "0-1000000"] {
+        let error = core_ids(serde_json::json!(spec)).unwrap_err();
+        assert!(error.to_string().contains("coreIds"), "{}: {}", spec, error);
+    }
+}
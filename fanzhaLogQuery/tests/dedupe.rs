@@ -0,0 +1,49 @@
+mod common;
+
+use common::TempDir;
+use fanzha_log_query::run_query;
+use std::fs;
+
+/// Writes three files that each hold one line twice, the same line in every file, and one line
+/// of their own; returns the matches kept and the lines of the result file.
+fn deduped(name: &str, extra: serde_json::Value) -> (usize, Vec<String>) {
+    let dir = TempDir::new(name);
+    let (day, config) = common::log_day(&dir, common::merged(serde_json::json!({ "dedupe": true }), extra));
+    for file in 0..3 {
+        let lines = format!("10.0.0.1|www.test.com|x\n10.0.0.1|www.test.com|x\n10.0.0.{}|mail.test.com|x\n", file + 2);
+        fs::write(day.join(format!("dns_{}.log", file)), lines).unwrap();
+    }
+    let report = run_query(&config, None).unwrap();
+    let output = fs::read_to_string(report.tasks[0].output.as_ref().unwrap()).unwrap();
+    let mut lines: Vec<_> = output.lines().map(str::to_string).collect();
+    lines.sort();
+    (report.tasks[0].matches, lines)
+}
+
+#[test]
+fn global_scope_keeps_one_copy_across_files_and_workers() {
+    let expected = ["10.0.0.1|www.test.com|x", "10.0.0.2|mail.test.com|x", "10.0.0.3|mail.test.com|x", "10.0.0.4|mail.test.com|x"];
+    for backend in ["threads", "rayon"] {
+        let extra = serde_json::json!({ "dedupeScope": "global", "workerPoolSize": 3, "executionBackend": backend });
+        let (matches, lines) = deduped(&format!("dedupe_global_{}", backend), extra);
+        assert_eq!(matches, 4, "{}", backend);
+        assert_eq!(lines, expected, "{}", backend);
+    }
+}
+
+#[test]
+fn worker_scope_drops_the_repeats_each_worker_sees() {
+    // A single worker sees everything, so its set is as good as the global one
+    let (matches, lines) = deduped("dedupe_one_worker", serde_json::json!({ "dedupeScope": "worker", "workerPoolSize": 1 }));
+    assert_eq!((matches, lines.len()), (4, 4));
+
+    // Every worker (or rayon `map_init` state) keeps at most one copy of the shared line, and
+    // the repeat inside each file never gets through
+    for backend in ["threads", "rayon"] {
+        let extra = serde_json::json!({ "dedupeScope": "worker", "workerPoolSize": 3, "executionBackend": backend });
+        let (matches, lines) = deduped(&format!("dedupe_worker_{}", backend), extra);
+        assert!((4..=6).contains(&matches), "{}: {} matches", backend, matches);
+        assert_eq!(lines.len(), matches, "{}", backend);
+        assert_eq!(lines.iter().filter(|line| line.contains("mail.test.com")).count(), 3, "{}", backend);
+    }
+}
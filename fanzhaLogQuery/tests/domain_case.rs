@@ -0,0 +1,28 @@
+mod common;
+
+use common::merged;
+use fanzha_log_query::build_processor;
+use fanzha_log_query::output::write_record;
+
+fn emitted(extra: serde_json::Value, line: &str) -> String {
+    let base = serde_json::json!({
+        "sourceIP": ["10.0.0.0/8"],
+        "filterLogic": "or",
+        "normalizeDomainCase": true,
+    });
+    let config = common::config(merged(base, extra));
+    let processor = build_processor(&config).unwrap();
+    let mut buf = Vec::new();
+    assert!(processor.match_line(line.as_bytes(), false, |matched| write_record(&mut buf, matched, None, &config.record_options())));
+    String::from_utf8(buf).unwrap()
+}
+
+#[test]
+fn only_the_domain_field_is_lowercased() {
+    // OR logic settles on the source IP, yet the domain is still located and rewritten
+    assert_eq!(emitted(serde_json::json!({}), "10.0.0.1|WWW.Test.COM.|ABC"), "10.0.0.1|www.test.com.|ABC\n");
+    assert_eq!(emitted(serde_json::json!({}), "10.0.0.1|www.test.com|ABC"), "10.0.0.1|www.test.com|ABC\n");
+    let jsonl = emitted(serde_json::json!({ "outputFormat": "jsonl" }), "10.0.0.1|Mail.Test.com|X");
+    assert!(jsonl.contains(r#""domain":"mail.test.com""#), "{}", jsonl);
+    assert!(jsonl.contains(r#""raw":"10.0.0.1|mail.test.com|X""#), "{}", jsonl);
+}
@@ -0,0 +1,39 @@
+mod common;
+
+use common::processor;
+use fanzha_log_query::processor::TraceCheck;
+
+#[test]
+fn label_bounds_apply_on_top_of_the_domain_rules() {
+    let processor = processor(serde_json::json!({
+        "queryDomain": ["*.tunnel.example.com"],
+        "sourceIP": ["192.168.0.0/16"],
+        "filterLogic": "or",
+        "minDomainLabels": 6,
+    }));
+    let matches = |line: &str| processor.match_line(line.as_bytes(), false, |_| {});
+
+    assert!(matches("10.0.0.1|a.b.c.tunnel.example.com|x"));
+    assert!(matches("10.0.0.1|a.b.c.tunnel.example.com.|x"));
+    assert!(!matches("10.0.0.1|a.b.tunnel.example.com|x"));
+    assert!(!matches("10.0.0.1|a.b.c.d.other.example.com|x"));
+    // Under OR the source IP still matches on its own
+    assert!(matches("192.168.1.1|a.b.tunnel.example.com|x"));
+
+    let trace = processor.explain_line(b"10.0.0.1|a.b.tunnel.example.com|x", false);
+    let step = trace.steps.iter().find(|step| step.check == TraceCheck::Domain).unwrap();
+    assert!(!step.passed);
+    assert_eq!(step.domain_labels, Some(5));
+}
+
+#[test]
+fn label_bounds_filter_without_domain_rules() {
+    let processor = processor(serde_json::json!({ "minDomainLabels": 2, "maxDomainLabels": 3 }));
+    let matches = |line: &str| processor.match_line(line.as_bytes(), false, |_| {});
+
+    assert!(matches("10.0.0.1|example.com|x"));
+    assert!(matches("10.0.0.1|www.example.com|x"));
+    assert!(!matches("10.0.0.1|localhost|x"));
+    assert!(!matches("10.0.0.1|a.www.example.com|x"));
+    assert!(!matches("10.0.0.1||x"));
+}
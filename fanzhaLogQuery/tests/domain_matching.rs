@@ -0,0 +1,49 @@
+use fanzha_log_query::DomainMatcher;
+
+fn matcher(rules: &[String]) -> DomainMatcher {
+    DomainMatcher::new(rules).unwrap()
+}
+
+/// Enough `*.suffix` rules for the trie, with nested suffixes and a non-wildcard rule between them.
+fn many_wildcards() -> Vec<String> {
+    let mut rules: Vec<String> = (0..30).map(|i| format!("*.zone{}.example", i)).collect();
+    rules.push("*.deep.zone3.example".to_string());
+    rules.push("exact.org".to_string());
+    rules.push("*.com".to_string());
+    rules.push("*.test.com".to_string());
+    rules
+}
+
+#[test]
+fn wildcards_match_on_label_boundaries() {
+    let matcher = matcher(&many_wildcards());
+    assert!(matcher.matches(b"zone7.example"));
+    assert!(matcher.matches(b"www.zone7.example"));
+    assert!(matcher.matches(b"a.b.zone29.example"));
+    assert!(matcher.matches(b"exact.org"));
+    assert!(!matcher.matches(b"xzone7.example"));
+    assert!(!matcher.matches(b"zone30.example"));
+    assert!(!matcher.matches(b"example"));
+    assert!(!matcher.matches(b"www.exact.org"));
+}
+
+#[test]
+fn first_matching_rule_in_config_order_is_reported() {
+    let rules = many_wildcards();
+    let matcher = matcher(&rules);
+    let position = |rule: &str| rules.iter().position(|r| r == rule);
+    // The shorter suffix comes first in the config, so it wins over the deeper one
+    assert_eq!(matcher.matched_rule(b"x.deep.zone3.example"), position("*.zone3.example"));
+    assert_eq!(matcher.matched_rule(b"www.test.com"), position("*.com"));
+    assert_eq!(matcher.matched_rule(b"exact.org"), position("exact.org"));
+    assert_eq!(matcher.matched_rule(b"other.net"), None);
+}
+
+#[test]
+fn negation_still_applies_to_trie_matches() {
+    let mut rules = many_wildcards();
+    rules.push("!*.internal.zone5.example".to_string());
+    let matcher = matcher(&rules);
+    assert!(matcher.matches(b"www.zone5.example"));
+    assert!(!matcher.matches(b"db.internal.zone5.example"));
+}
@@ -0,0 +1,20 @@
+mod common;
+
+use common::TempDir;
+use fanzha_log_query::run_query;
+use std::fs;
+
+#[test]
+fn zero_length_files_are_left_out() {
+    let dir = TempDir::new("empty_inputs");
+    let (day, config) = common::log_day(&dir, serde_json::json!({ "fileExtensions": [".log", ".gz"], "countOnly": true }));
+    fs::write(day.join("a.log"), "10.0.0.1|www.test.com|x\n").unwrap();
+    fs::write(day.join("rotated.log.gz"), "").unwrap();
+    fs::write(day.join("b.log"), "").unwrap();
+
+    let report = run_query(&config, None).unwrap();
+    let task = &report.tasks[0];
+    assert_eq!(task.inputs.len(), 1);
+    assert_eq!(task.failed_files, 0);
+    assert_eq!(task.matches, 1);
+}
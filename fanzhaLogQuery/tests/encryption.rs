@@ -0,0 +1,44 @@
+use fanzha_log_query::encryption::{decrypt_stream, EncryptWriter, OutputKey};
+use std::io::Write;
+
+fn encrypt(key: &OutputKey, plaintext: &[u8]) -> Vec<u8> {
+    let mut writer = EncryptWriter::new(Vec::new(), key).unwrap();
+    // Uneven writes, so chunks fill across write calls
+    for piece in plaintext.chunks(10_000) {
+        writer.write_all(piece).unwrap();
+    }
+    writer.finish().unwrap()
+}
+
+fn lines(count: usize) -> Vec<u8> {
+    (0..count).map(|i| format!("10.0.0.{}|host{}.test.com|x\n", i % 256, i)).collect::<String>().into_bytes()
+}
+
+#[test]
+fn appended_segments_decrypt_in_order() {
+    let key = OutputKey::derive(b"correct horse").unwrap();
+    let first = lines(8000);
+    // Exactly two full chunks: the last chunk is then empty
+    let second = vec![b'x'; 128 * 1024];
+    let mut file = encrypt(&key, &first);
+    file.extend(encrypt(&OutputKey::derive(b"correct horse").unwrap(), &second));
+    file.extend(encrypt(&key, b""));
+
+    let mut plaintext = Vec::new();
+    let written = decrypt_stream(&file[..], &mut plaintext, b"correct horse").unwrap();
+    assert_eq!(written as usize, first.len() + second.len());
+    assert_eq!(plaintext, [first, second].concat());
+}
+
+#[test]
+fn wrong_passphrase_and_truncation_are_errors() {
+    let key = OutputKey::derive(b"correct horse").unwrap();
+    let file = encrypt(&key, &lines(5000));
+    assert!(decrypt_stream(&file[..], &mut Vec::new(), b"battery staple").is_err());
+    assert!(decrypt_stream(&file[..file.len() - 1], &mut Vec::new(), b"correct horse").is_err());
+    // Dropping the last chunk must not pass for a complete file
+    let full_chunk = 4 + 64 * 1024 + 16;
+    let header = 8 + 12 + 16 + 7;
+    assert!(decrypt_stream(&file[..header + full_chunk], &mut Vec::new(), b"correct horse").is_err());
+    assert!(decrypt_stream(&b"plain text"[..], &mut Vec::new(), b"correct horse").is_err());
+}
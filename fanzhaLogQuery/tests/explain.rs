@@ -0,0 +1,72 @@
+mod common;
+
+use common::merged;
+use fanzha_log_query::matcher::IpMiss;
+use fanzha_log_query::processor::TraceCheck;
+use fanzha_log_query::FileProcessor;
+
+fn processor(extra: serde_json::Value) -> FileProcessor {
+    let base = serde_json::json!({
+        "isQueryNativeLog": "yes",
+        "queryDomain": ["*.test.com", "!*.internal.test.com"],
+        "sourceIP": ["10.0.0.0/8"],
+        "excludeSourceIP": ["10.9.0.0/16"],
+    });
+    common::processor(merged(base, extra))
+}
+
+#[test]
+fn trace_names_the_deciding_rules() {
+    let processor = processor(serde_json::json!({}));
+
+    let trace = processor.explain_line(b"10.0.0.1|www.test.com|x\n", false);
+    assert!(trace.matched);
+    assert_eq!(trace.field_count, Some(3));
+    let rules: Vec<_> = trace.steps.iter().map(|step| (step.check, step.passed, step.rule.as_deref())).collect();
+    assert_eq!(rules, [(TraceCheck::SourceIp, true, Some("10.0.0.0/8")), (TraceCheck::Domain, true, Some("*.test.com"))]);
+
+    let trace = processor.explain_line(b"10.0.0.1|db.internal.test.com", false);
+    assert!(!trace.matched);
+    assert_eq!(trace.steps[1].rule.as_deref(), Some("!*.internal.test.com"));
+
+    // AND stops at the excluded source IP; the domain is never checked
+    let trace = processor.explain_line(b"10.9.0.1|www.test.com", false);
+    assert!(!trace.matched);
+    assert_eq!(trace.steps.len(), 1);
+    assert_eq!(trace.steps[0].ip_miss, Some(IpMiss::Excluded(0)));
+    assert_eq!(trace.steps[0].rule.as_deref(), Some("10.9.0.0/16"));
+}
+
+#[test]
+fn trace_matches_the_search_decision_on_short_native_lines() {
+    let processor = processor(serde_json::json!({ "filterLogic": "or" }));
+    // IP at 4, domain at 7: the domain is missing but OR accepts the IP
+    let trace = processor.explain_line(b"t|a|b|c|10.0.0.1|x", true);
+    assert!(trace.matched);
+    assert_eq!(trace.checks, [(TraceCheck::SourceIp, 4), (TraceCheck::Domain, 7)]);
+    assert_eq!(trace.field_count, Some(6));
+
+    let trace = processor.explain_line(b"t|a|b|c|192.168.0.1|x", true);
+    assert!(!trace.matched);
+    assert_eq!(trace.steps[0].ip_miss, Some(IpMiss::NoRule));
+}
+
+#[test]
+fn any_field_mode_finds_the_domain_wherever_it_is() {
+    let processor = processor(serde_json::json!({ "domainAnyField": true }));
+    // The domain sits where the native layout expects the response code
+    let trace = processor.explain_line(b"10.0.0.1|x|www.test.com", false);
+    assert!(trace.matched);
+    let step = trace.steps.iter().find(|step| step.check == TraceCheck::Domain).unwrap();
+    assert_eq!((step.index, step.value.as_str()), (2, "www.test.com"));
+
+    let mut domains = Vec::new();
+    assert!(processor.match_line(b"10.0.0.1|x|y|www.test.com", false, |matched| domains.push(matched.domain.map(<[u8]>::to_vec))));
+    assert_eq!(domains, [Some(b"www.test.com".to_vec())]);
+
+    let trace = processor.explain_line(b"10.0.0.1|other.org|x|db.internal.test.com", false);
+    assert!(!trace.matched);
+    assert!(!trace.steps.last().unwrap().passed);
+    // The AND source IP check still ends the walk before any domain is tried
+    assert!(!processor.match_line(b"10.9.0.1|www.test.com", false, |_| {}));
+}
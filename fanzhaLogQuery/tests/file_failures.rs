@@ -0,0 +1,39 @@
+mod common;
+
+use common::TempDir;
+use fanzha_log_query::failures::FailureKind;
+use fanzha_log_query::run_query;
+use flate2::write::GzEncoder;
+use std::fs;
+use std::io::Write;
+
+#[test]
+fn failed_files_are_recorded_with_their_cause() {
+    let dir = TempDir::new("file_failures");
+    let (day, config) = common::log_day(&dir, serde_json::json!({ "fileExtensions": [".log", ".gz"], "countOnly": true }));
+    fs::write(day.join("a.log"), "10.0.0.1|www.test.com|x\n").unwrap();
+    // Plain text renamed to .gz fails the header check
+    fs::write(day.join("renamed.gz"), "10.0.0.2|www.test.com|x\n").unwrap();
+    // A gzip stream cut off half-way fails while decoding
+    let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::default());
+    for i in 0..2000 {
+        writeln!(encoder, "10.0.{}.{}|host{}.test.com|x", i / 256, i % 256, i).unwrap();
+    }
+    let gzip = encoder.finish().unwrap();
+    fs::write(day.join("truncated.gz"), &gzip[..gzip.len() / 2]).unwrap();
+
+    let report = run_query(&config, None).unwrap();
+    let task = &report.tasks[0];
+    assert_eq!(task.failed_files, 2);
+    let mut failures: Vec<_> = task
+        .file_failures
+        .iter()
+        .map(|failure| (failure.path.file_name().unwrap().to_string_lossy().into_owned(), failure.kind))
+        .collect();
+    failures.sort_by(|a, b| a.0.cmp(&b.0));
+    assert_eq!(
+        failures,
+        [("renamed.gz".to_string(), FailureKind::Decode), ("truncated.gz".to_string(), FailureKind::Decode)]
+    );
+    assert!(task.file_failures.iter().any(|failure| failure.error.contains("gzip magic")));
+}
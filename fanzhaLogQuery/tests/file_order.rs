@@ -0,0 +1,41 @@
+mod common;
+
+use common::TempDir;
+use fanzha_log_query::run_query;
+use std::fs::{self, File};
+use std::time::{Duration, SystemTime};
+
+/// Writes one matching line per file, named after the file, and returns the order the lines
+/// come out in with a single worker.
+fn search_order(dir: &TempDir, order: &str, files: &[(&str, u64)]) -> Vec<String> {
+    let (day, config) = common::log_day(dir, serde_json::json!({ "workerPoolSize": 1, "fileOrder": order }));
+    for &(name, age_secs) in files {
+        let path = day.join(name);
+        fs::write(&path, format!("10.0.0.1|www.test.com|{}\n", name)).unwrap();
+        let modified = SystemTime::now() - Duration::from_secs(age_secs);
+        File::options().write(true).open(&path).unwrap().set_modified(modified).unwrap();
+    }
+    let report = run_query(&config, None).unwrap();
+    let output = fs::read_to_string(report.tasks[0].output.as_ref().unwrap()).unwrap();
+    output.lines().map(|line| line.rsplit('|').next().unwrap().to_string()).collect()
+}
+
+#[test]
+fn mtime_searches_the_most_recently_modified_file_first() {
+    let dir = TempDir::new("file_order_mtime");
+    let order = search_order(&dir, "mtime", &[("a.log", 300), ("b.log", 100), ("c.log", 200)]);
+    assert_eq!(order, ["b.log", "c.log", "a.log"]);
+}
+
+#[test]
+fn timestamp_searches_the_newest_file_name_time_first() {
+    let dir = TempDir::new("file_order_timestamp");
+    let order = search_order(
+        &dir,
+        "timestamp",
+        // Modification times disagree with the names, which win; a name without an hour falls
+        // back to the day directory
+        &[("dns_2025062608.log", 0), ("dns_2025062619.log", 300), ("current.log", 0), ("dns_2025062612.log", 0)],
+    );
+    assert_eq!(order, ["dns_2025062619.log", "dns_2025062612.log", "dns_2025062608.log", "current.log"]);
+}
@@ -0,0 +1,118 @@
+//! Runs the processor over the gzip samples in `tests/fixtures`, checking the exact lines
+//! each kind of rule lets through.
+
+mod common;
+
+use common::merged;
+use fanzha_log_query::processor::{Compression, MatchedLine};
+use std::fs;
+use std::path::PathBuf;
+
+const AGGREGATED: &str = "aggregated_20250626.log.gz";
+const NATIVE: &str = "native_250_132228145205_20250626080000_1.log.gz";
+
+/// The raw lines matched in `fixture`, read as an aggregated or a native log.
+fn matched(fixture: &str, native: bool, filters: serde_json::Value) -> Vec<String> {
+    let base = serde_json::json!({ "isQueryNativeLog": if native { "yes" } else { "no" } });
+    let processor = common::processor(merged(base, filters));
+
+    let path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures").join(fixture);
+    let data = fs::read(&path).unwrap();
+    let compression = Compression::detect(&path, &data);
+    assert_eq!(compression, Compression::Gzip);
+    let mut lines = Vec::new();
+    let collect = |matched: &MatchedLine| lines.push(String::from_utf8_lossy(matched.raw).into_owned());
+    let stats = match native {
+        false => processor.process_aggregated_data(&path, &data, compression, collect),
+        true => processor.process_native_data(&path, &data, compression, collect),
+    }
+    .unwrap();
+    assert_eq!(stats.failed_files, 0);
+    assert_eq!(stats.matches, lines.len());
+    lines
+}
+
+#[test]
+fn aggregated_cidr_rule() {
+    assert_eq!(
+        matched(AGGREGATED, false, serde_json::json!({ "sourceIP": ["10.0.0.0/24"] })),
+        [
+            "10.0.0.1|www.example.com|A|20250626080000",
+            "10.0.0.200|mail.example.com|AAAA|20250626080001",
+        ]
+    );
+    assert_eq!(
+        matched(AGGREGATED, false, serde_json::json!({ "sourceIP": ["2001:db8::/32"] })),
+        ["2001:db8::1|v6.example.com|AAAA|20250626080007"]
+    );
+}
+
+#[test]
+fn aggregated_range_and_exact_rules() {
+    assert_eq!(
+        matched(AGGREGATED, false, serde_json::json!({ "sourceIP": ["172.16.5.1-172.16.5.20"] })),
+        ["172.16.5.10|api.test.com|A|20250626080003"]
+    );
+    assert_eq!(
+        matched(AGGREGATED, false, serde_json::json!({ "sourceIP": ["192.168.1.2", "8.8.8.8"] })),
+        [
+            "192.168.1.2|evil-test.com|A|20250626080006",
+            "8.8.8.8|dns.google|A|20250626080008",
+        ]
+    );
+}
+
+#[test]
+fn aggregated_domain_wildcard_rule() {
+    // The apex matches too; a longer label ending in the suffix does not
+    assert_eq!(
+        matched(AGGREGATED, false, serde_json::json!({ "queryDomain": ["*.test.com"] })),
+        [
+            "172.16.5.10|api.test.com|A|20250626080003",
+            "172.16.5.30|cdn.test.com|CNAME|20250626080004",
+            "192.168.1.1|test.com|A|20250626080005",
+        ]
+    );
+}
+
+#[test]
+fn aggregated_ip_and_domain_rules_combined() {
+    let filters = serde_json::json!({ "sourceIP": ["10.0.0.0/16"], "queryDomain": ["*.example.com"] });
+    assert_eq!(
+        matched(AGGREGATED, false, filters),
+        [
+            "10.0.0.1|www.example.com|A|20250626080000",
+            "10.0.0.200|mail.example.com|AAAA|20250626080001",
+        ]
+    );
+    let filters = serde_json::json!({ "sourceIP": ["8.8.8.8"], "queryDomain": ["*.test.com"], "filterLogic": "or" });
+    assert_eq!(
+        matched(AGGREGATED, false, filters),
+        [
+            "172.16.5.10|api.test.com|A|20250626080003",
+            "172.16.5.30|cdn.test.com|CNAME|20250626080004",
+            "192.168.1.1|test.com|A|20250626080005",
+            "8.8.8.8|dns.google|A|20250626080008",
+        ]
+    );
+}
+
+#[test]
+fn native_rules_read_the_native_fields() {
+    // The trailing dot of the FQDN form is ignored
+    assert_eq!(
+        matched(NATIVE, true, serde_json::json!({ "sourceIP": ["10.0.0.0/22"], "queryDomain": ["*.test.com"] })),
+        ["250|132228145205|20250626080001|1|10.0.0.7|53|0|www.test.com.|A|203.0.113.9"]
+    );
+    assert_eq!(
+        matched(NATIVE, true, serde_json::json!({ "resolvedIP": ["198.51.100.0/24", "8.8.8.8"] })),
+        [
+            "250|132228145205|20250626080002|1|172.16.5.10|53|0|api.test.com|A|198.51.100.20",
+            "250|132228145205|20250626080003|1|192.168.1.2|53|0|dns.google|A|8.8.8.8",
+        ]
+    );
+    assert_eq!(
+        matched(NATIVE, true, serde_json::json!({ "sourceIP": ["192.168.1.1-192.168.1.9"] })),
+        ["250|132228145205|20250626080003|1|192.168.1.2|53|0|dns.google|A|8.8.8.8"]
+    );
+}
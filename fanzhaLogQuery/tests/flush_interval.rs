@@ -0,0 +1,38 @@
+mod common;
+
+use common::TempDir;
+use fanzha_log_query::progress::ProgressEvent;
+use fanzha_log_query::run_query;
+use std::fs;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+#[test]
+fn matches_reach_the_result_file_before_the_run_ends() {
+    let dir = TempDir::new("flush_interval");
+    let (day, config) = common::log_day(&dir, serde_json::json!({ "workerFlushIntervalMs": 20 }));
+    for name in ["a.log", "b.log"] {
+        fs::write(day.join(name), "10.0.0.1|www.test.com|x\n").unwrap();
+    }
+
+    // Far below the 128 KB worker and 1 MB writer buffers, so only the interval pushes the
+    // first file's match out while the run is still going
+    let result_file = dir.0.join("out/wildcard.test.com_all_ips_20250626_results/matched_aggregated_logs.txt");
+    let seen_mid_run = Arc::new(Mutex::new(None));
+    let sink = Arc::clone(&seen_mid_run);
+    let watched = result_file.clone();
+    let callback = Arc::new(move |event| {
+        if let ProgressEvent::FileCompleted(counts) = event {
+            if counts.processed_files == 1 {
+                std::thread::sleep(Duration::from_millis(500));
+                *sink.lock().unwrap() = Some(fs::read_to_string(&watched).unwrap_or_default());
+            }
+        }
+    });
+    let report = run_query(&config, Some(callback)).unwrap();
+    assert_eq!(report.total_matches(), 2);
+    assert_eq!(report.tasks[0].output.as_deref(), Some(result_file.as_path()));
+
+    let seen = seen_mid_run.lock().unwrap().clone().unwrap();
+    assert!(seen.starts_with("10.0.0.1|www.test.com|x\n"), "result file mid-run: {:?}", seen);
+}
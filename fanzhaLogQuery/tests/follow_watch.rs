@@ -0,0 +1,33 @@
+mod common;
+
+use common::TempDir;
+use fanzha_log_query::search::{FollowWatch, TaskKind};
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+
+#[test]
+fn a_new_file_is_ready_once_two_polls_agree() {
+    let dir = TempDir::new("follow_watch");
+    let old = dir.0.join("old.log");
+    let new = dir.0.join("new.log");
+    let empty = dir.0.join("empty.log");
+    fs::write(&old, "10.0.0.1|www.test.com|x\n").unwrap();
+    fs::write(&new, "10.0.0.2|www.test.com|x\n").unwrap();
+    fs::write(&empty, "").unwrap();
+    let listed = || vec![old.clone(), new.clone(), empty.clone()];
+
+    let mut watch = FollowWatch::default();
+    watch.mark_seen(TaskKind::Aggregated, std::slice::from_ref(&old));
+    assert!(watch.ready(TaskKind::Aggregated, listed()).is_empty());
+    // Still being written: the size moved since the last poll
+    OpenOptions::new().append(true).open(&new).unwrap().write_all(b"10.0.0.3|www.test.com|x\n").unwrap();
+    assert!(watch.ready(TaskKind::Aggregated, listed()).is_empty());
+    assert_eq!(watch.ready(TaskKind::Aggregated, listed()), std::slice::from_ref(&new));
+    // Searched once only; the empty file waits for its first write
+    assert!(watch.ready(TaskKind::Aggregated, listed()).is_empty());
+    assert!(watch.ready(TaskKind::Aggregated, listed()).is_empty());
+
+    // Each task keeps its own view of the same path
+    assert!(watch.ready(TaskKind::Native, vec![new.clone()]).is_empty());
+    assert_eq!(watch.ready(TaskKind::Native, vec![new.clone()]), [new]);
+}
@@ -0,0 +1,126 @@
+mod common;
+
+use fanzha_log_query::processor::{self, gzip_member_ranges, MatchedLine};
+use flate2::read::MultiGzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use serde_json::json;
+use std::io::{Read, Write};
+use std::path::Path;
+
+fn gzip(data: &[u8]) -> Vec<u8> {
+    gzip_at(data, Compression::default())
+}
+
+fn gzip_at(data: &[u8], level: Compression) -> Vec<u8> {
+    let mut encoder = GzEncoder::new(Vec::new(), level);
+    encoder.write_all(data).unwrap();
+    encoder.finish().unwrap()
+}
+
+fn gunzip(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::new();
+    MultiGzDecoder::new(data).read_to_end(&mut out).unwrap();
+    out
+}
+
+fn lines(from: usize, to: usize) -> Vec<u8> {
+    (from..to).map(|i| format!("10.0.0.{}|host{}.test.com|x\n", i % 256, i)).collect::<String>().into_bytes()
+}
+
+#[test]
+fn members_split_into_independently_decodable_pieces() {
+    let members: Vec<Vec<u8>> = (0..6).map(|i| gzip(&lines(i * 1000, (i + 1) * 1000))).collect();
+    let file = members.concat();
+
+    let ranges = gzip_member_ranges(&file, 3);
+    assert_eq!(ranges.len(), 3);
+    assert_eq!(ranges.first().unwrap().start, 0);
+    assert_eq!(ranges.last().unwrap().end, file.len());
+    let decoded: Vec<u8> = ranges.into_iter().flat_map(|range| gunzip(&file[range])).collect();
+    assert_eq!(decoded, lines(0, 6000));
+}
+
+#[test]
+fn single_member_and_plain_data_stay_whole() {
+    let file = gzip(&lines(0, 5000));
+    assert_eq!(gzip_member_ranges(&file, 4), vec![0..file.len()]);
+
+    let plain = lines(0, 10);
+    assert_eq!(gzip_member_ranges(&plain, 4), vec![0..plain.len()]);
+}
+
+#[test]
+fn pieces_cut_mid_line_match_what_a_sequential_scan_does() {
+    // Fixed-size members like bgzip's, so nearly every boundary falls inside a line
+    let text = lines(0, 6000);
+    let file: Vec<u8> = text.chunks(10_000).flat_map(gzip).collect();
+    let processor = common::processor(json!({ "sourceIP": ["10.0.0.7"] }));
+    let path = Path::new("big.log.gz");
+
+    let mut sequential = Vec::new();
+    let whole = processor
+        .process_aggregated_data(path, &file, processor::Compression::Gzip, |line: &MatchedLine| sequential.push(line.raw.to_vec()))
+        .unwrap();
+
+    let ranges = gzip_member_ranges(&file, 4);
+    assert_eq!(ranges.len(), 4);
+    let mut pieced = Vec::new();
+    let mut lines_read = 0;
+    for piece in 0..ranges.len() {
+        let stats = processor
+            .process_aggregated_piece(path, &file, &ranges, piece, |line: &MatchedLine| pieced.push(line.raw.to_vec()))
+            .unwrap();
+        assert_eq!(stats.failed_files, 0);
+        lines_read += stats.lines;
+    }
+    assert_eq!(lines_read, whole.lines);
+    assert_eq!(whole.lines, 6000);
+    pieced.sort();
+    sequential.sort();
+    assert_eq!(pieced, sequential);
+}
+
+#[test]
+fn header_bytes_inside_member_data_are_not_a_boundary() {
+    // Stored (level 0) members carry their input verbatim, here a copy of their own header
+    let header = gzip_at(b"", Compression::none())[..10].to_vec();
+    let payload: Vec<u8> = [lines(0, 500), header, lines(500, 1000)].concat();
+    let members = [gzip_at(&payload, Compression::none()), gzip_at(&payload, Compression::none())];
+    let file = members.concat();
+
+    let ranges = gzip_member_ranges(&file, 8);
+    assert_eq!(ranges, vec![0..members[0].len(), members[0].len()..file.len()]);
+}
+
+#[test]
+fn a_piece_ending_inside_a_member_searches_on_through_the_next_piece() {
+    let text = lines(0, 6000);
+    let members: Vec<Vec<u8>> = text.chunks(10_000).map(gzip).collect();
+    let file = members.concat();
+    let processor = common::processor(json!({ "sourceIP": ["10.0.0.7"] }));
+    let path = Path::new("big.log.gz");
+
+    let mut sequential = Vec::new();
+    processor
+        .process_aggregated_data(path, &file, processor::Compression::Gzip, |line: &MatchedLine| sequential.push(line.raw.to_vec()))
+        .unwrap();
+
+    // As if the probe had let through a point in the middle of the second member
+    let second = members[0].len();
+    let third = second + members[1].len();
+    let ranges = vec![0..second + 100, second + 100..third, third..file.len()];
+    let mut pieced = Vec::new();
+    let mut failed = Vec::new();
+    for piece in 0..ranges.len() {
+        let stats = processor
+            .process_aggregated_piece(path, &file, &ranges, piece, |line: &MatchedLine| pieced.push(line.raw.to_vec()))
+            .unwrap();
+        failed.push(stats.failed_files);
+    }
+    // The piece starting at the false boundary cannot decode; the one before it covered its lines
+    assert_eq!(failed, vec![0, 1, 0]);
+    pieced.sort();
+    sequential.sort();
+    assert_eq!(pieced, sequential);
+}
@@ -0,0 +1,99 @@
+use fanzha_log_query::matcher::IpFieldEncoding;
+use fanzha_log_query::IPMatcher;
+
+fn matcher(rules: &[&str]) -> IPMatcher {
+    let rules: Vec<String> = rules.iter().map(|r| r.to_string()).collect();
+    IPMatcher::new(&rules, &[]).unwrap()
+}
+
+#[test]
+fn padded_ipv4_matches_exact_and_prefix_rules() {
+    let exact = matcher(&["10.0.0.1"]);
+    let prefix = matcher(&["10.0.0.0/24"]);
+    for field in [&b" 10.0.0.1 "[..], b"\t10.0.0.1\t", b"  10.0.0.1", b"10.0.0.1\t "] {
+        assert!(exact.matches(field), "{:?}", field);
+        assert!(prefix.matches(field), "{:?}", field);
+    }
+    assert!(!exact.matches(b" 10.0.0.10 "));
+}
+
+#[test]
+fn padded_ipv4_matches_parsed_rules() {
+    // Neither rule has a textual prefix, so both go through the byte parser
+    let cidr = matcher(&["10.0.0.0/28"]);
+    let range = matcher(&["10.0.0.1-10.0.0.5"]);
+    for field in [&b" 10.0.0.3 "[..], b"\t10.0.0.3\t"] {
+        assert!(cidr.matches(field), "{:?}", field);
+        assert!(range.matches(field), "{:?}", field);
+    }
+    assert!(!cidr.matches(b" 10.0.0.30 "));
+    assert!(!range.matches(b"\t10.0.0.6\t"));
+}
+
+#[test]
+fn padded_ipv6_matches() {
+    let exact = matcher(&["2001:db8::1"]);
+    let prefix = matcher(&["2001:db8::/32"]);
+    // Zero network group: no textual prefix, matched by parsing
+    let cidr = matcher(&["2001:0:db8::/48"]);
+    assert!(exact.matches(b" 2001:db8::1 "));
+    assert!(exact.matches(b"\t2001:db8::1\t"));
+    assert!(prefix.matches(b" 2001:db8:1::2 "));
+    assert!(prefix.matches(b"\t2001:db8:1::2\t"));
+    assert!(cidr.matches(b" 2001:0:db8::5 "));
+    assert!(cidr.matches(b"\t2001:0:db8::5\t"));
+    assert!(!cidr.matches(b"\t2001:0:db9::5\t"));
+}
+
+#[test]
+fn ipv6_prefix_rules_match_any_spelling_of_the_address() {
+    let prefix = matcher(&["2001:db8::/32"]);
+    for field in [
+        &b"2001:0db8::1"[..],
+        b"2001:0DB8:0000:0000:0000:0000:0000:0001",
+        b"2001:db8:0:0:0:0:0:1",
+        b"\t2001:0db8::1\t",
+    ] {
+        assert!(prefix.matches(field), "{:?}", field);
+    }
+    assert!(!prefix.matches(b"2001:0db9::1"));
+    assert!(!prefix.matches(b"2001:0db9:0000:0000:0000:0000:0000:0001"));
+    assert!(!prefix.matches(b"10.0.0.1"));
+}
+
+#[test]
+fn padded_field_with_port() {
+    let ip = matcher(&["10.0.0.1"]).with_ports(true, &[53]);
+    assert!(ip.matches(b" 10.0.0.1:53 "));
+    assert!(!ip.matches(b" 10.0.0.1:54 "));
+    let ip6 = matcher(&["2001:db8::1"]).with_ports(true, &[53]);
+    assert!(ip6.matches(b"\t[2001:db8::1]:53\t"));
+}
+
+#[test]
+fn uint32_fields_match_cidr_range_and_exact_rules() {
+    let rules: Vec<String> =
+        ["10.0.0.0/24", "192.168.1.1-192.168.1.9", "172.16.0.5"].iter().map(|r| r.to_string()).collect();
+    let matcher = IPMatcher::with_encoding(&rules, &[], IpFieldEncoding::Uint32).unwrap();
+    assert!(matcher.matches(b"167772161")); // 10.0.0.1
+    assert!(matcher.matches(b" 3232235777 ")); // 192.168.1.1
+    assert!(matcher.matches(b"2886729733")); // 172.16.0.5
+    assert!(!matcher.matches(b"167772417")); // 10.0.1.1
+    assert!(!matcher.matches(b"10.0.0.1"));
+    assert!(!matcher.matches(b"4294967296"));
+}
+
+#[test]
+fn every_malformed_rule_is_reported_with_its_position() {
+    let rules: Vec<String> = ["10.0.0.1", "10.0.0.0/33", "", "10.0.0.9-10.0.0.1", "10.0.0.0/8"]
+        .iter()
+        .map(|r| r.to_string())
+        .collect();
+    let excludes = vec!["bad/cidr".to_string()];
+    let error = IPMatcher::new(&rules, &excludes).unwrap_err().to_string();
+    assert!(error.starts_with("3 invalid IP rule(s)"), "{}", error);
+    assert!(error.contains("rule #2 \"10.0.0.0/33\""), "{}", error);
+    assert!(error.contains("rule #4 \"10.0.0.9-10.0.0.1\""), "{}", error);
+    assert!(error.contains("exclude rule #1 \"bad/cidr\""), "{}", error);
+    assert!(!error.contains("#1 \"10.0.0.1\""), "{}", error);
+}
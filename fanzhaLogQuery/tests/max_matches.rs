@@ -0,0 +1,42 @@
+mod common;
+
+use common::TempDir;
+use fanzha_log_query::run_query;
+use std::fs;
+
+/// Searches four files of three matching lines (each written twice) and one other line,
+/// returning the matches counted, whether the task was cut short and the lines written.
+fn limited(name: &str, extra: serde_json::Value) -> (usize, bool, usize) {
+    let dir = TempDir::new(name);
+    let (day, config) = common::log_day(&dir, common::merged(serde_json::json!({ "workerPoolSize": 2 }), extra));
+    for file in 0..4 {
+        let lines: String = (0..3)
+            .map(|i| format!("10.0.{}.{}|www.test.com|x\n", file, i).repeat(2))
+            .chain([format!("10.0.{}.9|other.org|x\n", file)])
+            .collect();
+        fs::write(day.join(format!("dns_{}.log", file)), lines).unwrap();
+    }
+    let report = run_query(&config, None).unwrap();
+    let task = &report.tasks[0];
+    let written = fs::read_to_string(task.output.as_ref().unwrap()).unwrap().lines().count();
+    (task.matches, task.truncated, written)
+}
+
+#[test]
+fn matches_past_the_limit_are_neither_written_nor_counted() {
+    assert_eq!(limited("max_matches_hit", serde_json::json!({ "maxMatches": 5 })), (5, true, 5));
+}
+
+#[test]
+fn a_limit_never_reached_keeps_everything() {
+    assert_eq!(limited("max_matches_unreached", serde_json::json!({ "maxMatches": 100 })), (24, false, 24));
+}
+
+#[test]
+fn duplicates_do_not_use_up_the_limit() {
+    // 12 distinct lines, each read twice
+    let extra = serde_json::json!({ "maxMatches": 13, "dedupe": true });
+    assert_eq!(limited("max_matches_dedupe", extra), (12, false, 12));
+    let extra = serde_json::json!({ "maxMatches": 7, "dedupe": true });
+    assert_eq!(limited("max_matches_dedupe_hit", extra), (7, true, 7));
+}
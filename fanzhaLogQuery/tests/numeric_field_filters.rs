@@ -0,0 +1,33 @@
+use fanzha_log_query::processor::TraceCheck;
+use fanzha_log_query::{build_processor, Config};
+
+#[test]
+fn numeric_range_keeps_slow_queries_only() {
+    let config: Config = serde_json::from_value(serde_json::json!({
+        "logDirectory": "/nonexistent",
+        "queryTime_day": ["20250626"],
+        "isQueryNativeLog": "no",
+        "queryDomain": ["*.test.com"],
+        "fieldFilters": { "2": ["A"] },
+        "numericFieldFilters": [{ "index": 2, "max": 1 }, { "index": 3, "min": 500 }],
+        "aggregatedNumericFieldFilters": [{ "index": 3, "min": 500, "max": 1000.5 }],
+    }))
+    .unwrap();
+    // The aggregated list replaces the shared one, so field 2 only has its value filter
+    let processor = build_processor(&config).unwrap();
+    let matches = |line: &str| processor.match_line(line.as_bytes(), false, |_| {});
+
+    assert!(matches("10.0.0.1|www.test.com|A|500"));
+    assert!(matches("10.0.0.1|www.test.com|A|1000.5"));
+    assert!(!matches("10.0.0.1|www.test.com|A|499"));
+    assert!(!matches("10.0.0.1|www.test.com|A|1001"));
+    assert!(!matches("10.0.0.1|www.test.com|A|slow"));
+    assert!(!matches("10.0.0.1|www.test.com|A|NaN"));
+    assert!(!matches("10.0.0.1|www.test.com|A"));
+    assert!(!matches("10.0.0.1|www.test.com|AAAA|700"));
+
+    let trace = processor.explain_line(b"10.0.0.1|www.test.com|A|20", false);
+    assert_eq!(trace.checks.last(), Some(&(TraceCheck::NumericFieldFilter, 3)));
+    assert!(!trace.steps.last().unwrap().passed);
+}
+
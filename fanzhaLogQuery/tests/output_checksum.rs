@@ -0,0 +1,29 @@
+mod common;
+
+use common::TempDir;
+use fanzha_log_query::run_query;
+use sha2::{Digest, Sha256};
+use std::fs;
+
+#[test]
+fn every_result_part_gets_the_sha256_of_its_bytes() {
+    let dir = TempDir::new("output_checksum");
+    let (day, config) = common::log_day(&dir, serde_json::json!({ "outputCompression": "gzip", "outputMaxBytes": 300 }));
+    // Each file reaches the writer as one chunk, and a part is closed after the chunk that
+    // takes it past outputMaxBytes
+    for file in 0..3 {
+        let lines: String = (0..20).map(|i| format!("10.0.{}.{}|www.test.com|{}\n", file, i, i)).collect();
+        fs::write(day.join(format!("dns_{}.log", file)), lines).unwrap();
+    }
+
+    let report = run_query(&config, None).unwrap();
+    let checksums = &report.tasks[0].output_sha256;
+    assert!(checksums.len() > 1, "expected several parts: {:?}", checksums);
+
+    for checksum in checksums {
+        let bytes = fs::read(&checksum.path).unwrap();
+        assert_eq!(checksum.sha256, format!("{:x}", Sha256::digest(&bytes)), "{:?}", checksum.path);
+    }
+    let manifest = fs::read_to_string(&report.manifests[0]).unwrap();
+    assert!(manifest.contains(&checksums[0].sha256));
+}
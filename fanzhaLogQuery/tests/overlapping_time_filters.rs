@@ -0,0 +1,59 @@
+mod common;
+
+use common::{merged, TempDir};
+use fanzha_log_query::{run_query, Config};
+use std::fs;
+use std::path::Path;
+
+fn write_log(path: &Path) {
+    fs::create_dir_all(path.parent().unwrap()).unwrap();
+    fs::write(path, "10.0.0.1|www.test.com|x|1.2.3.4|a|b|c|www.test.com|A|1.2.3.4\n10.0.0.2|other.org|y\n").unwrap();
+}
+
+fn config(dir: &Path, extra: serde_json::Value) -> Config {
+    let base = serde_json::json!({
+        "logDirectory": dir.join("agg").to_string_lossy(),
+        "queryDomain": ["*.test.com"],
+        "queryTime_day": ["20250626", "20250626"],
+        "queryTime_hour": ["2025062619"],
+        "fileExtensions": [".log"],
+        "countOnly": true,
+    });
+    common::config(merged(base, extra))
+}
+
+#[test]
+fn aggregated_file_matching_day_and_hour_is_searched_once() {
+    let dir = TempDir::new("overlap_aggregated");
+    // The day directory and the hour in the file name both match
+    write_log(&dir.0.join("agg/20250626/dns_2025062619.log"));
+    write_log(&dir.0.join("agg/20250626/dns_2025062620.log"));
+
+    let report = run_query(&config(&dir.0, serde_json::json!({})), None).unwrap();
+    let task = &report.tasks[0];
+    assert_eq!(task.inputs.len(), 2);
+    assert_eq!(task.lines_scanned, 4);
+    assert_eq!(task.matches, 2);
+}
+
+#[test]
+fn native_file_matching_day_and_hour_is_searched_once() {
+    let dir = TempDir::new("overlap_native");
+    fs::create_dir_all(dir.0.join("agg")).unwrap();
+    write_log(&dir.0.join("native/250_132228145205_20250626191500_1.log"));
+    write_log(&dir.0.join("native/250_132228145205_20250626201500_1.log"));
+
+    let extra = serde_json::json!({
+        "isQueryNativeLog": "yes",
+        "nativeLogLoc": dir.0.join("native").to_string_lossy(),
+        "nativeIpIndex": 0,
+        "nativeDomainIndex": 1,
+    });
+    let report = run_query(&config(&dir.0, extra), None).unwrap();
+    let native = report.tasks.iter().find(|task| task.task == "native").unwrap();
+    let mut inputs: Vec<_> = native.inputs.iter().map(|input| input.path.clone()).collect();
+    inputs.dedup();
+    assert_eq!(inputs.len(), native.inputs.len());
+    assert_eq!(native.inputs.len(), 2);
+    assert_eq!(native.matches, 2);
+}
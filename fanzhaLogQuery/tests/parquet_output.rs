@@ -0,0 +1,67 @@
+mod common;
+
+use arrow_array::Array;
+use arrow_array::cast::AsArray;
+use arrow_array::types::{Float64Type, Int64Type, TimestampMillisecondType};
+use common::TempDir;
+use fanzha_log_query::run_query;
+use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+use sha2::{Digest, Sha256};
+use std::fs::{self, File};
+
+#[test]
+fn matches_are_written_as_typed_parquet_columns() {
+    let dir = TempDir::new("parquet_output");
+    let (day, config) = common::log_day(
+        &dir,
+        serde_json::json!({
+            "normalizeDomainCase": true,
+            "outputFormat": "parquet",
+            "parquetColumns": [
+                { "name": "time", "index": 2, "type": "timestamp" },
+                { "name": "latency", "index": 3, "type": "int64" },
+                { "name": "score", "index": 4, "type": "float64" },
+            ],
+        }),
+    );
+    fs::write(
+        day.join("dns_2025062619.log"),
+        "10.0.0.1|WWW.test.com|1750924800|12|0.5\n\
+         10.0.0.2|other.org|1750924800|3|1\n\
+         10.0.0.3|mail.test.com|2025-06-26T08:00:00.123Z|slow\n",
+    )
+    .unwrap();
+
+    let report = run_query(&config, None).unwrap();
+    let output = report.tasks[0].output.clone().unwrap();
+    assert!(output.to_string_lossy().ends_with("matched_aggregated_logs.parquet"));
+    let checksum = &report.tasks[0].output_sha256[0];
+    assert_eq!(checksum.sha256, format!("{:x}", Sha256::digest(fs::read(&output).unwrap())));
+
+    let reader = ParquetRecordBatchReaderBuilder::try_new(File::open(&output).unwrap()).unwrap().build().unwrap();
+    let batches: Vec<_> = reader.map(Result::unwrap).collect();
+    assert_eq!(batches.len(), 1);
+    let batch = &batches[0];
+    assert_eq!(batch.num_rows(), 2);
+    let names: Vec<_> = batch.schema().fields().iter().map(|field| field.name().clone()).collect();
+    assert_eq!(names, ["ip", "domain", "raw", "time", "latency", "score"]);
+
+    let mut rows: Vec<usize> = (0..2).collect();
+    let ip = batch.column(0).as_string::<i32>();
+    rows.sort_by_key(|&row| ip.value(row));
+    let (first, second) = (rows[0], rows[1]);
+    assert_eq!(ip.value(first), "10.0.0.1");
+    assert_eq!(batch.column(1).as_string::<i32>().value(first), "www.test.com");
+    assert_eq!(batch.column(2).as_string::<i32>().value(first), "10.0.0.1|www.test.com|1750924800|12|0.5");
+
+    let time = batch.column(3).as_primitive::<TimestampMillisecondType>();
+    assert_eq!(time.value(first), 1_750_924_800_000);
+    assert_eq!(time.value(second), 1_750_924_800_123);
+    let latency = batch.column(4).as_primitive::<Int64Type>();
+    assert_eq!(latency.value(first), 12);
+    // Values that do not parse, and missing fields, are null
+    assert!(latency.is_null(second));
+    let score = batch.column(5).as_primitive::<Float64Type>();
+    assert_eq!(score.value(first), 0.5);
+    assert!(score.is_null(second));
+}
@@ -0,0 +1,55 @@
+use fanzha_log_query::{build_processor, Config};
+
+fn config() -> Config {
+    serde_json::from_value(serde_json::json!({
+        "logDirectory": "/nonexistent",
+        "queryTime_day": ["20250626"],
+        "queryTime_hour": ["2025062612"],
+        "isQueryNativeLog": "no",
+        "queryDomain": ["*.test.com"],
+        "sourceIP": ["10.0.0.0/8"],
+        "profiles": [
+            { "name": "inherits" },
+            { "name": "phishing", "queryDomain": "*.evil.com", "filterLogic": "or", "queryTime_day": ["20250627"] },
+        ],
+    }))
+    .unwrap()
+}
+
+#[test]
+fn profile_keys_replace_the_top_level_ones() {
+    let profiles = config().profile_configs();
+    assert_eq!(profiles.len(), 2);
+
+    let inherits = &profiles[0];
+    assert_eq!(inherits.profile_name.as_deref(), Some("inherits"));
+    assert_eq!(inherits.query_domain, ["*.test.com"]);
+    assert_eq!(inherits.query_time_hour, Some(vec!["2025062612".to_string()]));
+    assert!(inherits.profiles.is_empty());
+
+    let phishing = &profiles[1];
+    assert_eq!(phishing.query_domain, ["*.evil.com"]);
+    assert_eq!(phishing.source_ip, ["10.0.0.0/8"]);
+    // The day and hour lists are one time window: setting either replaces both
+    assert_eq!(phishing.query_time_day, Some(vec!["20250627".to_string()]));
+    assert_eq!(phishing.query_time_hour, None);
+}
+
+#[test]
+fn each_profile_filters_the_lines_read_once() {
+    let config = config();
+    let driver = build_processor(&config.without_filters()).unwrap();
+    let profiles: Vec<_> = config.profile_configs().iter().map(|profile| build_processor(profile).unwrap()).collect();
+
+    let lines = ["10.0.0.1|www.test.com|x", "192.168.0.1|login.evil.com|x", "10.0.0.2|other.org|x", "192.168.0.2|other.org|x"];
+    let mut routed = vec![Vec::new(); profiles.len()];
+    for line in lines {
+        assert!(driver.match_line(line.as_bytes(), false, |_| {}));
+        for (i, profile) in profiles.iter().enumerate() {
+            profile.match_line(line.as_bytes(), false, |matched| routed[i].push(String::from_utf8_lossy(matched.raw).into_owned()));
+        }
+    }
+    assert_eq!(routed[0], ["10.0.0.1|www.test.com|x"]);
+    // OR: the inherited sourceIP or the profile's own domain
+    assert_eq!(routed[1], ["10.0.0.1|www.test.com|x", "192.168.0.1|login.evil.com|x", "10.0.0.2|other.org|x"]);
+}
@@ -0,0 +1,28 @@
+mod common;
+
+use common::TempDir;
+use fanzha_log_query::progress::ProgressEvent;
+use fanzha_log_query::run_query;
+use std::fs;
+use std::sync::{Arc, Mutex};
+
+#[test]
+fn callback_hears_every_file_then_the_end() {
+    let dir = TempDir::new("progress");
+    let (day, config) = common::log_day(&dir, serde_json::json!({ "countOnly": true }));
+    for name in ["a.log", "b.log", "c.log"] {
+        fs::write(day.join(name), "10.0.0.1|www.test.com|x\n").unwrap();
+    }
+
+    let events = Arc::new(Mutex::new(Vec::new()));
+    let sink = Arc::clone(&events);
+    let report = run_query(&config, Some(Arc::new(move |event| sink.lock().unwrap().push(event)))).unwrap();
+    assert_eq!(report.total_matches(), 3);
+
+    let events = events.lock().unwrap();
+    let completed: Vec<_> = events.iter().filter(|event| matches!(event, ProgressEvent::FileCompleted(_))).collect();
+    assert_eq!(completed.len(), 3);
+    let ProgressEvent::Finished(last) = events.last().unwrap() else { panic!("last event: {:?}", events.last()) };
+    assert_eq!((last.task, last.processed_files, last.total_files), ("aggregated", 3, 3));
+    assert_eq!((last.processed_bytes, last.total_bytes), (72, 72));
+}
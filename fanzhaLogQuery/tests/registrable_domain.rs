@@ -0,0 +1,46 @@
+mod common;
+
+use common::TempDir;
+use fanzha_log_query::{build_processor, FileProcessor};
+use std::fs;
+use std::path::Path;
+
+fn processor(list: &Path, query_domain: &[&str]) -> FileProcessor {
+    common::processor(serde_json::json!({
+        "queryDomain": query_domain,
+        "matchRegistrableDomain": true,
+        "publicSuffixList": list.to_string_lossy(),
+    }))
+}
+
+#[test]
+fn rules_see_the_registrable_domain() {
+    let dir = TempDir::new("psl");
+    let list = dir.0.join("public_suffix_list.dat");
+    fs::write(&list, "// ===BEGIN ICANN DOMAINS===\ncom\nuk\nco.uk\n// ===END ICANN DOMAINS===\n").unwrap();
+
+    let processor = processor(&list, &["foo.co.uk", "!bar.com", "*.internal"]);
+    let matches = |domain: &str| processor.match_line(format!("10.0.0.1|{}|x", domain).as_bytes(), false, |_| {});
+
+    assert!(matches("a.b.foo.co.uk"));
+    assert!(matches("foo.co.uk."));
+    assert!(!matches("foo.uk"));
+    assert!(!matches("co.uk"));
+    // Negation rules also see the registrable domain
+    assert!(!matches("www.bar.com"));
+    // Unlisted suffixes fall under the implicit `*` rule, which leaves `b.internal` here
+    assert!(matches("a.b.internal"));
+    // A bare suffix or single label has no registrable domain and is matched whole
+    assert!(!matches("localhost"));
+}
+
+#[test]
+fn a_missing_list_fails_the_build() {
+    let config = common::config(serde_json::json!({
+        "queryDomain": ["foo.co.uk"],
+        "matchRegistrableDomain": true,
+        "publicSuffixList": "/nonexistent/public_suffix_list.dat",
+    }));
+    let Err(error) = build_processor(&config) else { panic!("built without a suffix list") };
+    assert!(format!("{:#}", error).contains("public suffix list"), "{:#}", error);
+}
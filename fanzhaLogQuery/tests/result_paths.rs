@@ -0,0 +1,21 @@
+mod common;
+
+use common::TempDir;
+use fanzha_log_query::run_query;
+use std::fs;
+
+#[test]
+fn a_regex_rule_names_a_single_result_directory() {
+    let dir = TempDir::new("result_paths");
+    let (day, config) = common::log_day(&dir, serde_json::json!({ "queryDomain": ["re:^(www|api)/*\\.test\\.com$"] }));
+    fs::write(day.join("a.log"), "10.0.0.1|www.test.com|x\n").unwrap();
+
+    let report = run_query(&config, None).unwrap();
+    assert_eq!(report.total_matches(), 1);
+    let result_file = report.tasks[0].output.clone().unwrap();
+    assert_eq!(result_file.parent().unwrap().parent(), Some(dir.0.join("out").as_path()));
+    assert_eq!(
+        result_file.parent().unwrap().file_name().unwrap(),
+        "re___www_api__wildcard_.test_.com__all_ips_20250626_results"
+    );
+}
@@ -0,0 +1,40 @@
+mod common;
+
+use common::merged;
+use fanzha_log_query::FileProcessor;
+
+fn processor(extra: serde_json::Value) -> FileProcessor {
+    common::processor(merged(serde_json::json!({ "queryDomain": ["*.test.com"] }), extra))
+}
+
+fn kept(processor: &FileProcessor, lines: &[String]) -> Vec<String> {
+    lines
+        .iter()
+        .filter(|line| processor.match_line(line.as_bytes(), false, |_| {}))
+        .cloned()
+        .collect()
+}
+
+#[test]
+fn sample_keeps_about_the_configured_share() {
+    let lines: Vec<_> = (0..20000).map(|i| format!("10.0.{}.{}|host{}.test.com|x", i / 256 % 256, i % 256, i)).collect();
+    let sampled = kept(&processor(serde_json::json!({ "samplePercent": 10 })), &lines);
+    assert!((1500..2500).contains(&sampled.len()), "kept {}", sampled.len());
+    // Same lines on every run
+    assert_eq!(sampled, kept(&processor(serde_json::json!({ "samplePercent": 10 })), &lines));
+    // Lines that fail the filters are never sampled in
+    assert!(kept(&processor(serde_json::json!({ "samplePercent": 100 })), &["10.0.0.1|other.org|x".to_string()]).is_empty());
+}
+
+#[test]
+fn sample_by_source_ip_keeps_hosts_together() {
+    let processor = processor(serde_json::json!({ "samplePercent": 50, "sampleKey": "sourceIP" }));
+    let hosts: Vec<_> = (0..200).map(|i| format!("192.168.{}.{}", i / 256, i % 256)).collect();
+    let lines: Vec<_> = hosts.iter().flat_map(|ip| (0..5).map(move |n| format!("{}|q{}.test.com|x", ip, n))).collect();
+    let sampled = kept(&processor, &lines);
+    assert!(!sampled.is_empty() && sampled.len() < lines.len());
+    for ip in &hosts {
+        let count = sampled.iter().filter(|line| line.starts_with(&format!("{}|", ip))).count();
+        assert!(count == 0 || count == 5, "{} kept {} of 5", ip, count);
+    }
+}
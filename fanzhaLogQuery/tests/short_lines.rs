@@ -0,0 +1,106 @@
+mod common;
+
+use common::merged;
+use fanzha_log_query::processor::{Compression, MatchedLine};
+use fanzha_log_query::FileProcessor;
+use std::path::Path;
+
+fn processor(extra: serde_json::Value) -> FileProcessor {
+    common::processor(merged(serde_json::json!({ "isQueryNativeLog": "yes" }), extra))
+}
+
+/// Lines reported as matches by the aggregated or native task.
+fn matches(processor: &FileProcessor, native: bool, data: &str) -> Vec<String> {
+    let mut lines = Vec::new();
+    let callback = |matched: &MatchedLine| {
+        lines.push(String::from_utf8_lossy(matched.raw).into_owned());
+    };
+    let path = Path::new("test.log");
+    if native {
+        processor.process_native_data(path, data.as_bytes(), Compression::None, callback).unwrap();
+    } else {
+        processor.process_aggregated_data(path, data.as_bytes(), Compression::None, callback).unwrap();
+    }
+    lines
+}
+
+#[test]
+fn aggregated_line_without_the_domain_field_never_matches_it() {
+    let data = "10.0.0.1|a|b|c|d\n10.0.0.1|a|b|c|d|e|f|www.test.com\n";
+    for logic in ["and", "or"] {
+        let processor = processor(serde_json::json!({
+            "queryDomain": ["*.test.com"],
+            "aggregatedDomainIndex": 7,
+            "filterLogic": logic,
+        }));
+        assert_eq!(matches(&processor, false, data), ["10.0.0.1|a|b|c|d|e|f|www.test.com"], "{}", logic);
+    }
+
+    // Under OR the source IP still matches on its own
+    let processor = processor(serde_json::json!({
+        "queryDomain": ["*.test.com"],
+        "sourceIP": ["10.0.0.1"],
+        "aggregatedDomainIndex": 7,
+        "filterLogic": "or",
+    }));
+    assert_eq!(matches(&processor, false, data).len(), 2);
+}
+
+#[test]
+fn native_short_lines_fail_filters_past_their_end() {
+    // IP at 4, domain at 7, resolved IP at 9
+    let short = "t|a|b|c|10.0.0.1|x";
+    let no_answer = "t|a|b|c|10.0.0.1|x|y|www.test.com";
+    let full = "t|a|b|c|10.0.0.1|x|y|www.test.com|A|1.2.3.4";
+    let data = format!("{}\n{}\n{}\n", short, no_answer, full);
+
+    let and = processor(serde_json::json!({
+        "queryDomain": ["*.test.com"],
+        "sourceIP": ["10.0.0.1"],
+    }));
+    assert_eq!(matches(&and, true, &data), [no_answer, full]);
+
+    let or = processor(serde_json::json!({
+        "queryDomain": ["*.test.com"],
+        "sourceIP": ["10.0.0.1"],
+        "filterLogic": "or",
+    }));
+    assert_eq!(matches(&or, true, &data), [short, no_answer, full]);
+
+    let resolved = processor(serde_json::json!({ "resolvedIP": ["1.2.3.0/24"] }));
+    assert_eq!(matches(&resolved, true, &data), [full]);
+}
+
+#[test]
+fn field_filter_past_the_end_of_the_line_fails_under_either_logic() {
+    let data = "t|a|b|c|10.0.0.1|x|y|www.test.com\nt|a|b|c|10.0.0.1|x|y|www.test.com|A\n";
+    for logic in ["and", "or"] {
+        let processor = processor(serde_json::json!({
+            "sourceIP": ["10.0.0.1"],
+            "nativeFieldFilters": { "8": ["A"] },
+            "filterLogic": logic,
+        }));
+        assert_eq!(matches(&processor, true, data), ["t|a|b|c|10.0.0.1|x|y|www.test.com|A"], "{}", logic);
+    }
+}
+
+#[test]
+fn lines_with_the_wrong_field_count_are_passed_on_as_malformed() {
+    let processor = processor(serde_json::json!({
+        "queryDomain": ["*.test.com"],
+        "aggregatedExpectedFieldCount": 3,
+        "filterLogic": "or",
+    }));
+    let data = "10.0.0.1|www.test.com|x\n10.0.0.2|www.test.com\n10.0.0.3|other.org|x\n10.0.0.4|www.test.com|x|y\n";
+    let mut malformed = Vec::new();
+    let mut matched = Vec::new();
+    let stats = processor
+        .process_aggregated_data(Path::new("test.log"), data.as_bytes(), Compression::None, |line: &MatchedLine| {
+            let raw = String::from_utf8_lossy(line.raw).into_owned();
+            if line.malformed { malformed.push(raw) } else { matched.push(raw) }
+        })
+        .unwrap();
+    assert_eq!(matched, ["10.0.0.1|www.test.com|x"]);
+    assert_eq!(malformed, ["10.0.0.2|www.test.com", "10.0.0.4|www.test.com|x|y"]);
+    assert_eq!((stats.lines, stats.matches, stats.malformed_lines), (4, 1, 2));
+}
@@ -0,0 +1,38 @@
+mod common;
+
+use common::TempDir;
+use fanzha_log_query::run_query;
+use std::fs;
+
+/// Sorts one file of lines with the given third fields, returning the fields in result order.
+fn sorted_times(dir: &TempDir, times: &[&str], extra: serde_json::Value) -> Vec<String> {
+    let (day, config) = common::log_day(dir, common::merged(serde_json::json!({ "sortByTimestampField": 2 }), extra));
+    let lines: String = times
+        .iter()
+        .map(|time| match *time {
+            "" => "10.0.0.1|www.test.com\n".to_string(),
+            time => format!("10.0.0.1|www.test.com|{}\n", time),
+        })
+        .collect();
+    fs::write(day.join("a.log"), lines).unwrap();
+    let report = run_query(&config, None).unwrap();
+    let output = fs::read_to_string(report.tasks[0].output.as_ref().unwrap()).unwrap();
+    output.lines().map(|line| line.split('|').nth(2).unwrap_or_default().to_string()).collect()
+}
+
+#[test]
+fn mixed_timestamps_sort_the_same_in_memory_and_through_spilled_runs() {
+    let times = ["10", "1a", "9", "1700000000.5", "", "2025-06-26T08:00:00Z", "999999999", "0009", "1700000000", "1700000000.50"];
+    // Missing fields first, then numbers by value, then the rest byte-wise; equal values keep
+    // their order
+    let expected = ["", "9", "0009", "10", "999999999", "1700000000", "1700000000.5", "1700000000.50", "1a", "2025-06-26T08:00:00Z"];
+
+    let dir = TempDir::new("sort_in_memory");
+    assert_eq!(sorted_times(&dir, &times, serde_json::json!({})), expected);
+    // A buffer smaller than one line spills every line to a run of its own
+    let dir = TempDir::new("sort_spilled");
+    assert_eq!(sorted_times(&dir, &times, serde_json::json!({ "sortBufferBytes": 1 })), expected);
+    let results = dir.0.join("out/wildcard.test.com_all_ips_20250626_results");
+    let leftovers: Vec<_> = fs::read_dir(results).unwrap().map(|entry| entry.unwrap().file_name()).collect();
+    assert!(leftovers.iter().all(|name| !name.to_string_lossy().contains("tmp")), "{:?}", leftovers);
+}
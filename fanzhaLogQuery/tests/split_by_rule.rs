@@ -0,0 +1,43 @@
+mod common;
+
+use common::TempDir;
+use fanzha_log_query::run_query;
+use std::fs;
+
+#[test]
+fn each_rule_gets_its_own_file_and_ip_only_matches_go_to_other() {
+    let dir = TempDir::new("split_by_rule");
+    let (day, config) = common::log_day(
+        &dir,
+        serde_json::json!({
+            // The first two sanitize to the same name, and nothing matches the last
+            "queryDomain": ["wildcard.x.org", "*.x.org", "*.test.com", "*.none.net"],
+            "sourceIP": ["10.0.0.9"],
+            "filterLogic": "or",
+            "splitByDomainRule": true,
+        }),
+    );
+    fs::write(
+        day.join("a.log"),
+        "10.0.0.1|wildcard.x.org|x\n10.0.0.2|www.x.org|x\n10.0.0.3|mail.test.com|x\n10.0.0.9|other.org|x\n10.0.0.4|other.org|x\n",
+    )
+    .unwrap();
+
+    let report = run_query(&config, None).unwrap();
+    let task = &report.tasks[0];
+    assert_eq!(task.matches, 4);
+    let files: Vec<_> = task
+        .split_outputs
+        .iter()
+        .map(|path| (path.file_name().unwrap().to_string_lossy().into_owned(), fs::read_to_string(path).unwrap()))
+        .collect();
+    assert_eq!(
+        files,
+        [
+            ("matched_aggregated_logs_wildcard_x_org.txt".to_string(), "10.0.0.1|wildcard.x.org|x\n".to_string()),
+            ("matched_aggregated_logs_wildcard_x_org_1.txt".to_string(), "10.0.0.2|www.x.org|x\n".to_string()),
+            ("matched_aggregated_logs_wildcard_test_com.txt".to_string(), "10.0.0.3|mail.test.com|x\n".to_string()),
+            ("matched_aggregated_logs_other.txt".to_string(), "10.0.0.9|other.org|x\n".to_string()),
+        ]
+    );
+}
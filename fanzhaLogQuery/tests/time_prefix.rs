@@ -0,0 +1,31 @@
+use fanzha_log_query::search::find_time_prefix;
+
+#[test]
+fn date_directory_and_file_name_match() {
+    assert_eq!(find_time_prefix("/data/dns/20250626/access.log.gz", "20250626"), Some(10));
+    assert!(find_time_prefix("/data/dns/20250626/access.log.gz", "202506").is_some());
+    assert!(find_time_prefix("/data/dns/2025062619/access.log.gz", "20250626").is_some());
+    assert!(find_time_prefix("/data/dns/access_2025062619.log.gz", "2025062619").is_some());
+    assert!(find_time_prefix("/data/dns/access-20250626.log", "20250626").is_some());
+    assert!(find_time_prefix("20250626.log", "20250626").is_some());
+}
+
+#[test]
+fn prefix_inside_other_names_does_not_match() {
+    assert_eq!(find_time_prefix("/data/id202512xyz/access.log.gz", "202512"), None);
+    assert_eq!(find_time_prefix("/data/dns/host120250626/access.log.gz", "20250626"), None);
+    assert_eq!(find_time_prefix("/data/dns/20250626abc/access.log.gz", "20250626"), None);
+    assert_eq!(find_time_prefix("/data/dns/20250627/access.log.gz", "20250626"), None);
+}
+
+#[test]
+fn first_anchored_occurrence_is_returned() {
+    // The unanchored hit in the directory name is skipped for the one in the file name
+    let path = "/data/id20250626/dns_20250626.log";
+    assert_eq!(find_time_prefix(path, "20250626"), Some(path.rfind("20250626").unwrap()));
+}
+
+#[test]
+fn empty_prefix_never_matches() {
+    assert_eq!(find_time_prefix("/data/dns/20250626/access.log.gz", ""), None);
+}